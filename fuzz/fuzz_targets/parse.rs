@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|s: &str| {
+    if let Ok(lexed) = novelang::lex::lex(s.to_owned()) {
+        let _ = novelang::parse::parse(&lexed, &novelang::lint::Levels::new(), &[]);
+    }
+});
@@ -0,0 +1,75 @@
+//! Cache of parsed programs, so that launching a large story again skips lexing and parsing
+//!
+//! Entries are kept in `$XDG_CACHE_HOME/novelang` (`~/.cache/novelang` by default),
+//! named by the hash of the source, the build of the interpreter and the rest given to the parser.
+//! Only programs parsed without any diagnostic are cached, so that warnings are shown every time.
+
+use novelang::parse::AST;
+use std::path::{Path, PathBuf};
+
+/// Directory of the cache, if the home directory is known
+pub fn dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|dir| dir.join("novelang"))
+}
+
+/// Path of the entry for the program parsed from `source` with `inputs` (lint levels, definitions, ...)
+pub fn entry(source: &str, inputs: &str) -> Option<PathBuf> {
+    use sha2::{Digest, Sha256};
+    use std::fmt::Write;
+
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    // programs of development builds of the same version may differ
+    let built = std::env::current_exe()
+        .and_then(|exe| exe.metadata())
+        .and_then(|meta| meta.modified())
+        .ok()?;
+    hasher.update(format!("\0{built:?}\0{inputs}\0"));
+    hasher.update(source);
+    let name = hasher.finalize().iter().fold(String::new(), |mut name, b| {
+        let _ = write!(name, "{b:02x}");
+        name
+    });
+    Some(dir()?.join(name))
+}
+
+/// Read the program cached in `path`
+pub fn load(path: &Path) -> Option<AST> {
+    let bytes = std::fs::read(path).ok()?;
+    rmp_serde::from_slice(&bytes)
+        .map_err(|e| log::warn!("Ignoring the broken cache \"{}\" : {}", path.display(), e))
+        .ok()
+}
+
+/// Write the program to `path` (a failure only makes the next launch slower)
+pub fn store(path: &Path, prog: &AST) {
+    let write = || -> Result<(), String> {
+        let bytes = rmp_serde::to_vec(prog).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        // renamed when complete, so that another process never reads a half-written entry
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&tmp, bytes).map_err(|e| e.to_string())?;
+        std::fs::rename(&tmp, path).map_err(|e| e.to_string())
+    };
+    if let Err(e) = write() {
+        log::warn!("Failed to write the cache \"{}\" : {}", path.display(), e);
+    }
+}
+
+/// Remove all the cached programs
+pub fn clean() -> std::io::Result<()> {
+    match dir() {
+        Some(dir) if dir.exists() => {
+            std::fs::remove_dir_all(&dir)?;
+            log::info!("Removed {}", dir.display());
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
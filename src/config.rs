@@ -0,0 +1,92 @@
+//! Per-project configuration (`novelint.toml`)
+
+use crate::lint::{Level, Levels, Lint};
+use crossterm::style::Color;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAME: &str = "novelint.toml";
+
+/// Defaults read from the configuration file, overridden by the command line
+#[allow(clippy::struct_excessive_bools)] // independent options
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub seed: Option<u64>,
+    pub no_wait: bool,
+    pub deny_warnings: bool,
+    /// Lint name to level ("allow", "warn" or "deny")
+    pub lints: BTreeMap<String, String>,
+    /// Width of the box the names of speakers are padded to
+    pub name_width: Option<usize>,
+    /// Milliseconds after the prompt during which Enter is ignored (0 keeps what was typed ahead)
+    pub wait_cooldown: Option<u64>,
+    /// Calls which may be nested before the script stops with a stack overflow
+    pub max_call_depth: Option<usize>,
+    /// Speaker to the color of the name (e.g. "cyan" or "magenta")
+    pub speakers: BTreeMap<String, String>,
+    /// Reorder right-to-left text for terminals which show it as stored
+    pub reorder_bidi: bool,
+    /// Write Japanese prose vertically from the start (the initial value of `_vertical`)
+    pub vertical: bool,
+}
+
+impl Config {
+    /// Find `novelint.toml` in the directory of `script` or its ancestors and load it.
+    /// For stdin (`-`), the search starts from the current directory.
+    pub fn discover(script: &str) -> Option<(PathBuf, Self)> {
+        let start = if script == "-" {
+            std::env::current_dir().ok()?
+        } else {
+            std::fs::canonicalize(script).ok()?.parent()?.to_path_buf()
+        };
+        let path = start
+            .ancestors()
+            .map(|dir| dir.join(FILE_NAME))
+            .find(|p| p.is_file())?;
+        let config = Self::load(&path).unwrap_or_else(|e| crate::die!("Error: {}", e));
+        Some((path, config))
+    }
+
+    fn load(path: &Path) -> Result<Self, String> {
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read \"{}\" : {}", path.display(), e))?;
+        let config: Self = toml::from_str(&s)
+            .map_err(|e| format!("failed to parse \"{}\" : {}", path.display(), e))?;
+        // check here to report errors even for the commands not using lints
+        config
+            .apply_lints(&mut Levels::new())
+            .map_err(|e| format!("{} in \"{}\"", e, path.display()))?;
+        config
+            .speaker_colors()
+            .map_err(|e| format!("{} in \"{}\"", e, path.display()))?;
+        Ok(config)
+    }
+
+    /// Return the colors of speakers
+    pub fn speaker_colors(&self) -> Result<HashMap<String, Color>, String> {
+        self.speakers
+            .iter()
+            .map(|(name, color)| {
+                Color::try_from(color.as_str())
+                    .map(|c| (name.clone(), c))
+                    .map_err(|()| format!("unknown color \"{color}\" for speaker \"{name}\""))
+            })
+            .collect()
+    }
+
+    /// Set the levels of lints specified in the configuration
+    pub fn apply_lints(&self, levels: &mut Levels) -> Result<(), String> {
+        for (name, level) in &self.lints {
+            let lint = Lint::from_str(name).ok_or_else(|| format!("unknown lint \"{name}\""))?;
+            let level = Level::from_str(level)
+                .ok_or_else(|| format!("unknown level \"{level}\" for lint \"{name}\""))?;
+            levels.set(lint, level);
+        }
+        if self.deny_warnings {
+            levels.deny_warnings();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,323 @@
+//! Rendering of the diagnostics (errors, warnings and notes) found in a script
+
+use crate::lex::LocInfo;
+use crate::theme::{Role, Theme};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Note => "note",
+        }
+    }
+
+    const fn role(self) -> Role {
+        match self {
+            Self::Error => Role::Error,
+            Self::Warning => Role::Warning,
+            Self::Note => Role::Note,
+        }
+    }
+}
+
+/// Stable code for each kind of diagnostics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    E001,
+    E002,
+    E003,
+    E004,
+    E005,
+    E006,
+    E007,
+    E008,
+    E009,
+    E010,
+    E011,
+    E012,
+    E013,
+    W001,
+    W002,
+}
+
+impl Code {
+    pub const DISCRIMINANTS: &'static [Self] = &[
+        Self::E001,
+        Self::E002,
+        Self::E003,
+        Self::E004,
+        Self::E005,
+        Self::E006,
+        Self::E007,
+        Self::E008,
+        Self::E009,
+        Self::E010,
+        Self::E011,
+        Self::E012,
+        Self::E013,
+        Self::W001,
+        Self::W002,
+    ];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::E001 => "E001",
+            Self::E002 => "E002",
+            Self::E003 => "E003",
+            Self::E004 => "E004",
+            Self::E005 => "E005",
+            Self::E006 => "E006",
+            Self::E007 => "E007",
+            Self::E008 => "E008",
+            Self::E009 => "E009",
+            Self::E010 => "E010",
+            Self::E011 => "E011",
+            Self::E012 => "E012",
+            Self::E013 => "E013",
+            Self::W001 => "W001",
+            Self::W002 => "W002",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::DISCRIMINANTS
+            .iter()
+            .find(|c| c.as_str().eq_ignore_ascii_case(s))
+            .copied()
+    }
+
+    /// Whether the code is for malformed scripts, rather than for wrong meaning
+    pub const fn is_syntactic(self) -> bool {
+        matches!(self, Self::E003 | Self::E004 | Self::E010)
+    }
+
+    /// Return the detailed description of the code shown by `--explain`
+    #[allow(clippy::too_many_lines)]
+    pub const fn explain(self) -> &'static str {
+        match self {
+            Self::E001 => {
+                "A string literal is not terminated.\n\
+                 \n\
+                 Strings must be closed with `\"` on the same line they start.\n\
+                 \n\
+                 \x20   print \"hello;    # error\n\
+                 \x20   print \"hello\";  # ok"
+            }
+            Self::E002 => {
+                "A character which cannot start any token was found.\n\
+                 \n\
+                 Only commands, keywords, identifiers, numbers, strings, operators,\n\
+                 `,`, `;`, `:`, `(`, `)`, `[` and `]` can appear outside of comments."
+            }
+            Self::E003 => {
+                "A token different from the one the statement requires was found.\n\
+                 \n\
+                 Each statement has a fixed form (see README), e.g. `let` must be\n\
+                 followed by an identifier and `be`, and every statement ends with `;`.\n\
+                 A line must also begin with a command."
+            }
+            Self::E004 => {
+                "An expression is malformed.\n\
+                 \n\
+                 This happens when an expression is empty, ends abruptly, has an\n\
+                 unpaired parenthesis or has tokens left after a complete expression.\n\
+                 \n\
+                 \x20   print 1 +;     # error\n\
+                 \x20   print (1 + 2;  # error\n\
+                 \x20   print 1 + 2;   # ok"
+            }
+            Self::E005 => {
+                "A variable or subroutine is used but it is not declared.\n\
+                 \n\
+                 Variables must be declared with `let` and subroutines with `sub`\n\
+                 before they are used, in the current or an enclosing block."
+            }
+            Self::E006 => {
+                "A variable or subroutine is declared twice with the same name.\n\
+                 \n\
+                 Names must be unique within a block. Use `modify` to change the\n\
+                 value of an existing variable instead. `global` declares at the top\n\
+                 level, so its name must not be used by any variable visible there."
+            }
+            Self::E007 => {
+                "An identifier starting with `_` is declared.\n\
+                 \n\
+                 Such identifiers are reserved for internal variables (e.g. `_wait`)."
+            }
+            Self::E008 => {
+                "A value has a type different from the one required.\n\
+                 \n\
+                 Values are never converted implicitly: conditions of `if`,\n\
+                 `else if` and `while` must be Bool, `roll` takes Num, `modify`\n\
+                 must keep the type of the variable, the elements of an array must\n\
+                 be of one type other than Sub, a subroutine must return values of\n\
+                 one type (that of the variable of `call ... into`) and operators\n\
+                 are defined only for some combination of types.\n\
+                 \n\
+                 \x20   let n be 1 asmut;\n\
+                 \x20   modify n to \"one\";  # error\n\
+                 \x20   if n;               # error\n\
+                 \x20   if n != 0;          # ok"
+            }
+            Self::E009 => {
+                "An immutable variable is modified.\n\
+                 \n\
+                 Variables are immutable unless declared with `asmut`.\n\
+                 \n\
+                 \x20   let n be 1 asmut;\n\
+                 \x20   modify n to 2;  # ok"
+            }
+            Self::E010 => {
+                "Blocks are not paired correctly.\n\
+                 \n\
+                 `sub`, `while`, `if`, `switch` and `try` open a block which must be closed\n\
+                 by `end`. `else if` and `else` may appear only inside an `if` block, `case`\n\
+                 and `default` only inside a `switch` block, right after `switch`,\n\
+                 `recover` exactly once inside a `try` block, and `return` only inside\n\
+                 a `sub` block."
+            }
+            Self::E011 => {
+                "An integer literal is too large.\n\
+                 \n\
+                 Integers are 64-bit signed, so literals must not exceed\n\
+                 9223372036854775807."
+            }
+            Self::E012 => {
+                "A macro cannot be expanded.\n\
+                 \n\
+                 It is given a wrong number of arguments, it is expanded in itself\n\
+                 (directly or through other macros), or an argument has a name\n\
+                 declared by `let` or `sub` in the macro, which would refer to that\n\
+                 one instead of the one at the call site.\n\
+                 \n\
+                 \x20   macro twice(n);\n\
+                 \x20       let tmp be n * 2;\n\
+                 \x20       print tmp;\n\
+                 \x20   end;\n\
+                 \x20   twice(tmp);  # error"
+            }
+            Self::E013 => {
+                "The script is written for another version of novelint.\n\
+                 \n\
+                 `pragma novelint 0.3;` requires a version compatible with 0.3\n\
+                 (0.3.x for 0.x, and 1.x from 1.2 for `1.2`). Run the script with\n\
+                 such a version, or update the script and the pragma."
+            }
+            Self::W001 => {
+                "A subroutine is declared but never called (lint `unused-sub`).\n\
+                 \n\
+                 Remove the subroutine or call it somewhere."
+            }
+            Self::W002 => {
+                "A variable hides another one declared in an outer block (lint `shadowing`).\n\
+                 \n\
+                 The outer variable cannot be used until the end of the block.\n\
+                 \n\
+                 \x20   let n be 1;\n\
+                 \x20   while true;\n\
+                 \x20       let n be 2;  # warning\n\
+                 \x20   end;"
+            }
+        }
+    }
+}
+
+/// Trait for the things reported to the user
+pub trait Diagnostic {
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+    fn code(&self) -> Code;
+    fn message(&self) -> String;
+    fn loc_info(&self) -> &LocInfo;
+    fn notes(&self) -> &[String] {
+        &[]
+    }
+}
+
+/// Renders diagnostics to stderr
+pub struct Emitter {
+    filename: String,
+    theme: Theme,
+}
+
+impl Emitter {
+    pub fn new(filename: &str) -> Self {
+        use crossterm::tty::IsTty;
+        Self {
+            filename: filename.to_owned(),
+            theme: Theme::for_stream(std::io::stderr().is_tty()),
+        }
+    }
+
+    fn paint(&self, s: &str, role: Role) -> String {
+        self.theme.paint(s, role)
+    }
+
+    pub fn emit<D: Diagnostic>(&self, d: &D) {
+        let severity = d.severity();
+        let l = d.loc_info();
+        let gutter = |s: &str| self.paint(s, Role::Gutter);
+
+        eprintln!(
+            "{}: {}",
+            self.paint(
+                &format!("{}[{}]", severity.as_str(), d.code().as_str()),
+                severity.role()
+            ),
+            self.paint(&d.message(), Role::Emphasis)
+        );
+        eprintln!(
+            "  {} {}:{}:{}",
+            gutter("-->"),
+            self.filename,
+            l.loc.row,
+            l.loc.col
+        );
+        eprintln!("     {}", gutter("|"));
+        eprintln!("{} {}", gutter(&format!("{:<4} |", l.loc.row)), l.line);
+        let (pad, carets) = l.underline();
+        eprintln!(
+            "     {} {}{}",
+            gutter("|"),
+            pad,
+            self.paint(&"^".repeat(carets), severity.role())
+        );
+        eprintln!("     {}", gutter("|"));
+        for note in d.notes() {
+            eprintln!(
+                "     {} {}: {}",
+                gutter("="),
+                self.paint(Severity::Note.as_str(), Role::Emphasis),
+                note
+            );
+        }
+        eprintln!();
+    }
+
+    /// Print the summary of the diagnostics emitted
+    pub fn summary(&self, errors: usize, warnings: usize) {
+        if warnings > 0 {
+            eprintln!(
+                "{}: {} warning(s) emitted",
+                self.paint(Severity::Warning.as_str(), Severity::Warning.role()),
+                warnings
+            );
+        }
+        if errors > 0 {
+            eprintln!(
+                "{}: aborting due to {} previous error(s)",
+                self.paint(Severity::Error.as_str(), Severity::Error.role()),
+                errors
+            );
+        }
+    }
+}
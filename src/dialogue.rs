@@ -0,0 +1,110 @@
+//! Import of dialogue drafted in spreadsheets
+
+use crate::i18n::csv_fields;
+use std::fmt::Write;
+
+/// Lines of a scene, in order
+struct Scene {
+    name: String,
+    /// Speaker (possibly empty for narration) and line
+    lines: Vec<(String, String)>,
+}
+
+/// Make a string literal, as the language has no escapes for `"`
+fn literal(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'"))
+}
+
+/// Make the name of the subroutine for a scene
+fn sub_name(scene: &str, taken: &[Scene]) -> String {
+    let base: String = scene
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    // the prefix keeps the name away from keywords, numbers and `_` (used internally)
+    let base = format!("scene_{base}");
+    let mut name = base.clone();
+    let mut n = 2;
+    while taken.iter().any(|s| s.name == name) {
+        name = format!("{base}_{n}");
+        n += 1;
+    }
+    name
+}
+
+/// Convert CSV with the columns `speaker`, `line` and `scene` (in any order, named in the header)
+/// into a script with a subroutine per scene, called in order of appearance.
+/// A row with an empty scene continues the previous one.
+pub fn import_csv(s: &str) -> Result<String, String> {
+    let mut rows = s.lines().filter(|l| !l.trim().is_empty());
+    let header = csv_fields(rows.next().ok_or("the file is empty")?);
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(name))
+    };
+    let line_col = column("line").ok_or("the header has no \"line\" column")?;
+    let speaker_col = column("speaker");
+    let scene_col = column("scene");
+
+    let mut scenes: Vec<Scene> = Vec::new();
+    let mut titles: Vec<String> = Vec::new();
+    let mut current = None;
+    for row in rows {
+        let fields = csv_fields(row);
+        let get = |col: Option<usize>| {
+            col.and_then(|c| fields.get(c))
+                .map_or("", |f| f.trim())
+                .to_owned()
+        };
+        let title = get(scene_col);
+        if !title.is_empty() {
+            current = Some(titles.iter().position(|t| *t == title).unwrap_or_else(|| {
+                scenes.push(Scene {
+                    name: sub_name(&title, &scenes),
+                    lines: Vec::new(),
+                });
+                titles.push(title);
+                scenes.len() - 1
+            }));
+        }
+        let idx = *current.get_or_insert_with(|| {
+            scenes.push(Scene {
+                name: sub_name("start", &scenes),
+                lines: Vec::new(),
+            });
+            titles.push(String::new());
+            scenes.len() - 1
+        });
+        scenes[idx]
+            .lines
+            .push((get(speaker_col), get(Some(line_col))));
+    }
+
+    let mut out = String::new();
+    for (scene, title) in scenes.iter().zip(&titles) {
+        if !title.is_empty() {
+            writeln!(out, "# {title}").unwrap();
+        }
+        writeln!(out, "sub {};", scene.name).unwrap();
+        for (speaker, line) in &scene.lines {
+            if speaker.is_empty() {
+                writeln!(out, "    print {};", literal(line)).unwrap();
+            } else {
+                writeln!(out, "    say {}: {};", literal(speaker), literal(line)).unwrap();
+            }
+        }
+        out.push_str("end;\n\n");
+    }
+    for scene in &scenes {
+        writeln!(out, "call {};", scene.name).unwrap();
+    }
+    Ok(out)
+}
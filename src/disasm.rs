@@ -0,0 +1,222 @@
+//! Human-readable listing of parsed programs
+
+use crate::exprs::Expr;
+use crate::lex::Lexed;
+use crate::parse::{Align, Statement, AST};
+use std::fmt::Write;
+
+/// Return the source text of `expr`
+fn expr_src(expr: &Expr, lexed: &Lexed) -> String {
+    let (from, to) = (&expr.span.from, &expr.span.to);
+    let line: Vec<_> = lexed.line(from.row).chars().collect();
+    let end = if to.row == from.row {
+        to.col - 1
+    } else {
+        line.len()
+    };
+    // arguments of macros put in place of the parameters may not fit
+    let end = end.min(line.len());
+    line[(from.col - 1).min(end)..end].iter().collect()
+}
+
+/// Format a statement at `idx`, with its jump target resolved to an absolute index
+#[allow(clippy::option_if_let_else, clippy::too_many_lines)]
+fn format_stmt(idx: usize, stmt: &Statement, lexed: &Lexed) -> String {
+    let src = |e: &Expr| expr_src(e, lexed);
+    match stmt {
+        Statement::Print {
+            args,
+            sep,
+            nobreak,
+            align,
+            speaker,
+        } => format!(
+            "PRINT   {}{}{}{}{}",
+            speaker
+                .as_ref()
+                .map_or_else(String::new, |s| format!("{s}: ")),
+            args.iter().map(src).collect::<Vec<_>>().join(", "),
+            if *align == Align::Left {
+                String::new()
+            } else {
+                format!(" ({})", align.as_str())
+            },
+            if sep == " " {
+                String::new()
+            } else {
+                format!(" (sep {sep:?})")
+            },
+            if *nobreak { " (nobreak)" } else { "" }
+        ),
+        // Sub and While skip the End when jumping
+        Statement::Sub {
+            name,
+            offset_to_end,
+            ..
+        } => format!("SUB     {} (skip to {:04})", name, idx + offset_to_end + 1),
+        Statement::Call { name, into, .. } => match into {
+            Some((var, _)) => format!("CALL    {name} (into {var})"),
+            None => format!("CALL    {name}"),
+        },
+        Statement::While {
+            cond,
+            offset_to_end,
+        } => format!(
+            "WHILE   {} (else to {:04})",
+            src(cond),
+            idx + offset_to_end + 1
+        ),
+        Statement::Let {
+            name,
+            init,
+            is_mut,
+            is_global,
+            ..
+        } => format!(
+            "LET     {} = {}{}{}",
+            name,
+            src(init),
+            if *is_mut { " (mut)" } else { "" },
+            if *is_global { " (global)" } else { "" }
+        ),
+        Statement::Modify {
+            name,
+            indices,
+            expr,
+            ..
+        } => format!(
+            "MODIFY  {}{} = {}",
+            name,
+            indices.iter().fold(String::new(), |mut s, index| {
+                write!(s, "[{}]", src(index)).unwrap();
+                s
+            }),
+            src(expr)
+        ),
+        Statement::If {
+            cond,
+            offset_to_next,
+        } => format!(
+            "IF      {} (else to {:04})",
+            src(cond),
+            idx + offset_to_next
+        ),
+        Statement::ElIf {
+            cond,
+            offset_to_next,
+        } => format!(
+            "ELIF    {} (else to {:04})",
+            src(cond),
+            idx + offset_to_next
+        ),
+        Statement::Switch {
+            subject,
+            offset_to_next,
+        } => format!(
+            "SWITCH  {} (cases from {:04})",
+            src(subject),
+            idx + offset_to_next
+        ),
+        Statement::Case {
+            value,
+            offset_to_next,
+        } => format!(
+            "CASE    {} (else to {:04})",
+            src(value),
+            idx + offset_to_next
+        ),
+        Statement::Else { offset_to_end } => {
+            format!("ELSE    (skip to {:04})", idx + offset_to_end)
+        }
+        Statement::Try { offset_to_recover } => {
+            format!("TRY     (recover at {:04})", idx + offset_to_recover)
+        }
+        Statement::Recover { offset_to_end } => {
+            format!("RECOVER (skip to {:04})", idx + offset_to_end)
+        }
+        Statement::End => "END".to_owned(),
+        Statement::Input {
+            prompt,
+            name,
+            as_num,
+            mask,
+            valid,
+            ..
+        } => format!(
+            "INPUT   {} as {}{}{}{}",
+            name,
+            if *as_num { "Num" } else { "Str" },
+            mask.as_ref()
+                .map_or_else(String::new, |m| format!(" (mask {m:?})")),
+            if valid.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    " (where {})",
+                    valid.iter().map(src).collect::<Vec<_>>().join(" and ")
+                )
+            },
+            prompt
+                .as_ref()
+                .map_or_else(String::new, |p| format!(" (prompt {p:?})"))
+        ),
+        Statement::Roll {
+            count,
+            face,
+            name,
+            silent,
+            ..
+        } => format!(
+            "ROLL    {} = {}d{}{}",
+            name,
+            src(count),
+            src(face),
+            if *silent { " (silent)" } else { "" }
+        ),
+        Statement::Halt { code } => match code {
+            Some(code) => format!("HALT    {}", src(code)),
+            None => "HALT".to_owned(),
+        },
+        Statement::Ill => "ILL".to_owned(),
+        Statement::Break => "BREAK".to_owned(),
+        Statement::Return { value } => match value {
+            Some(value) => format!("RETURN  {}", src(value)),
+            None => "RETURN".to_owned(),
+        },
+        Statement::Scene { title } => format!("SCENE   {title:?}"),
+        Statement::Breakpoint => "BRKPT".to_owned(),
+        Statement::Newline { count } => match count {
+            Some(count) => format!("NEWLINE {}", src(count)),
+            None => "NEWLINE".to_owned(),
+        },
+        Statement::Log { args } => format!(
+            "LOG     {}",
+            args.iter().map(src).collect::<Vec<_>>().join(", ")
+        ),
+        Statement::Status { template } => format!("STATUS  {:?}", template.to_string()),
+        Statement::Checkpoint => "CHKPT".to_owned(),
+        Statement::Restore => "RESTORE".to_owned(),
+        Statement::After { ms, name, .. } => format!("AFTER   {} ms, CALL {}", src(ms), name),
+        Statement::OnKey { key, name, .. } => format!("ONKEY   {key:?}, CALL {name}"),
+        Statement::OnError { name, .. } => format!("ONERROR CALL {name}"),
+        Statement::Hook { point, name, .. } => format!("HOOK    {}, CALL {}", point.as_str(), name),
+    }
+}
+
+/// Print all the statements of `prog` along with the source lines they came from
+pub fn print(prog: &AST, lexed: &Lexed) {
+    for (idx, (stmt, &row)) in prog.stmts.iter().zip(&prog.rows).enumerate() {
+        let inst = format_stmt(idx, stmt, lexed);
+        if row == 0 {
+            println!("{idx:04}  {inst}");
+        } else {
+            println!(
+                "{:04}  {:<40} ; {:>4} | {}",
+                idx,
+                inst,
+                row,
+                lexed.line(row).trim()
+            );
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! `doctor`: a report of the terminal the stories are played on,
+//! telling what may spoil the output and what to do about it
+//!
+//! The widths of characters are measured by printing them and asking the terminal where the cursor is,
+//! which needs the raw mode for a moment and a terminal on both stdin and stdout.
+//! Stories use no mouse, sound or save files, so none of them are checked.
+
+use crossterm::{cursor, terminal, tty::IsTty};
+use novelang::theme::{Role, Theme};
+use std::io::Write;
+use unicode_width::UnicodeWidthStr;
+
+/// Narrower terminals wrap most lines of text
+const MIN_COLUMNS: u16 = 40;
+
+/// Characters measured, with what they stand for
+const SAMPLES: [(&str, &str); 3] = [
+    ("wide characters", "あ"),
+    ("emoji", "\u{1F3B2}"),
+    ("combining marks", "e\u{301}"),
+];
+
+struct Check {
+    name: &'static str,
+    found: String,
+    /// What may go wrong, and what to do about it
+    problem: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, found: impl Into<String>) -> Self {
+        Self {
+            name,
+            found: found.into(),
+            problem: None,
+        }
+    }
+
+    fn problem(name: &'static str, found: impl Into<String>, problem: impl Into<String>) -> Self {
+        Self {
+            name,
+            found: found.into(),
+            problem: Some(problem.into()),
+        }
+    }
+}
+
+fn streams() -> Vec<Check> {
+    let describe = |is_tty: bool| if is_tty { "terminal" } else { "not a terminal" };
+    let stdin = std::io::stdin().is_tty();
+    let stdout = std::io::stdout().is_tty();
+    let mut checks = vec![Check::ok("stdin", describe(stdin))];
+    checks.push(if stdout {
+        Check::ok("stdout", describe(stdout))
+    } else {
+        Check::ok(
+            "stdout",
+            "not a terminal (plain text, without the status line and animations)",
+        )
+    });
+    checks
+}
+
+fn size() -> Check {
+    match terminal::size() {
+        Ok((0, _) | (_, 0)) => Check::problem(
+            "size",
+            "not reported by the terminal",
+            "80x24 is assumed for alignment, vertical writing and the status line",
+        ),
+        Ok((w, h)) if w < MIN_COLUMNS => Check::problem(
+            "size",
+            format!("{w}x{h}"),
+            format!("most lines wrap in fewer than {MIN_COLUMNS} columns"),
+        ),
+        Ok((w, h)) => Check::ok("size", format!("{w}x{h}")),
+        Err(e) => Check::problem(
+            "size",
+            format!("unknown ({e})"),
+            "80x24 is assumed for alignment, vertical writing and the status line",
+        ),
+    }
+}
+
+/// Whether the raw mode can be entered (and left)
+fn raw_mode() -> Check {
+    match terminal::enable_raw_mode().and_then(|()| terminal::disable_raw_mode()) {
+        Ok(()) => Check::ok("raw mode", "available"),
+        Err(e) => Check::ok("raw mode", format!("unavailable ({e})")),
+    }
+}
+
+fn colors(no_color: bool, high_contrast: bool) -> Vec<Check> {
+    let var = |name| std::env::var(name).unwrap_or_default();
+    let (term, colorterm) = (var("TERM"), var("COLORTERM"));
+    let depth = if colorterm == "truecolor" || colorterm == "24bit" {
+        "24-bit".to_owned()
+    } else if term.contains("256color") {
+        "256".to_owned()
+    } else if term.is_empty() {
+        "unknown (TERM is not set)".to_owned()
+    } else {
+        format!("16 (TERM={term})")
+    };
+    let depth = if term == "dumb" {
+        Check::problem(
+            "colors",
+            "none (TERM=dumb)",
+            "escape codes may be shown as they are; run with --no-color",
+        )
+    } else {
+        Check::ok("colors", depth)
+    };
+
+    let theme = match Theme::for_stream(std::io::stdout().is_tty()) {
+        Theme::Normal => "colors".to_owned(),
+        Theme::HighContrast => "high contrast".to_owned(),
+        Theme::NoColor if no_color => "no colors (--no-color)".to_owned(),
+        Theme::NoColor if std::env::var_os("NO_COLOR").is_some() => {
+            "no colors (NO_COLOR is set)".to_owned()
+        }
+        Theme::NoColor => "no colors (stdout is not a terminal)".to_owned(),
+    };
+    let theme = if high_contrast && no_color {
+        format!("{theme}, --high-contrast is overridden")
+    } else {
+        theme
+    };
+    vec![depth, Check::ok("theme", theme)]
+}
+
+/// The encoding told by the locale, which non-ASCII text is written in by the terminal
+fn locale() -> Option<Check> {
+    if cfg!(windows) {
+        return None;
+    }
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|v| !v.is_empty());
+    Some(match locale {
+        Some(l) if l.to_lowercase().replace('-', "").contains("utf8") => Check::ok("locale", l),
+        Some(l) => Check::problem(
+            "locale",
+            l,
+            "text other than ASCII may be garbled; use a UTF-8 locale",
+        ),
+        None => Check::problem(
+            "locale",
+            "not set",
+            "text other than ASCII may be garbled; set LANG to a UTF-8 locale",
+        ),
+    })
+}
+
+/// The columns taken by `s` when printed at the beginning of the line, which is cleared afterwards
+fn measure(w: &mut impl Write, s: &str) -> crossterm::Result<u16> {
+    write!(w, "\r{s}")?;
+    w.flush()?;
+    let col = cursor::position().map(|(col, _)| col);
+    write!(w, "\r{}", terminal::Clear(terminal::ClearType::CurrentLine))?;
+    w.flush()?;
+    col
+}
+
+fn widths() -> Vec<Check> {
+    let measurable = std::io::stdin().is_tty()
+        && std::io::stdout().is_tty()
+        && terminal::enable_raw_mode().is_ok();
+    if !measurable {
+        return vec![Check::ok(
+            "widths",
+            "not measured (needs the raw mode and a terminal on stdin and stdout)",
+        )];
+    }
+    let stdout = std::io::stdout();
+    let mut lock = stdout.lock();
+    let mut checks = Vec::new();
+    for &(name, sample) in &SAMPLES {
+        let expected = sample.width();
+        checks.push(match measure(&mut lock, sample) {
+            Ok(col) if usize::from(col) == expected => {
+                Check::ok(name, format!("ok ({col} column(s))"))
+            }
+            Ok(col) => Check::problem(
+                name,
+                format!("{col} column(s), {expected} expected"),
+                "vertical writing may be misaligned",
+            ),
+            Err(e) => {
+                // a terminal not answering once won't answer the next time, after the same timeout
+                checks.push(Check::ok("widths", format!("not measured ({e})")));
+                break;
+            }
+        });
+    }
+    let _ = terminal::disable_raw_mode();
+    checks
+}
+
+/// Print the report, and return whether no problems were found
+pub fn run(no_color: bool, high_contrast: bool) -> bool {
+    let mut checks = streams();
+    checks.push(size());
+    checks.push(raw_mode());
+    checks.extend(colors(no_color, high_contrast));
+    checks.extend(locale());
+    checks.extend(widths());
+
+    let stdout = std::io::stdout();
+    let theme = Theme::for_stream(stdout.is_tty());
+    let name_width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    let mut problems = 0;
+    for c in &checks {
+        println!("{:>width$}: {}", c.name, c.found, width = name_width);
+        if let Some(problem) = &c.problem {
+            problems += 1;
+            println!(
+                "{:>width$}  {} {}",
+                "",
+                theme.paint("warning:", Role::Warning),
+                problem,
+                width = name_width
+            );
+        }
+    }
+    println!();
+    match problems {
+        0 => println!("No problems found"),
+        1 => println!("1 problem found"),
+        n => println!("{n} problems found"),
+    }
+    problems == 0
+}
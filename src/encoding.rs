@@ -0,0 +1,244 @@
+//! Detection of the encodings of scripts and conversion into UTF-8
+//!
+//! Many scripts of Japanese novels are written in Shift-JIS (or EUC-JP),
+//! so they are converted before lexing, which only handles UTF-8.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Shift-JIS with the extensions of Windows (CP932)
+    ShiftJis,
+    EucJp,
+}
+
+impl Encoding {
+    pub const DISCRIMINANTS: &'static [Self] = &[
+        Self::Utf8,
+        Self::Utf16Le,
+        Self::Utf16Be,
+        Self::ShiftJis,
+        Self::EucJp,
+    ];
+
+    /// Names accepted for `--encoding`
+    pub const NAMES: &'static [&'static str] =
+        &["utf-8", "utf-16le", "utf-16be", "shift_jis", "euc-jp"];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+            Self::ShiftJis => "shift_jis",
+            Self::EucJp => "euc-jp",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::DISCRIMINANTS
+            .iter()
+            .find(|e| e.as_str().eq_ignore_ascii_case(s))
+            .copied()
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The byte at `offset` (from the beginning of the file) cannot be decoded
+    Invalid {
+        encoding: Encoding,
+        offset: usize,
+        byte: u8,
+    },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid {
+                encoding,
+                offset,
+                byte,
+            } => write!(
+                f,
+                "invalid {} byte 0x{:02X} at offset {}",
+                encoding.as_str(),
+                byte,
+                offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+const BOMS: &[(&[u8], Encoding)] = &[
+    (b"\xEF\xBB\xBF", Encoding::Utf8),
+    (b"\xFF\xFE", Encoding::Utf16Le),
+    (b"\xFE\xFF", Encoding::Utf16Be),
+];
+
+fn bom(bytes: &[u8]) -> Option<(Encoding, usize)> {
+    BOMS.iter()
+        .find(|(bom, _)| bytes.starts_with(bom))
+        .map(|(bom, enc)| (*enc, bom.len()))
+}
+
+/// Return the offset of the first byte that breaks the structure of multibyte characters
+fn first_invalid(bytes: &[u8], encoding: Encoding) -> Option<usize> {
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let len = match encoding {
+            Encoding::Utf8 => {
+                return std::str::from_utf8(bytes).err().map(|e| e.valid_up_to());
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => unreachable!(),
+            Encoding::ShiftJis => match b {
+                // ASCII and half-width katakana
+                0x00..=0x7F | 0xA1..=0xDF => 1,
+                0x81..=0x9F | 0xE0..=0xFC
+                    if matches!(bytes.get(i + 1), Some(0x40..=0x7E | 0x80..=0xFC)) =>
+                {
+                    2
+                }
+                _ => return Some(i),
+            },
+            Encoding::EucJp => {
+                let trail = |n: usize| bytes.get(i + n).is_some_and(|t| (0xA1..=0xFE).contains(t));
+                match b {
+                    0x00..=0x7F => 1,
+                    // half-width katakana
+                    0x8E if bytes.get(i + 1).is_some_and(|t| (0xA1..=0xDF).contains(t)) => 2,
+                    // JIS X 0212
+                    0x8F if trail(1) && trail(2) => 3,
+                    0xA1..=0xFE if trail(1) => 2,
+                    _ => return Some(i),
+                }
+            }
+        };
+        i += len;
+    }
+    None
+}
+
+/// Guess the encoding from the BOM, or from which encoding the bytes are valid in.
+/// UTF-8 is preferred, then Shift-JIS.
+pub fn detect(bytes: &[u8]) -> Encoding {
+    if let Some((encoding, _)) = bom(bytes) {
+        return encoding;
+    }
+    // the encoding valid the longest is the most likely, and ties go to the preferred one
+    let valid = |e| first_invalid(bytes, e).unwrap_or(usize::MAX);
+    let best = [Encoding::Utf8, Encoding::ShiftJis, Encoding::EucJp]
+        .iter()
+        .copied()
+        .max_by_key(|&e| (valid(e), std::cmp::Reverse(e as u8)))
+        .unwrap();
+    if best == Encoding::ShiftJis
+        && valid(Encoding::EucJp) == valid(Encoding::ShiftJis)
+        && mostly_kana(bytes)
+    {
+        Encoding::EucJp
+    } else {
+        best
+    }
+}
+
+/// Whether the bytes read as Shift-JIS have more half-width katakana than full-width characters.
+/// Text in EUC-JP looks like that, while half-width katakana are rare in actual Shift-JIS.
+fn mostly_kana(bytes: &[u8]) -> bool {
+    let (mut kana, mut wide) = (0_usize, 0_usize);
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            0xA1..=0xDF => kana += 1,
+            0x81..=0x9F | 0xE0..=0xFC => {
+                wide += 1;
+                i += 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    kana > wide
+}
+
+/// Convert `bytes` into a string, detecting the encoding if not given.
+/// The BOM is removed, and UTF-8 is taken as it is without copying.
+pub fn decode(mut bytes: Vec<u8>, encoding: Option<Encoding>) -> Result<String, DecodeError> {
+    let encoding = encoding.unwrap_or_else(|| detect(&bytes));
+    if let Some((e, len)) = bom(&bytes) {
+        if e == encoding {
+            bytes.drain(..len);
+        }
+    }
+    let invalid = |bytes: &[u8], offset: usize| DecodeError::Invalid {
+        encoding,
+        offset,
+        byte: bytes[offset],
+    };
+    match encoding {
+        Encoding::Utf8 => String::from_utf8(bytes)
+            .map_err(|e| invalid(e.as_bytes(), e.utf8_error().valid_up_to())),
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let units = bytes.chunks(2).map(|c| {
+                let pair = [c[0], *c.get(1).unwrap_or(&0)];
+                if encoding == Encoding::Utf16Le {
+                    u16::from_le_bytes(pair)
+                } else {
+                    u16::from_be_bytes(pair)
+                }
+            });
+            let mut s = String::new();
+            for (i, c) in std::char::decode_utf16(units).enumerate() {
+                match c {
+                    Ok(c) if bytes.len() >= 2 * i + 2 => s.push(c),
+                    _ => return Err(invalid(&bytes, 2 * i)),
+                }
+            }
+            Ok(s)
+        }
+        Encoding::ShiftJis | Encoding::EucJp => {
+            if let Some(offset) = first_invalid(&bytes, encoding) {
+                return Err(invalid(&bytes, offset));
+            }
+            convert(&bytes, encoding)
+        }
+    }
+}
+
+/// Convert the multibyte encodings with the tables of `encoding_rs`
+fn convert(bytes: &[u8], encoding: Encoding) -> Result<String, DecodeError> {
+    use encoding_rs::DecoderResult;
+
+    let mut decoder = match encoding {
+        // `Shift_JIS` of `encoding_rs` has the extensions of Windows
+        Encoding::ShiftJis => encoding_rs::SHIFT_JIS,
+        Encoding::EucJp => encoding_rs::EUC_JP,
+        _ => unreachable!(),
+    }
+    .new_decoder_without_bom_handling();
+    let mut out = String::with_capacity(
+        decoder
+            .max_utf8_buffer_length_without_replacement(bytes.len())
+            .expect("script too large to convert"),
+    );
+    match decoder.decode_to_string_without_replacement(bytes, &mut out, true) {
+        (DecoderResult::InputEmpty, _) => Ok(out),
+        // a character with no counterpart in Unicode
+        (DecoderResult::Malformed(len, after), read) => {
+            let offset = read - usize::from(after) - usize::from(len);
+            Err(DecodeError::Invalid {
+                encoding,
+                offset,
+                byte: bytes[offset],
+            })
+        }
+        (DecoderResult::OutputFull, _) => {
+            unreachable!("the buffer has the room for the longest output")
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! Exit codes of the process
+
+/// Other failures (unreadable files, invalid arguments, ...)
+pub const FAILURE: i32 = 1;
+/// The script could not be lexed
+pub const LEX_ERROR: i32 = 2;
+/// The script is syntactically wrong
+pub const PARSE_ERROR: i32 = 3;
+/// The script is well-formed but wrong in meaning (unknown names, types, denied lints, ...)
+pub const SEMANTIC_ERROR: i32 = 4;
+/// The script failed while running
+pub const RUNTIME_ERROR: i32 = 5;
+/// The user quit while running (Ctrl-C, or `q` in the debugger)
+pub const USER_QUIT: i32 = 130;
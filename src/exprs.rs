@@ -1,30 +1,55 @@
 mod eval;
+mod time;
+
+use crate::types::IntType;
+use std::convert::TryFrom;
 
 pub use eval::VarsMap;
+pub use time::DateTime;
 
 #[derive(Debug)]
 pub enum EvalError {
     VariableNotFound(String),
     OverFlow,
     ZeroDivision,
+    OutOfRange {
+        index: IntType,
+        len: usize,
+    },
     TypeError(String),
+    /// The local time zone cannot be told for the current time
+    NoLocalTime,
 }
 
 impl std::fmt::Display for EvalError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Failed to eval because ")?;
         match self {
-            Self::VariableNotFound(s) => write!(f, "variable {} was not found", s),
+            Self::VariableNotFound(s) => write!(f, "variable {s} was not found"),
             Self::OverFlow => write!(f, "of overflow"),
             Self::ZeroDivision => write!(f, "of zero division"),
-            Self::TypeError(s) => write!(f, "of type error: {}", s),
+            Self::OutOfRange { index, len } => write!(
+                f,
+                "index {index} is out of range for an array of {len} element(s)"
+            ),
+            Self::TypeError(s) => write!(f, "of type error: {s}"),
+            Self::NoLocalTime => write!(
+                f,
+                "the local time is unavailable (give it with --fixed-time)"
+            ),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// Position of the element at `index` in an array of `len` elements, if there is one
+pub fn element_at(index: IntType, len: usize) -> Option<usize> {
+    usize::try_from(index).ok().filter(|&i| i < len)
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Expr {
-    pub content: items::Rel,
+    pub content: Box<items::Rel>,
+    pub span: crate::lex::Span,
 }
 
 impl Expr {
@@ -35,46 +60,119 @@ impl Expr {
 }
 
 pub mod items {
-    #[derive(Debug, Clone)]
+    //! Items of expressions, from the lowest precedence
+    //! (operators keep the span of their token for diagnostics)
+
+    use crate::lex::Span;
+
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
     pub enum Rel {
         Single(AddSub),
-        Equal(AddSub, AddSub),
-        NotEqual(AddSub, AddSub),
-        LessEqual(AddSub, AddSub),
-        GreaterEqual(AddSub, AddSub),
-        LessThan(AddSub, AddSub),
-        GreaterThan(AddSub, AddSub),
+        Equal(AddSub, AddSub, Span),
+        NotEqual(AddSub, AddSub, Span),
+        LessEqual(AddSub, AddSub, Span),
+        GreaterEqual(AddSub, AddSub, Span),
+        LessThan(AddSub, AddSub, Span),
+        GreaterThan(AddSub, AddSub, Span),
     }
 
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
     pub enum AddSub {
         Single(MulDiv),
-        Add(MulDiv, Box<AddSub>),
-        Sub(MulDiv, Box<AddSub>),
+        Add(MulDiv, Box<Self>, Span),
+        Sub(MulDiv, Box<Self>, Span),
     }
 
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
     pub enum MulDiv {
         Single(Node),
-        Mul(Node, Box<MulDiv>),
-        Div(Node, Box<MulDiv>),
-        Mod(Node, Box<MulDiv>),
+        Mul(Node, Box<Self>, Span),
+        Div(Node, Box<Self>, Span),
+        Mod(Node, Box<Self>, Span),
     }
 
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
     pub enum Node {
         Single(Core),
-        Plus(Box<Node>),
-        Minus(Box<Node>),
+        Plus(Box<Self>, Span),
+        Minus(Box<Self>, Span),
     }
 
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
     pub enum Core {
         Str(String),
         Num(crate::types::IntType),
-        Ident(String),
+        Ident(String, crate::types::Slot),
         True,
         False,
         Paren(Box<Rel>),
+        /// `[a, b, c]` (with the span of `[`)
+        Array(Vec<Rel>, Span),
+        /// `array[index]` (with the span of `[`)
+        Index(Box<Self>, Box<Rel>, Span),
+        /// Formatter applied to the args (with the span of its name)
+        Fmt(Fmt, Vec<Rel>, Span),
+    }
+
+    /// Built-in formatters, which make `Str` from values, functions on `Str`, and the date and time
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+    pub enum Fmt {
+        /// `pad(num, width)`: pad with zeros
+        Pad,
+        /// `width(val, width)`: align to the right (to the left if width is negative)
+        Width,
+        /// `hex(num)`: hexadecimal
+        Hex,
+        /// `group(num)`: group digits by three with commas
+        Group,
+        /// `len(str)`: number of characters (grapheme clusters), or `len(array)`: number of elements
+        Len,
+        /// `substr(str, start, len)`: characters (grapheme clusters) from `start`
+        Substr,
+        /// `now_hour()`: the hour of the current time (0 to 23)
+        NowHour,
+        /// `today_weekday()`: the day of the week, from 0 (Sunday) to 6 (Saturday)
+        TodayWeekday,
+        /// `date_str()` or `date_str(format)`: the date and time formatted (`%Y-%m-%d` by default)
+        DateStr,
+        /// `arg(n)`: the `n`th argument given after `--` on the command line (empty if not given)
+        Arg,
+    }
+
+    impl Fmt {
+        pub const DISCRIMINANTS: &'static [Self] = &[
+            Self::Pad,
+            Self::Width,
+            Self::Hex,
+            Self::Group,
+            Self::Len,
+            Self::Substr,
+            Self::NowHour,
+            Self::TodayWeekday,
+            Self::DateStr,
+            Self::Arg,
+        ];
+
+        pub const fn as_str(self) -> &'static str {
+            match self {
+                Self::Pad => "pad",
+                Self::Width => "width",
+                Self::Hex => "hex",
+                Self::Group => "group",
+                Self::Len => "len",
+                Self::Substr => "substr",
+                Self::NowHour => "now_hour",
+                Self::TodayWeekday => "today_weekday",
+                Self::DateStr => "date_str",
+                Self::Arg => "arg",
+            }
+        }
+
+        pub fn from_str(s: &str) -> Option<Self> {
+            Self::DISCRIMINANTS
+                .iter()
+                .find(|f| f.as_str() == s)
+                .copied()
+        }
     }
 }
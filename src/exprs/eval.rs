@@ -1,9 +1,22 @@
+use std::convert::TryFrom;
+
+#[allow(clippy::wildcard_imports)]
 use super::items::*;
-use super::EvalError;
-use crate::types::Typed;
+use super::{DateTime, EvalError};
+use crate::types::{IntType, Slot, Typed};
 
 pub trait VarsMap {
-    fn get(&self, name: &str) -> Option<&Typed>;
+    fn get(&self, slot: Slot) -> Option<&Typed>;
+
+    /// The date and time seen by the script
+    fn now(&self) -> Option<DateTime> {
+        DateTime::now()
+    }
+
+    /// The arguments given to the script after `--`
+    fn args(&self) -> &[String] {
+        &[]
+    }
 }
 
 pub trait Eval {
@@ -29,12 +42,14 @@ impl Eval for Rel {
         use std::cmp::Ordering;
         Ok(match self {
             Self::Single(l) => l.eval_on(vmap)?,
-            Self::Equal(l, r) => def_cmp!(vmap, l, r, Ordering::Equal)?,
-            Self::NotEqual(l, r) => def_cmp!(vmap, l, r, Ordering::Less | Ordering::Greater)?,
-            Self::LessEqual(l, r) => def_cmp!(vmap, l, r, Ordering::Less | Ordering::Equal)?,
-            Self::GreaterEqual(l, r) => def_cmp!(vmap, l, r, Ordering::Greater | Ordering::Equal)?,
-            Self::LessThan(l, r) => def_cmp!(vmap, l, r, Ordering::Less)?,
-            Self::GreaterThan(l, r) => def_cmp!(vmap, l, r, Ordering::Greater)?,
+            Self::Equal(l, r, _) => def_cmp!(vmap, l, r, Ordering::Equal)?,
+            Self::NotEqual(l, r, _) => def_cmp!(vmap, l, r, Ordering::Less | Ordering::Greater)?,
+            Self::LessEqual(l, r, _) => def_cmp!(vmap, l, r, Ordering::Less | Ordering::Equal)?,
+            Self::GreaterEqual(l, r, _) => {
+                def_cmp!(vmap, l, r, Ordering::Greater | Ordering::Equal)?
+            }
+            Self::LessThan(l, r, _) => def_cmp!(vmap, l, r, Ordering::Less)?,
+            Self::GreaterThan(l, r, _) => def_cmp!(vmap, l, r, Ordering::Greater)?,
         })
     }
 }
@@ -59,10 +74,11 @@ macro_rules! def_ari {
 }
 
 impl Eval for AddSub {
+    #[allow(clippy::option_if_let_else)]
     fn eval_on<T: VarsMap>(&self, vmap: &T) -> Result<Typed, EvalError> {
         Ok(match self {
             Self::Single(l) => l.eval_on(vmap)?,
-            Self::Add(l, r) => {
+            Self::Add(l, r, _) => {
                 let l = l.eval_on(vmap)?;
                 let r = r.eval_on(vmap)?;
                 match (&l, &r) {
@@ -71,6 +87,9 @@ impl Eval for AddSub {
                         None => Err(EvalError::OverFlow),
                     },
                     (Typed::Str(this), Typed::Str(that)) => Ok(Typed::Str(this.clone() + that)),
+                    (Typed::Array(this), Typed::Array(that)) => {
+                        Ok(Typed::Array([this.as_slice(), that].concat()))
+                    }
                     _ => Err(EvalError::TypeError(format!(
                         "cannot perform {} between {} and {}",
                         "addition",
@@ -79,7 +98,7 @@ impl Eval for AddSub {
                     ))),
                 }
             }?,
-            Self::Sub(l, r) => {
+            Self::Sub(l, r, _) => {
                 def_ari!(vmap, l, r, checked_sub, EvalError::OverFlow, "subtraction")?
             }
         })
@@ -87,10 +106,11 @@ impl Eval for AddSub {
 }
 
 impl Eval for MulDiv {
+    #[allow(clippy::option_if_let_else)]
     fn eval_on<T: VarsMap>(&self, vmap: &T) -> Result<Typed, EvalError> {
         Ok(match self {
             Self::Single(l) => l.eval_on(vmap)?,
-            Self::Mul(l, r) => {
+            Self::Mul(l, r, _) => {
                 let l = l.eval_on(vmap)?;
                 let r = r.eval_on(vmap)?;
                 match (&l, &r) {
@@ -99,7 +119,7 @@ impl Eval for MulDiv {
                         None => Err(EvalError::OverFlow),
                     },
                     (Typed::Num(n), Typed::Str(s)) | (Typed::Str(s), Typed::Num(n)) => {
-                        Ok(Typed::Str(s.repeat(*n as usize)))
+                        Ok(Typed::Str(s.repeat(usize::try_from(*n).unwrap_or(0))))
                     }
                     _ => Err(EvalError::TypeError(format!(
                         "cannot perform {} between {} and {}",
@@ -109,10 +129,12 @@ impl Eval for MulDiv {
                     ))),
                 }
             }?,
-            Self::Div(l, r) => {
+            Self::Div(l, r, _) => {
                 def_ari!(vmap, l, r, checked_div, EvalError::ZeroDivision, "division")?
             }
-            Self::Mod(l, r) => def_ari!(vmap, l, r, checked_rem, EvalError::ZeroDivision, "mod")?,
+            Self::Mod(l, r, _) => {
+                def_ari!(vmap, l, r, checked_rem, EvalError::ZeroDivision, "mod")?
+            }
         })
     }
 }
@@ -121,8 +143,8 @@ impl Eval for Node {
     fn eval_on<T: VarsMap>(&self, vmap: &T) -> Result<Typed, EvalError> {
         Ok(match self {
             Self::Single(l) => l.eval_on(vmap)?,
-            Self::Plus(l) => l.eval_on(vmap)?,
-            Self::Minus(l) => -l.eval_on(vmap)?,
+            Self::Plus(l, _) => l.eval_on(vmap)?,
+            Self::Minus(l, _) => -l.eval_on(vmap)?,
         })
     }
 }
@@ -132,13 +154,129 @@ impl Eval for Core {
         Ok(match self {
             Self::Str(s) => Typed::Str(s.clone()),
             Self::Num(n) => Typed::Num(*n),
-            Self::Ident(name) => vmap
-                .get(name)
+            Self::Ident(name, slot) => vmap
+                .get(*slot)
                 .cloned()
                 .ok_or_else(|| EvalError::VariableNotFound(name.clone()))?,
             Self::True => Typed::Bool(true),
             Self::False => Typed::Bool(false),
             Self::Paren(expr) => expr.eval_on(vmap)?,
+            Self::Array(elems, _) => Typed::Array(
+                elems
+                    .iter()
+                    .map(|e| e.eval_on(vmap))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Self::Index(array, index, _) => {
+                let array = array.eval_on(vmap)?;
+                let index = index.eval_on(vmap)?;
+                match (array, index) {
+                    (Typed::Array(mut a), Typed::Num(n)) => match super::element_at(n, a.len()) {
+                        Some(i) => a.swap_remove(i),
+                        None => {
+                            return Err(EvalError::OutOfRange {
+                                index: n,
+                                len: a.len(),
+                            })
+                        }
+                    },
+                    (array, index) => {
+                        return Err(EvalError::TypeError(format!(
+                            "cannot index {} with {}",
+                            array.typename(),
+                            index.typename()
+                        )))
+                    }
+                }
+            }
+            Self::Fmt(fmt, args, _) => {
+                let args = args
+                    .iter()
+                    .map(|a| a.eval_on(vmap))
+                    .collect::<Result<Vec<_>, _>>()?;
+                apply_fmt(*fmt, &args, vmap)?
+            }
         })
     }
 }
+
+fn apply_fmt<T: VarsMap>(fmt: Fmt, args: &[Typed], vmap: &T) -> Result<Typed, EvalError> {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    Ok(Typed::Str(match (fmt, args) {
+        (Fmt::Pad, [Typed::Num(n), Typed::Num(w)]) => {
+            format!("{:0w$}", n, w = usize::try_from(*w).unwrap_or(0))
+        }
+        (Fmt::Width, [val, Typed::Num(w)]) => {
+            let s = match val {
+                Typed::Str(s) => s.clone(),
+                val => val.to_string(),
+            };
+            // counted in grapheme clusters, so that combining marks don't shorten the padding
+            let width = usize::try_from(w.unsigned_abs()).unwrap_or(usize::MAX);
+            let pad = " ".repeat(width.saturating_sub(s.graphemes(true).count()));
+            if *w < 0 {
+                s + &pad
+            } else {
+                pad + &s
+            }
+        }
+        (Fmt::Hex, [Typed::Num(n)]) => {
+            let sign = if *n < 0 { "-" } else { "" };
+            format!("{}{:x}", sign, n.unsigned_abs())
+        }
+        (Fmt::Group, [Typed::Num(n)]) => {
+            let digits = n.unsigned_abs().to_string();
+            let mut s = String::from(if *n < 0 { "-" } else { "" });
+            for (i, c) in digits.chars().enumerate() {
+                if i > 0 && (digits.len() - i) % 3 == 0 {
+                    s.push(',');
+                }
+                s.push(c);
+            }
+            s
+        }
+        (Fmt::Len, [Typed::Str(s)]) => {
+            let len = s.graphemes(true).count();
+            return Ok(Typed::Num(IntType::try_from(len).unwrap_or(IntType::MAX)));
+        }
+        (Fmt::Len, [Typed::Array(a)]) => {
+            return Ok(Typed::Num(
+                IntType::try_from(a.len()).unwrap_or(IntType::MAX),
+            ));
+        }
+        (Fmt::Substr, [Typed::Str(s), Typed::Num(start), Typed::Num(len)]) => {
+            // out of range is clamped, as in slicing of other languages
+            let start = usize::try_from(*start).unwrap_or(0);
+            let len = usize::try_from(*len).unwrap_or(0);
+            s.graphemes(true).skip(start).take(len).collect()
+        }
+        (Fmt::NowHour, []) => {
+            return Ok(Typed::Num(
+                vmap.now().ok_or(EvalError::NoLocalTime)?.hour.into(),
+            ))
+        }
+        (Fmt::TodayWeekday, []) => {
+            return Ok(Typed::Num(
+                vmap.now().ok_or(EvalError::NoLocalTime)?.weekday().into(),
+            ))
+        }
+        (Fmt::DateStr, []) => vmap.now().ok_or(EvalError::NoLocalTime)?.format("%Y-%m-%d"),
+        (Fmt::DateStr, [Typed::Str(f)]) => vmap.now().ok_or(EvalError::NoLocalTime)?.format(f),
+        (Fmt::Arg, [Typed::Num(n)]) => usize::try_from(*n)
+            .ok()
+            .and_then(|n| vmap.args().get(n))
+            .cloned()
+            .unwrap_or_default(),
+        _ => {
+            return Err(EvalError::TypeError(format!(
+                "cannot apply {} to ({})",
+                fmt.as_str(),
+                args.iter()
+                    .map(Typed::typename)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )))
+        }
+    }))
+}
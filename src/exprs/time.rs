@@ -0,0 +1,113 @@
+//! The date and time seen by `now_hour()`, `today_weekday()` and `date_str()`
+
+use std::convert::TryFrom;
+
+/// Date and time in the local time zone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    /// 1 to 12
+    pub month: u32,
+    /// 1 to 31
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+/// Days from 1970-01-01 to the date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+const fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    /// The current time of the system, or None if it cannot be told in the local time zone
+    pub fn now() -> Option<Self> {
+        use chrono::{Datelike, TimeZone, Timelike};
+
+        let now = chrono::Local
+            .timestamp_opt(chrono::Utc::now().timestamp(), 0)
+            .earliest()?;
+        Some(Self {
+            year: i64::from(now.year()),
+            month: now.month(),
+            day: now.day(),
+            hour: now.hour(),
+            minute: now.minute(),
+            second: now.second(),
+        })
+    }
+
+    /// Parse `YYYY-MM-DDTHH:MM[:SS]` (or with a space instead of `T`)
+    pub fn parse(s: &str) -> Option<Self> {
+        let (date, time) = s.split_once(['T', ' '])?;
+        let mut date = date.splitn(3, '-');
+        let year = date.next()?.parse().ok()?;
+        let month = date.next()?.parse().ok()?;
+        let day = date.next()?.parse().ok()?;
+        let mut time = time.splitn(3, ':');
+        let hour = time.next()?.parse().ok()?;
+        let minute = time.next()?.parse().ok()?;
+        let second = time.next().map_or(Some(0), |s| s.parse().ok())?;
+        let valid = (1..=12).contains(&month)
+            && (1..=days_in_month(year, month)).contains(&day)
+            && hour < 24
+            && minute < 60
+            && second < 60;
+        valid.then_some(Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    /// Day of the week, from 0 (Sunday) to 6 (Saturday)
+    pub fn weekday(&self) -> u32 {
+        // 1970-01-01 was a Thursday
+        u32::try_from((days_from_civil(self.year, self.month, self.day) + 4).rem_euclid(7)).unwrap()
+    }
+
+    /// Format with `%Y` (year), `%m` (month), `%d` (day), `%H` (hour), `%M` (minute), `%S` (second)
+    /// and `%%`, leaving the rest as it is
+    pub fn format(&self, fmt: &str) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let mut chars = fmt.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            let _ = match chars.next() {
+                Some('Y') => write!(out, "{:04}", self.year),
+                Some('m') => write!(out, "{:02}", self.month),
+                Some('d') => write!(out, "{:02}", self.day),
+                Some('H') => write!(out, "{:02}", self.hour),
+                Some('M') => write!(out, "{:02}", self.minute),
+                Some('S') => write!(out, "{:02}", self.second),
+                Some('%') | None => write!(out, "%"),
+                Some(c) => write!(out, "%{c}"),
+            };
+        }
+        out
+    }
+}
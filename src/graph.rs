@@ -0,0 +1,92 @@
+//! Call graph of subroutines in the Graphviz DOT format
+
+use crate::parse::{PrintHook, Statement, AST};
+
+const MAIN: &str = "(main)";
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// How a subroutine is called
+#[derive(PartialEq)]
+enum Via {
+    Call,
+    After,
+    OnKey(String),
+    OnError,
+    Hook(PrintHook),
+}
+
+impl Via {
+    /// Attributes of the edge, with the subroutines registered for later drawn dotted
+    fn attrs(&self) -> String {
+        let label = match self {
+            Self::Call => return String::new(),
+            Self::After => "after".to_owned(),
+            Self::OnKey(key) => format!("onkey {key}"),
+            Self::OnError => "onerror".to_owned(),
+            Self::Hook(point) => format!("hook {}", point.as_str()),
+        };
+        format!(" [style=dotted, label={}]", quote(&label))
+    }
+}
+
+/// Write the graph of calls between subroutines (and the top level as `(main)`).
+/// Subroutines never called are drawn dashed, and the ones registered by
+/// `after`, `onkey`, `onerror` and `hook` are reached by dotted edges labeled with the command.
+pub fn write_dot(w: &mut impl std::io::Write, prog: &AST) -> std::io::Result<()> {
+    let mut subs = Vec::new();
+    let mut edges: Vec<(String, String, Via)> = Vec::new();
+    // subroutines enclosing the current statement, with the index of their End
+    let mut owners: Vec<(&str, usize)> = Vec::new();
+
+    for (idx, stmt) in prog.stmts.iter().enumerate().skip(1) {
+        while owners.last().is_some_and(|(_, end)| idx > *end) {
+            owners.pop();
+        }
+        let caller = owners.last().map_or(MAIN, |(name, _)| name);
+        let (name, via) = match stmt {
+            Statement::Sub {
+                name,
+                offset_to_end,
+                ..
+            } => {
+                subs.push(name.as_str());
+                owners.push((name, idx + offset_to_end));
+                continue;
+            }
+            Statement::Call { name, .. } => (name, Via::Call),
+            Statement::After { name, .. } => (name, Via::After),
+            Statement::OnKey { key, name, .. } => (name, Via::OnKey(key.clone())),
+            Statement::OnError { name, .. } => (name, Via::OnError),
+            Statement::Hook { point, name, .. } => (name, Via::Hook(*point)),
+            _ => continue,
+        };
+        let edge = (caller.to_owned(), name.clone(), via);
+        if !edges.contains(&edge) {
+            edges.push(edge);
+        }
+    }
+
+    writeln!(w, "digraph calls {{")?;
+    writeln!(w, "    {} [shape=box];", quote(MAIN))?;
+    for sub in &subs {
+        if edges.iter().any(|(_, callee, _)| callee == sub) {
+            writeln!(w, "    {};", quote(sub))?;
+        } else {
+            writeln!(w, "    {} [style=dashed];", quote(sub))?;
+        }
+    }
+    for (caller, callee, via) in &edges {
+        writeln!(
+            w,
+            "    {} -> {}{};",
+            quote(caller),
+            quote(callee),
+            via.attrs()
+        )?;
+    }
+    writeln!(w, "}}")?;
+    Ok(())
+}
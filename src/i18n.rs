@@ -0,0 +1,414 @@
+//! Extraction of translatable strings
+
+#[allow(clippy::wildcard_imports)]
+use crate::exprs::items::*;
+use crate::lex::{Command, Items, Keywords, Lexed, Ops};
+use crate::parse::{Piece, Statement, Template, AST};
+
+/// A translatable string and where it appears
+pub struct Entry {
+    pub id: String,
+    pub text: String,
+    /// Rows where the string appears
+    pub rows: Vec<usize>,
+}
+
+/// FNV-1a, used as it must not change between builds
+fn hash(s: &str) -> u32 {
+    s.bytes().fold(0x811c_9dc5, |h, b| {
+        (h ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Return the name of the file used in IDs, so that they don't depend on where the project is
+pub fn file_key(filename: &str) -> String {
+    std::path::Path::new(filename)
+        .file_name()
+        .map_or_else(|| filename.to_owned(), |n| n.to_string_lossy().into_owned())
+}
+
+/// Return the stable ID of `text` in the file
+pub fn string_id(file_key: &str, text: &str) -> String {
+    format!("{}:{:08x}", file_key, hash(text))
+}
+
+/// Collect the strings in Print, the prompts of Input, status lines and scene titles,
+/// in order of appearance
+pub fn extract(lexed: &Lexed, file_key: &str) -> Vec<Entry> {
+    let is_rel = |item: Option<&Items>| matches!(item, Some(Items::Ops(Ops::Rel(_))));
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut in_text_stmt = false;
+    let mut prev = None;
+    for (i, tk) in lexed.tokens.iter().enumerate() {
+        match &tk.item {
+            // masks are not text
+            Items::Str(_) if prev == Some(&Items::Key(Keywords::Masked)) => {}
+            // nor are the strings compared with, which are left untranslated
+            Items::Str(_) if is_rel(prev) || is_rel(lexed.tokens.get(i + 1).map(|t| &t.item)) => {}
+            Items::Cmd(
+                Command::Print
+                | Command::Say
+                | Command::Center
+                | Command::Right
+                | Command::Input
+                | Command::Statusline
+                | Command::Scene,
+            ) => {
+                in_text_stmt = true;
+            }
+            Items::Semi => in_text_stmt = false,
+            Items::Str(s) if in_text_stmt => {
+                if let Some(e) = entries.iter_mut().find(|e| &e.text == s) {
+                    e.rows.push(tk.loc.row);
+                } else {
+                    entries.push(Entry {
+                        id: string_id(file_key, s),
+                        text: s.clone(),
+                        rows: vec![tk.loc.row],
+                    });
+                }
+            }
+            _ => {}
+        }
+        prev = Some(&tk.item);
+    }
+    entries
+}
+
+fn po_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Write entries in the PO format, with the ID as `msgctxt`
+pub fn write_po(
+    w: &mut impl std::io::Write,
+    file_key: &str,
+    entries: &[Entry],
+) -> std::io::Result<()> {
+    writeln!(w, "msgid \"\"")?;
+    writeln!(w, "msgstr \"\"")?;
+    writeln!(w, "\"Content-Type: text/plain; charset=UTF-8\\n\"")?;
+    for e in entries {
+        writeln!(w)?;
+        let refs: Vec<_> = e.rows.iter().map(|r| format!("{file_key}:{r}")).collect();
+        writeln!(w, "#: {}", refs.join(" "))?;
+        writeln!(w, "msgctxt \"{}\"", po_escape(&e.id))?;
+        writeln!(w, "msgid \"{}\"", po_escape(&e.text))?;
+        writeln!(w, "msgstr \"\"")?;
+    }
+    Ok(())
+}
+
+/// Write entries in CSV, with an empty column for translations
+pub fn write_csv(w: &mut impl std::io::Write, entries: &[Entry]) -> std::io::Result<()> {
+    writeln!(w, "id,line,source,translation")?;
+    for e in entries {
+        writeln!(w, "{},{},{},", e.id, e.rows[0], csv_escape(&e.text))?;
+    }
+    Ok(())
+}
+
+/// Write each occurrence of the strings as `file:line: text`,
+/// so that spellcheckers can be run over the prose
+pub fn write_text(
+    w: &mut impl std::io::Write,
+    filename: &str,
+    entries: &[Entry],
+) -> std::io::Result<()> {
+    let mut occurrences: Vec<_> = entries
+        .iter()
+        .flat_map(|e| e.rows.iter().map(move |r| (*r, &e.text)))
+        .collect();
+    occurrences.sort_by_key(|(row, _)| *row);
+    for (row, text) in occurrences {
+        writeln!(w, "{filename}:{row}: {text}")?;
+    }
+    Ok(())
+}
+
+/// Translations loaded from a catalog, keyed by string IDs
+pub struct Catalog {
+    file_key: String,
+    map: std::collections::HashMap<String, String>,
+}
+
+fn po_unescape(s: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => ret.push('\n'),
+                Some('t') => ret.push('\t'),
+                Some(c) => ret.push(c),
+                None => {}
+            }
+        } else {
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+/// Return the content of a quoted PO string (`"..."`)
+fn po_quoted(s: &str) -> Option<String> {
+    let s = s.trim();
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        Some(po_unescape(&s[1..s.len() - 1]))
+    } else {
+        None
+    }
+}
+
+/// Split a CSV line into fields
+pub fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+impl Catalog {
+    /// Load a catalog for the file of `file_key` (CSV if the extension is `csv`, PO otherwise)
+    pub fn load(path: &std::path::Path, file_key: &str) -> Result<Self, String> {
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read \"{}\" : {}", path.display(), e))?;
+        let mut cat = Self {
+            file_key: file_key.to_owned(),
+            map: std::collections::HashMap::new(),
+        };
+        if path.extension().is_some_and(|e| e == "csv") {
+            for line in s.lines().skip(1) {
+                let f = csv_fields(line);
+                if f.len() >= 4 {
+                    cat.add(&f[0], &f[3]);
+                }
+            }
+        } else {
+            cat.parse_po(&s);
+        }
+        Ok(cat)
+    }
+
+    fn add(&mut self, id: &str, translation: &str) {
+        // untranslated entries fall back to the source
+        if !translation.is_empty() {
+            self.map.insert(id.to_owned(), translation.to_owned());
+        }
+    }
+
+    fn parse_po(&mut self, s: &str) {
+        #[derive(PartialEq)]
+        enum Field {
+            None,
+            Ctxt,
+            Id,
+            Str,
+        }
+        let (mut ctxt, mut id, mut tr) = (None::<String>, String::new(), String::new());
+        let mut field = Field::None;
+        // an empty line is appended to flush the last entry
+        for line in s.lines().chain(std::iter::once("")) {
+            let line = line.trim();
+            if line.is_empty() {
+                if field == Field::Str && !id.is_empty() {
+                    let key = ctxt
+                        .take()
+                        .unwrap_or_else(|| string_id(&self.file_key, &id));
+                    self.add(&key, &tr);
+                }
+                ctxt = None;
+                id.clear();
+                tr.clear();
+                field = Field::None;
+            } else if let Some(rest) = line.strip_prefix("msgctxt ") {
+                ctxt = po_quoted(rest);
+                field = Field::Ctxt;
+            } else if let Some(rest) = line.strip_prefix("msgid ") {
+                id = po_quoted(rest).unwrap_or_default();
+                field = Field::Id;
+            } else if let Some(rest) = line.strip_prefix("msgstr ") {
+                tr = po_quoted(rest).unwrap_or_default();
+                field = Field::Str;
+            } else if let Some(cont) = po_quoted(line) {
+                match field {
+                    Field::Ctxt => ctxt.get_or_insert_with(String::new).push_str(&cont),
+                    Field::Id => id.push_str(&cont),
+                    Field::Str => tr.push_str(&cont),
+                    Field::None => {}
+                }
+            }
+        }
+    }
+
+    /// Return the translation of `text`, if any
+    pub fn get(&self, text: &str) -> Option<&String> {
+        self.map.get(&string_id(&self.file_key, text))
+    }
+}
+
+/// Replace the string literals that are shown with their translations,
+/// leaving those only compared, measured or used as indices as they are
+trait Translate {
+    fn translate(&mut self, cat: &Catalog);
+}
+
+impl Translate for crate::exprs::Expr {
+    fn translate(&mut self, cat: &Catalog) {
+        self.content.translate(cat);
+    }
+}
+
+impl Translate for Rel {
+    fn translate(&mut self, cat: &Catalog) {
+        match self {
+            Self::Single(i) => i.translate(cat),
+            // only the result of comparisons is shown
+            Self::Equal(..)
+            | Self::NotEqual(..)
+            | Self::LessEqual(..)
+            | Self::GreaterEqual(..)
+            | Self::LessThan(..)
+            | Self::GreaterThan(..) => {}
+        }
+    }
+}
+
+impl Translate for AddSub {
+    fn translate(&mut self, cat: &Catalog) {
+        match self {
+            Self::Single(i) => i.translate(cat),
+            Self::Add(l, r, _) | Self::Sub(l, r, _) => {
+                l.translate(cat);
+                r.translate(cat);
+            }
+        }
+    }
+}
+
+impl Translate for MulDiv {
+    fn translate(&mut self, cat: &Catalog) {
+        match self {
+            Self::Single(i) => i.translate(cat),
+            Self::Mul(l, r, _) | Self::Div(l, r, _) | Self::Mod(l, r, _) => {
+                l.translate(cat);
+                r.translate(cat);
+            }
+        }
+    }
+}
+
+impl Translate for Node {
+    fn translate(&mut self, cat: &Catalog) {
+        match self {
+            Self::Single(i) => i.translate(cat),
+            Self::Plus(i, _) | Self::Minus(i, _) => i.translate(cat),
+        }
+    }
+}
+
+impl Translate for Core {
+    fn translate(&mut self, cat: &Catalog) {
+        match self {
+            Self::Str(s) => {
+                if let Some(t) = cat.get(s) {
+                    *s = t.clone();
+                }
+            }
+            Self::Paren(i) => i.translate(cat),
+            Self::Array(elems, _) => {
+                for elem in elems {
+                    elem.translate(cat);
+                }
+            }
+            Self::Index(array, _, _) => array.translate(cat),
+            // the other formatters show numbers, or positions in the text as written
+            Self::Fmt(Fmt::Width | Fmt::Substr, args, _) => {
+                if let Some(val) = args.first_mut() {
+                    val.translate(cat);
+                }
+            }
+            Self::Fmt(..) | Self::Num(_) | Self::Ident(..) | Self::True | Self::False => {}
+        }
+    }
+}
+
+/// Parse the translation of a status line, taking the variables from the original template
+/// (None if it is malformed or shows a variable the original doesn't)
+fn translate_template(original: &Template, translation: &str) -> Option<Template> {
+    let mut template = Template::parse(translation).ok()?;
+    for piece in &mut template.0 {
+        if let Piece::Var(name, slot) = piece {
+            *slot = original.0.iter().find_map(|p| match p {
+                Piece::Var(n, s) if n == name => Some(*s),
+                _ => None,
+            })?;
+        }
+    }
+    Some(template)
+}
+
+/// Replace the strings in Print, the prompts and messages of Input,
+/// status lines and scene titles with their translations
+pub fn translate(prog: &mut AST, cat: &Catalog) {
+    for stmt in &mut prog.stmts {
+        match stmt {
+            Statement::Print { args, speaker, .. } => {
+                if let Some(t) = speaker.as_ref().and_then(|s| cat.get(s)) {
+                    *speaker = Some(t.clone());
+                }
+                for arg in args {
+                    arg.translate(cat);
+                }
+            }
+            Statement::Input {
+                prompt,
+                invalid_msg,
+                ..
+            } => {
+                for text in prompt.iter_mut().chain(invalid_msg.iter_mut()) {
+                    if let Some(t) = cat.get(text) {
+                        *text = t.clone();
+                    }
+                }
+            }
+            Statement::Status { template } => {
+                let text = template.to_string();
+                if let Some(t) = cat.get(&text) {
+                    if let Some(translated) = translate_template(template, t) {
+                        *template = translated;
+                    } else {
+                        log::warn!(
+                            "Ignoring the translation of the status line \"{text}\" : it is malformed or shows other variables"
+                        );
+                    }
+                }
+            }
+            Statement::Scene { title } => {
+                if let Some(t) = cat.get(title) {
+                    *title = t.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+}
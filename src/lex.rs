@@ -1,3 +1,11 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Return `s` in NFC, so that names which look the same are the same
+/// (e.g. "が" typed as one character, or as "か" followed by a combining voiced sound mark)
+pub fn nfc(s: &str) -> String {
+    s.nfc().collect()
+}
+
 /// Trait for token items
 pub trait Item
 where
@@ -44,6 +52,29 @@ pub enum Command {
     Roll,
     Halt,
     Break,
+    Scene,
+    Breakpoint,
+    Log,
+    Newline,
+    Center,
+    Right,
+    Statusline,
+    Say,
+    Checkpoint,
+    Restore,
+    After,
+    OnKey,
+    OnError,
+    Try,
+    Recover,
+    Hook,
+    Macro,
+    Pragma,
+    Switch,
+    Case,
+    Default,
+    Return,
+    Global,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -56,6 +87,13 @@ pub enum Keywords {
     Face,
     True,
     False,
+    Sep,
+    NoBreak,
+    Silent,
+    Masked,
+    Where,
+    And,
+    Into,
 }
 
 impl Item for Keywords {
@@ -68,6 +106,13 @@ impl Item for Keywords {
         Self::Face,
         Self::True,
         Self::False,
+        Self::Sep,
+        Self::NoBreak,
+        Self::Silent,
+        Self::Masked,
+        Self::Where,
+        Self::And,
+        Self::Into,
     ];
 
     fn as_str(&self) -> &str {
@@ -80,6 +125,13 @@ impl Item for Keywords {
             Self::Face => "face",
             Self::True => "true",
             Self::False => "false",
+            Self::Sep => "sep",
+            Self::NoBreak => "nobreak",
+            Self::Silent => "silent",
+            Self::Masked => "masked",
+            Self::Where => "where",
+            Self::And => "and",
+            Self::Into => "into",
         }
     }
 
@@ -116,6 +168,29 @@ impl Item for Command {
         Self::Roll,
         Self::Halt,
         Self::Break,
+        Self::Scene,
+        Self::Breakpoint,
+        Self::Log,
+        Self::Newline,
+        Self::Center,
+        Self::Right,
+        Self::Statusline,
+        Self::Say,
+        Self::Checkpoint,
+        Self::Restore,
+        Self::After,
+        Self::OnKey,
+        Self::OnError,
+        Self::Try,
+        Self::Recover,
+        Self::Hook,
+        Self::Macro,
+        Self::Pragma,
+        Self::Switch,
+        Self::Case,
+        Self::Default,
+        Self::Return,
+        Self::Global,
     ];
 
     fn as_str(&self) -> &str {
@@ -133,6 +208,29 @@ impl Item for Command {
             Self::Roll => "roll",
             Self::Halt => "halt",
             Self::Break => "break",
+            Self::Scene => "scene",
+            Self::Breakpoint => "breakpoint",
+            Self::Log => "log",
+            Self::Newline => "newline",
+            Self::Center => "center",
+            Self::Right => "right",
+            Self::Statusline => "statusline",
+            Self::Say => "say",
+            Self::Checkpoint => "checkpoint",
+            Self::Restore => "restore",
+            Self::After => "after",
+            Self::OnKey => "onkey",
+            Self::OnError => "onerror",
+            Self::Try => "try",
+            Self::Recover => "recover",
+            Self::Hook => "hook",
+            Self::Macro => "macro",
+            Self::Pragma => "pragma",
+            Self::Switch => "switch",
+            Self::Case => "case",
+            Self::Default => "default",
+            Self::Return => "return",
+            Self::Global => "global",
         }
     }
 
@@ -229,13 +327,9 @@ impl Item for Ops {
     }
 
     fn parse_slice(s: &[char]) -> Option<Self> {
-        if let Some(i) = AriOps::parse_slice(s) {
-            Some(Self::Ari(i))
-        } else if let Some(i) = RelOps::parse_slice(s) {
-            Some(Self::Rel(i))
-        } else {
-            None
-        }
+        AriOps::parse_slice(s)
+            .map(Self::Ari)
+            .or_else(|| RelOps::parse_slice(s).map(Self::Rel))
     }
 }
 
@@ -249,11 +343,15 @@ pub enum Items {
     Str(String),
     Semi,
     Comma,
+    Colon,
     LParen,
     RParen,
+    LBracket,
+    RBracket,
 }
 
 impl Items {
+    #[allow(clippy::enum_glob_use)]
     pub fn len(&self) -> usize {
         use Items::*;
         match self {
@@ -261,18 +359,26 @@ impl Items {
             Cmd(i) => i.len(),
             Ops(i) => i.len(),
             Num(_, l) => *l,
-            Ident(i) | Str(i) => i.len(),
-            Semi | Comma | LParen | RParen => 1,
+            Ident(i) => i.chars().count(),
+            Str(i) => i.chars().count() + 2, // including quotes
+            Semi | Comma | Colon | LParen | RParen | LBracket | RBracket => 1,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Location {
     pub row: usize,
     pub col: usize,
 }
 
+/// Represents a range in the source, from `from` until right before `to`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Span {
+    pub from: Location,
+    pub to: Location,
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub loc: Location,
@@ -287,6 +393,13 @@ impl Token {
             col: loc.col + self.item.len(),
         }
     }
+
+    pub fn span(&self) -> Span {
+        Span {
+            from: self.loc.clone(),
+            to: self.next_col_loc(),
+        }
+    }
 }
 
 impl std::fmt::Display for Token {
@@ -301,47 +414,127 @@ impl std::fmt::Display for Token {
 
 #[derive(Debug, Clone)]
 pub struct Lexed {
-    pub lines: Vec<String>,
+    /// The whole source, borrowed by the lines rather than copied into each of them
+    source: String,
+    /// The byte range of each line in `source`
+    lines: Vec<std::ops::Range<usize>>,
     pub tokens: Vec<Token>,
 }
 
 #[derive(Debug, Clone)]
 pub struct LocInfo {
-    line: String,
-    loc: Location,
+    pub line: String,
+    pub loc: Location,
+    pub len: usize,
+}
+
+impl LocInfo {
+    /// Return the spaces put before the carets and the number of the carets,
+    /// measured in columns of the terminal, so that they are right under full-width text
+    pub fn underline(&self) -> (String, usize) {
+        use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+        let start = self
+            .line
+            .char_indices()
+            .nth(self.loc.col - 1)
+            .map_or(self.line.len(), |(i, _)| i);
+        let (prefix, rest) = self.line.split_at(start);
+        // tabs are kept, as their width depends on the terminal
+        let pad = prefix
+            .chars()
+            .map(|c| {
+                if c == '\t' {
+                    "\t".to_owned()
+                } else {
+                    " ".repeat(c.width().unwrap_or(0))
+                }
+            })
+            .collect();
+        let marked: String = rest.chars().take(self.len).collect();
+        // beyond the end of the line (e.g. a missing token) one column for each
+        let carets = marked.width() + (self.len - marked.chars().count());
+        (pad, carets.max(1))
+    }
 }
 
 impl std::fmt::Display for LocInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let row = self.loc.row;
-        let col = self.loc.col;
+        let (pad, carets) = self.underline();
         writeln!(f, "     |")?;
-        writeln!(f, "{:<4} | {}", row, self.line)?;
-        writeln!(f, "     | {:>1$}", "^", col)?;
+        writeln!(f, "{:<4} | {}", self.loc.row, self.line)?;
+        writeln!(f, "     | {}{}", pad, "^".repeat(carets))?;
         writeln!(f, "     |")?;
         Ok(())
     }
 }
 
 impl Lexed {
+    /// The line at `row` (counted from 1)
+    pub fn line(&self, row: usize) -> &str {
+        &self.source[self.lines[row - 1].clone()]
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(move |r| &self.source[r.clone()])
+    }
+
+    /// The same source with other tokens
+    #[must_use]
+    pub fn with_tokens(&self, tokens: Vec<Token>) -> Self {
+        Self {
+            source: self.source.clone(),
+            lines: self.lines.clone(),
+            tokens,
+        }
+    }
+
     pub fn generate_loc_info(&self, loc: &Location) -> LocInfo {
         LocInfo {
-            line: self.lines[loc.row - 1].clone(),
+            line: self.line(loc.row).to_owned(),
             loc: loc.clone(),
+            len: 1,
         }
     }
+
+    /// Generate `LocInfo` underlining `span`
+    /// (only the first line is shown for spans across lines)
+    pub fn generate_span_info(&self, span: &Span) -> LocInfo {
+        let line = self.line(span.from.row).to_owned();
+        let to_col = if span.to.row == span.from.row {
+            span.to.col
+        } else {
+            line.chars().count() + 1
+        };
+        LocInfo {
+            line,
+            loc: span.from.clone(),
+            len: to_col.saturating_sub(span.from.col).max(1),
+        }
+    }
+}
+
+/// Write a line followed by its tokens
+fn fmt_line(
+    f: &mut std::fmt::Formatter<'_>,
+    row: usize,
+    text: &str,
+    tokens: &[Token],
+) -> std::fmt::Result {
+    writeln!(f, "{row:4>} |{text}")?;
+    for tk in tokens {
+        write!(f, "{:?} ", tk.item)?;
+    }
+    writeln!(f)
 }
 
 impl std::fmt::Display for Lexed {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut i = 0;
-        for idx in 0..self.lines.len() {
-            writeln!(f, "{:4>} |{}", idx + 1, self.lines[idx])?;
-            while i < self.tokens.len() && self.tokens[i].loc.row == idx + 1 {
-                write!(f, "{:?} ", self.tokens[i].item)?;
-                i += 1;
-            }
-            writeln!(f)?;
+        let mut rest = &self.tokens[..];
+        for (idx, line) in self.lines().enumerate() {
+            let n = rest.iter().take_while(|tk| tk.loc.row == idx + 1).count();
+            fmt_line(f, idx + 1, line, &rest[..n])?;
+            rest = &rest[n..];
         }
         Ok(())
     }
@@ -359,141 +552,310 @@ impl std::error::Error for Error {}
 enum ErrorKind {
     UnterminatedStr,
     UnexpectedChar(char),
+    NumTooLarge,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnterminatedStr => write!(f, "String is not terminated"),
+            Self::UnexpectedChar(c) => write!(f, "Unexpected character '{c}'"),
+            Self::NumTooLarge => write!(f, "Integer literal is too large"),
+        }
+    }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.kind {
-            ErrorKind::UnterminatedStr => write!(f, "String is not terminated")?,
-            ErrorKind::UnexpectedChar(c) => write!(f, "Unexpected character '{}'", c)?,
-        };
+        write!(f, "{}", self.kind)?;
         let l = &self.loc_info;
         writeln!(f, " ({}:{})\n{}", l.loc.row, l.loc.col, l)?;
         Ok(())
     }
 }
 
+impl crate::diag::Diagnostic for Error {
+    fn code(&self) -> crate::diag::Code {
+        use crate::diag::Code;
+        match self.kind {
+            ErrorKind::UnterminatedStr => Code::E001,
+            ErrorKind::UnexpectedChar(_) => Code::E002,
+            ErrorKind::NumTooLarge => Code::E011,
+        }
+    }
+
+    fn message(&self) -> String {
+        self.kind.to_string()
+    }
+
+    fn loc_info(&self) -> &LocInfo {
+        &self.loc_info
+    }
+}
+
 const RESERVED_CHARS: &[char] = &[
-    '+', '-', '*', '/', '%', '"', '<', '>', '!', '=', ';', ',', '(', ')',
+    '+', '-', '*', '/', '%', '"', '<', '>', '!', '=', ';', ',', '(', ')', ':', '[', ']',
 ];
 
-fn is_ident_char(c: char) -> bool {
+/// Return the ASCII digit for `c` if it is a digit, including full-width ones (`０`-`９`) typed with IMEs
+pub fn ascii_digit(c: char) -> Option<char> {
+    match c {
+        '0'..='9' => Some(c),
+        '０'..='９' => char::from_u32(u32::from(c) - u32::from('０') + u32::from('0')),
+        _ => None,
+    }
+}
+
+/// Whether `c` can be a part of identifiers (and names in placeholders)
+pub(crate) fn is_ident_char(c: char) -> bool {
     !c.is_whitespace() && !RESERVED_CHARS.contains(&c)
 }
 
-fn is_sep(c: char) -> bool {
-    c.is_whitespace() || c == ';'
+const fn is_sep(c: char) -> bool {
+    c.is_whitespace() || c == ';' || c == ':'
 }
 
-pub fn lex(s: String) -> Result<Lexed, Error> {
-    let mut tks = Vec::new();
-    let lines: Vec<_> = s.lines().map(String::from).collect();
-    for (idx, l) in lines.iter().enumerate() {
-        let v: Vec<_> = l.chars().collect();
-        let mut i = 0;
-        while i < v.len() {
-            if v[i].is_whitespace() {
-                i += 1;
-            } else {
-                let loc = Location {
-                    row: idx + 1,
-                    col: i + 1,
-                };
-                tks.push(Token {
-                    loc: loc.clone(),
-                    item: match v[i] {
-                        '#' => {
-                            break;
-                        }
-                        ';' => {
-                            i += 1;
-                            Items::Semi
+/// Remove the BOM and unify line breaks (CRLF and CR) into LF,
+/// so that files written on Windows are lexed as the others
+fn normalize(mut s: String) -> String {
+    if s.starts_with('\u{feff}') {
+        s.drain(..'\u{feff}'.len_utf8());
+    }
+    if s.contains('\r') {
+        s.replace("\r\n", "\n").replace('\r', "\n")
+    } else {
+        s
+    }
+}
+
+/// The byte ranges of the lines, without "\n"
+fn line_ranges(s: &str) -> Vec<std::ops::Range<usize>> {
+    let mut start = 0;
+    s.split_inclusive('\n')
+        .map(|l| {
+            let range = start..start + l.strip_suffix('\n').unwrap_or(l).len();
+            start += l.len();
+            range
+        })
+        .collect()
+}
+
+/// Lex the line at `row` (counted from 1) into `tks`, with the errors into `errors`
+#[allow(clippy::too_many_lines)]
+fn lex_line(row: usize, l: &str, tks: &mut Vec<Token>, errors: &mut Vec<Error>) {
+    let v: Vec<_> = l.chars().collect();
+    let mut i = 0;
+    'line: while i < v.len() {
+        if v[i].is_whitespace() {
+            i += 1;
+        } else {
+            let loc = Location { row, col: i + 1 };
+            let item = match v[i] {
+                '#' => {
+                    break;
+                }
+                ';' => {
+                    i += 1;
+                    Items::Semi
+                }
+                ',' => {
+                    i += 1;
+                    Items::Comma
+                }
+                ':' => {
+                    i += 1;
+                    Items::Colon
+                }
+                '(' => {
+                    i += 1;
+                    Items::LParen
+                }
+                ')' => {
+                    i += 1;
+                    Items::RParen
+                }
+                '[' => {
+                    i += 1;
+                    Items::LBracket
+                }
+                ']' => {
+                    i += 1;
+                    Items::RBracket
+                }
+                '"' => {
+                    let start = i;
+                    i += 1;
+                    let mut s = String::new();
+                    loop {
+                        if i >= v.len() {
+                            // the rest of the line is consumed by the string
+                            errors.push(Error {
+                                loc_info: LocInfo {
+                                    line: l.to_owned(),
+                                    loc,
+                                    len: v.len() - start,
+                                },
+                                kind: ErrorKind::UnterminatedStr,
+                            });
+                            break 'line;
                         }
-                        ',' => {
+                        if v[i] == '"' {
                             i += 1;
-                            Items::Comma
+                            break Items::Str(s);
                         }
-                        '(' => {
+                        s.push(v[i]);
+                        i += 1;
+                    }
+                }
+                _ => {
+                    let vs = &v[i..];
+                    let confirm_item = |len| len == vs.len() || is_sep(vs[len]);
+                    if is_item(&"die".chars().collect::<Vec<_>>(), vs) && confirm_item(3) {
+                        // convert "die" to "dice"
+                        i += 3;
+                        Items::Key(Keywords::Dice)
+                    } else if is_item(&"faces".chars().collect::<Vec<_>>(), vs) && confirm_item(5) {
+                        // convert "faces" to "face"
+                        i += 5;
+                        Items::Key(Keywords::Face)
+                    } else if let Some(res) = Keywords::parse_slice(vs) {
+                        i += res.len();
+                        Items::Key(res)
+                    } else if let Some(res) = Command::parse_slice(vs) {
+                        i += res.len();
+                        Items::Cmd(res)
+                    } else if let Some(res) = Ops::parse_slice(vs) {
+                        i += res.len();
+                        Items::Ops(res)
+                    } else if ascii_digit(v[i]).is_some() {
+                        let mut s = String::new();
+                        while let Some(d) = v.get(i).copied().and_then(ascii_digit) {
+                            s.push(d);
                             i += 1;
-                            Items::LParen
                         }
-                        ')' => {
-                            i += 1;
-                            Items::RParen
+                        if let Ok(n) = s.parse() {
+                            Items::Num(n, s.len())
+                        } else {
+                            // only digits are taken, so it must be an overflow
+                            errors.push(Error {
+                                loc_info: LocInfo {
+                                    line: l.to_owned(),
+                                    loc,
+                                    len: s.len(),
+                                },
+                                kind: ErrorKind::NumTooLarge,
+                            });
+                            continue;
                         }
-                        '"' => {
+                    } else if is_ident_char(v[i]) {
+                        let mut s = String::new();
+                        while i < v.len() && is_ident_char(v[i]) {
+                            s.push(v[i]);
                             i += 1;
-                            let mut s = String::new();
-                            loop {
-                                if i >= v.len() {
-                                    return Err(Error {
-                                        loc_info: LocInfo {
-                                            line: l.clone(),
-                                            loc,
-                                        },
-                                        kind: ErrorKind::UnterminatedStr,
-                                    });
-                                }
-                                if v[i] == '"' {
-                                    i += 1;
-                                    break Items::Str(s);
-                                }
-                                s.push(v[i]);
-                                i += 1;
-                            }
                         }
-                        _ => {
-                            let vs = &v[i..];
-                            let confirm_item = |len| len == vs.len() || is_sep(vs[len]);
-                            if is_item(&"die".chars().collect::<Vec<_>>(), vs) && confirm_item(3) {
-                                // convert "die" to "dice"
-                                i += 3;
-                                Items::Key(Keywords::Dice)
-                            } else if is_item(&"faces".chars().collect::<Vec<_>>(), vs)
-                                && confirm_item(5)
-                            {
-                                // convert "faces" to "face"
-                                i += 5;
-                                Items::Key(Keywords::Face)
-                            } else if let Some(res) = Keywords::parse_slice(vs) {
-                                i += res.len();
-                                Items::Key(res)
-                            } else if let Some(res) = Command::parse_slice(vs) {
-                                i += res.len();
-                                Items::Cmd(res)
-                            } else if let Some(res) = Ops::parse_slice(vs) {
-                                i += res.len();
-                                Items::Ops(res)
-                            } else if v[i].is_numeric() {
-                                let mut s = String::new();
-                                while i < v.len() && v[i].is_numeric() {
-                                    s.push(v[i]);
-                                    i += 1;
-                                }
-                                Items::Num(s.parse().unwrap(), s.len())
-                            } else if is_ident_char(v[i]) {
-                                let mut s = String::new();
-                                while i < v.len() && is_ident_char(v[i]) {
-                                    s.push(v[i]);
-                                    i += 1;
-                                }
-                                Items::Ident(s)
-                            } else {
-                                eprintln!("{:?}", tks);
-                                return Err(Error {
-                                    loc_info: LocInfo {
-                                        line: l.clone(),
-                                        loc,
-                                    },
-                                    kind: ErrorKind::UnexpectedChar(v[i]),
-                                });
-                            }
-                        }
-                    },
-                });
+                        Items::Ident(nfc(&s))
+                    } else {
+                        // skip the character and keep going
+                        errors.push(Error {
+                            loc_info: LocInfo {
+                                line: l.to_owned(),
+                                loc,
+                                len: 1,
+                            },
+                            kind: ErrorKind::UnexpectedChar(v[i]),
+                        });
+                        i += 1;
+                        continue;
+                    }
+                }
+            };
+            tks.push(Token { loc, item });
+        }
+    }
+}
+
+/// A line lexed on its own by `LexLines`
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub row: usize,
+    pub text: String,
+    pub tokens: Vec<Token>,
+    pub errors: Vec<Error>,
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt_line(f, self.row, &self.text, &self.tokens)
+    }
+}
+
+/// Lexer reading a line only when the tokens of the previous ones have been taken,
+/// so that the beginning of a script can be lexed without the rest
+pub struct LexLines<R> {
+    reader: R,
+    row: usize,
+    /// Lines read but not lexed yet (a read may end in several lines separated by "\r")
+    pending: std::collections::VecDeque<String>,
+}
+
+impl<R: std::io::BufRead> Iterator for LexLines<R> {
+    type Item = std::io::Result<Line>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending.is_empty() {
+            let mut buf = String::new();
+            match self.reader.read_line(&mut buf) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
             }
+            if self.row == 0 && buf.starts_with('\u{feff}') {
+                buf.drain(..'\u{feff}'.len_utf8());
+            }
+            // line endings as in `normalize`
+            let buf = buf.strip_suffix('\n').unwrap_or(&buf);
+            let buf = buf.strip_suffix('\r').unwrap_or(buf);
+            self.pending.extend(buf.split('\r').map(String::from));
         }
+        let text = self.pending.pop_front()?;
+        self.row += 1;
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        lex_line(self.row, &text, &mut tokens, &mut errors);
+        Some(Ok(Line {
+            row: self.row,
+            text,
+            tokens,
+            errors,
+        }))
+    }
+}
+
+/// Lex the UTF-8 text from `reader` line by line (decode other encodings first and use `lex`)
+pub const fn lex_lines<R: std::io::BufRead>(reader: R) -> LexLines<R> {
+    LexLines {
+        reader,
+        row: 0,
+        pending: std::collections::VecDeque::new(),
+    }
+}
+
+pub fn lex(s: String) -> Result<Lexed, Vec<Error>> {
+    let mut tks = Vec::new();
+    let mut errors = Vec::new();
+    let source = normalize(s);
+    let lines = line_ranges(&source);
+    for (idx, l) in lines.iter().map(|r| &source[r.clone()]).enumerate() {
+        lex_line(idx + 1, l, &mut tks, &mut errors);
     }
 
-    Ok(Lexed { lines, tokens: tks })
+    if errors.is_empty() {
+        Ok(Lexed {
+            source,
+            lines,
+            tokens: tks,
+        })
+    } else {
+        Err(errors)
+    }
 }
@@ -0,0 +1,27 @@
+//! Lexer, parser and checker of novelang scripts
+
+#![warn(future_incompatible)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(clippy::nursery)]
+#![warn(clippy::pedantic)]
+#![allow(clippy::fallible_impl_from)]
+#![allow(clippy::future_not_send)]
+#![allow(clippy::len_without_is_empty)]
+#![allow(clippy::match_wildcard_for_single_variants)]
+#![allow(clippy::missing_errors_doc)]
+#![allow(clippy::missing_panics_doc)]
+#![allow(clippy::must_use_candidate)]
+#![allow(clippy::needless_pass_by_value)]
+#![allow(clippy::new_without_default)]
+#![allow(clippy::should_implement_trait)]
+#![allow(clippy::similar_names)]
+
+pub mod diag;
+pub mod encoding;
+pub mod exprs;
+pub mod lex;
+pub mod lint;
+pub mod parse;
+pub mod theme;
+pub mod types;
@@ -0,0 +1,106 @@
+//! Lints (checks for suspicious but valid scripts) and their levels
+
+use crate::diag::Code;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    /// A subroutine is declared but never called
+    UnusedSub,
+    /// A variable hides another one declared in an outer block
+    Shadowing,
+}
+
+impl Lint {
+    pub const DISCRIMINANTS: &'static [Self] = &[Self::UnusedSub, Self::Shadowing];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::UnusedSub => "unused-sub",
+            Self::Shadowing => "shadowing",
+        }
+    }
+
+    /// Parse a lint name (`_` may be used instead of `-`)
+    pub fn from_str(s: &str) -> Option<Self> {
+        let s = s.replace('_', "-");
+        Self::DISCRIMINANTS
+            .iter()
+            .find(|l| l.as_str().eq_ignore_ascii_case(&s))
+            .copied()
+    }
+
+    pub const fn code(self) -> Code {
+        match self {
+            Self::UnusedSub => Code::W001,
+            Self::Shadowing => Code::W002,
+        }
+    }
+
+    const fn default_level(self) -> Level {
+        match self {
+            Self::UnusedSub | Self::Shadowing => Level::Warn,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Warn => "warn",
+            Self::Deny => "deny",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        [Self::Allow, Self::Warn, Self::Deny]
+            .iter()
+            .find(|l| l.as_str().eq_ignore_ascii_case(s))
+            .copied()
+    }
+}
+
+/// Level of each lint
+#[derive(Debug, Clone)]
+pub struct Levels {
+    levels: Vec<Level>,
+}
+
+impl Levels {
+    pub fn new() -> Self {
+        Self {
+            levels: Lint::DISCRIMINANTS
+                .iter()
+                .map(|l| l.default_level())
+                .collect(),
+        }
+    }
+
+    fn index(lint: Lint) -> usize {
+        Lint::DISCRIMINANTS.iter().position(|l| *l == lint).unwrap()
+    }
+
+    pub fn set(&mut self, lint: Lint, level: Level) {
+        self.levels[Self::index(lint)] = level;
+    }
+
+    pub fn get(&self, lint: Lint) -> Level {
+        self.levels[Self::index(lint)]
+    }
+
+    /// Promote all lints at `Warn` to `Deny`
+    pub fn deny_warnings(&mut self) {
+        for l in &mut self.levels {
+            if *l == Level::Warn {
+                *l = Level::Deny;
+            }
+        }
+    }
+}
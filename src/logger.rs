@@ -0,0 +1,39 @@
+//! Messages about the progress of the tool, written to stderr
+
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if self.enabled(record.metadata()) {
+            let level = match record.level() {
+                log::Level::Error => "Error",
+                log::Level::Warn => "Warning",
+                log::Level::Info => "Info",
+                log::Level::Debug => "Debug",
+                log::Level::Trace => "Trace",
+            };
+            eprintln!("{}: {}", level, record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Show warnings and errors only if `quiet`, and more details with each `verbose`
+pub fn init(quiet: bool, verbose: u8) {
+    let level = if quiet {
+        log::LevelFilter::Warn
+    } else {
+        match verbose {
+            0 => log::LevelFilter::Info,
+            1 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    };
+    log::set_logger(&Logger).expect("logger is set only once");
+    log::set_max_level(level);
+}
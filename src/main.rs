@@ -9,12 +9,23 @@
 #![allow(clippy::needless_pass_by_value)]
 #![allow(clippy::similar_names)]
 
-mod exprs;
-mod lex;
-mod parse;
+mod cache;
+mod config;
+mod dialogue;
+mod disasm;
+mod doctor;
+mod exit;
+mod graph;
+mod i18n;
+mod logger;
+mod minify;
+mod renpy;
 mod runtime;
-mod types;
+mod stats;
+mod watch;
 
+use novelang::encoding::{self, Encoding};
+use novelang::{diag, exprs, lex, lint, parse, theme, types};
 use structopt::StructOpt;
 
 #[macro_export]
@@ -22,44 +33,755 @@ macro_rules! die {
     ($( $x:expr ),*) => {
         {
             eprintln!($($x,)*);
-            std::process::exit(1)
+            std::process::exit($crate::exit::FAILURE)
         }
     }
 }
 
 #[derive(StructOpt)]
+#[allow(clippy::struct_excessive_bools)] // independent flags
+#[structopt(about = "An interpreter for novelang scripts")]
 struct Opt {
+    /// Seed for the random number generator used by Roll
+    #[structopt(long, global = true, value_name = "N")]
+    seed: Option<u64>,
+
+    /// Date and time seen by `now_hour()`, `today_weekday()` and `date_str()` (e.g. 2024-12-24T23:30)
+    #[structopt(long, global = true, value_name = "TIME", parse(try_from_str = parse_time))]
+    fixed_time: Option<exprs::DateTime>,
+
+    /// Never wait for Enter after Print, even if the script enables it
+    #[structopt(long, global = true)]
+    no_wait: bool,
+
+    /// Show only warnings and errors of the tool
+    #[structopt(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Encoding of the script (UTF-8, UTF-16 with BOM, Shift-JIS or EUC-JP), detected if not given
+    #[structopt(long, global = true, value_name = "NAME", parse(try_from_str = parse_encoding))]
+    encoding: Option<Encoding>,
+
+    /// Never use colors (also set by the `NO_COLOR` environment variable)
+    #[structopt(long, global = true)]
+    no_color: bool,
+
+    /// Use bold, underlined and reversed text instead of colors
+    #[structopt(long, global = true)]
+    high_contrast: bool,
+
+    /// Show more details of the tool (-vv for even more)
+    #[structopt(short, long, global = true, parse(from_occurrences))]
+    verbose: u8,
+
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Run a script
+    Run(RunOpts),
+    /// Check a script for errors without running it
+    Check(Script),
+    /// Print the parsed statements of a script
+    Ast(Script),
+    /// Print the parsed instructions with jump targets and source lines
+    Disasm(Script),
+    /// Print the call graph of subroutines in the DOT format
+    Graph(Script),
+    /// Print statistics of a story (word count, branches, endings, ...)
+    Stats(Script),
+    /// Convert a script for other engines
+    Export(Export),
+    /// Convert drafts from other formats into a script
+    Import(Import),
+    /// Print the script without comments and with short identifiers, in one line
+    Minify(Script),
+    /// Print the tokens of a script
+    Tokens {
+        /// Path to the script ("-" to read from stdin)
+        filename: String,
+
+        /// Print only the first N tokens, reading no further than their lines (UTF-8 only)
+        #[structopt(long, value_name = "N")]
+        head: Option<usize>,
+    },
+    /// Print the strings of Print and Input, status lines and scene titles as a translation catalog
+    ExtractStrings {
+        /// Path to the script ("-" to read from stdin)
+        filename: String,
+
+        /// Format of the catalog ("text" for plain text with locations, for spellcheckers)
+        #[structopt(long, default_value = "po", possible_values = &["po", "csv", "text"])]
+        format: String,
+    },
+    /// Show the detailed description of an error code
+    Explain {
+        /// Error code (e.g. E005)
+        code: String,
+    },
+    /// Check the terminal for what may spoil the output (size, colors, widths of characters, ...)
+    Doctor,
+    /// Remove the programs cached by `run`
+    CacheClean,
+}
+
+#[derive(StructOpt)]
+enum Export {
+    /// Print the script converted into Ren'Py
+    Renpy(Script),
+    /// Run the script and print the path taken as a Markdown document
+    Md {
+        #[structopt(flatten)]
+        script: Script,
+
+        /// File with the inputs to give, one per line
+        #[structopt(long, value_name = "PATH")]
+        replay: Option<String>,
+    },
+}
+
+#[derive(StructOpt)]
+enum Import {
+    /// Print a script made from dialogue in CSV (columns "speaker", "line" and "scene"),
+    /// with a subroutine per scene
+    Csv {
+        /// Path to the CSV file ("-" to read from stdin)
+        filename: String,
+    },
+}
+
+#[derive(StructOpt)]
+#[allow(clippy::struct_excessive_bools)] // independent flags
+struct RunOpts {
+    #[structopt(flatten)]
+    script: Script,
+
+    /// Run the script again whenever it is modified
+    #[structopt(long)]
+    watch: bool,
+
+    /// Language to show, using the catalog <LANG>.po (or <LANG>.csv) beside the script
+    #[structopt(long, value_name = "LANG")]
+    lang: Option<String>,
+
+    /// Translation catalog to use (PO or CSV)
+    #[structopt(long, value_name = "PATH")]
+    catalog: Option<std::path::PathBuf>,
+
+    /// Fast-forward to the scene with this title (everything before it is run without output)
+    #[structopt(long, value_name = "TITLE")]
+    scene: Option<String>,
+
+    /// Start by calling this sub, and end when it returns
+    /// (only `let` and `sub` at the top level are run before it)
+    #[structopt(long, value_name = "SUB", conflicts_with = "scene")]
+    entry: Option<String>,
+
+    /// Log each executed instruction with the values involved to the file (to stderr if omitted)
+    #[structopt(long, value_name = "FILE", require_equals = true)]
+    #[allow(clippy::option_option)] // the way to take an optional value
+    trace: Option<Option<std::path::PathBuf>>,
+
+    /// Write the messages of `log` to the file instead of stderr
+    #[structopt(long, value_name = "FILE")]
+    log_file: Option<std::path::PathBuf>,
+
+    /// Discard the messages of `log`
+    #[structopt(long, conflicts_with = "log-file")]
+    no_log: bool,
+
+    /// Developer mode: stop at `breakpoint;` to inspect variables
+    #[structopt(long)]
+    dev: bool,
+
+    /// Calls which may be nested (e.g. by recursion) before the script stops
+    /// with a stack overflow (1000 by default)
+    #[structopt(long, value_name = "N")]
+    max_call_depth: Option<usize>,
+
+    /// Parse the script again without reading or writing the cache of parsed programs
+    #[structopt(long)]
+    no_cache: bool,
+
+    /// Write the variables, the last instruction and the seed in JSON to the file
+    /// (to stderr if omitted) when the script ends or fails
+    #[structopt(long, value_name = "FILE", require_equals = true)]
+    #[allow(clippy::option_option)] // the way to take an optional value
+    dump_vars: Option<Option<std::path::PathBuf>>,
+
+    /// Arguments for the script, seen as `_argc` and `arg(0)`, `arg(1)`, ...
+    #[structopt(last = true, value_name = "ARGS")]
+    args: Vec<String>,
+}
+
+impl RunOpts {
+    fn load_catalog(&self) -> Option<i18n::Catalog> {
+        let filename = &self.script.filename;
+        let path = match (&self.catalog, &self.lang) {
+            (Some(path), _) => path.clone(),
+            (None, Some(lang)) => {
+                let dir = std::path::Path::new(filename)
+                    .parent()
+                    .unwrap_or_else(|| std::path::Path::new("."));
+                ["po", "csv"]
+                    .iter()
+                    .map(|ext| dir.join(format!("{lang}.{ext}")))
+                    .find(|p| p.is_file())
+                    .unwrap_or_else(|| die!("Error: no catalog for \"{}\" was found", lang))
+            }
+            (None, None) => return None,
+        };
+        let cat = i18n::Catalog::load(&path, &i18n::file_key(filename))
+            .unwrap_or_else(|e| die!("Error: {}", e));
+        Some(cat)
+    }
+}
+
+#[derive(StructOpt)]
+struct Script {
+    /// Path to the script ("-" to read from stdin)
     filename: String,
+
+    /// Declare a read-only variable before the script (e.g. `-d difficulty=2 -d debug=true`);
+    /// "true" and "false" are Bool, integers are Num and the rest is Str
+    #[structopt(short = "d", long = "define", value_name = "NAME=VALUE", number_of_values = 1, parse(try_from_str = parse_define))]
+    defines: Vec<(String, types::Typed)>,
+
+    #[structopt(flatten)]
+    lints: LintOpts,
 }
 
-fn main() {
-    let opt = Opt::from_args();
-    let s = if opt.filename == "-" {
+#[derive(StructOpt)]
+struct LintOpts {
+    /// Silence the lint
+    #[structopt(short = "A", long, value_name = "LINT", number_of_values = 1)]
+    allow: Vec<String>,
+
+    /// Report the lint as a warning
+    #[structopt(short = "W", long, value_name = "LINT", number_of_values = 1)]
+    warn: Vec<String>,
+
+    /// Report the lint as an error
+    #[structopt(short = "D", long, value_name = "LINT", number_of_values = 1)]
+    deny: Vec<String>,
+
+    /// Report all the warnings as errors
+    #[structopt(long)]
+    deny_warnings: bool,
+}
+
+impl LintOpts {
+    /// Levels from the configuration, overridden by the command line
+    fn levels(&self, config: &config::Config) -> lint::Levels {
+        let mut levels = lint::Levels::new();
+        config
+            .apply_lints(&mut levels)
+            .unwrap_or_else(|e| die!("Error: {}", e));
+        for (names, level) in &[
+            (&self.allow, lint::Level::Allow),
+            (&self.warn, lint::Level::Warn),
+            (&self.deny, lint::Level::Deny),
+        ] {
+            for name in *names {
+                let l = lint::Lint::from_str(name)
+                    .unwrap_or_else(|| die!("Error: unknown lint \"{}\"", name));
+                levels.set(l, *level);
+            }
+        }
+        if self.deny_warnings {
+            levels.deny_warnings();
+        }
+        levels
+    }
+}
+
+/// Load the configuration for the script (or the default one if not found)
+fn load_config(filename: &str) -> config::Config {
+    config::Config::discover(filename).map_or_else(Default::default, |(path, config)| {
+        log::info!("Using {}", path.display());
+        config
+    })
+}
+
+fn parse_define(s: &str) -> Result<(String, types::Typed), String> {
+    use types::Typed;
+
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("\"{s}\" is not in the form of NAME=VALUE"))?;
+    // the name has to be lexed as an identifier by itself
+    let ident = lex::lex(name.to_owned())
+        .ok()
+        .and_then(|lexed| match lexed.tokens.as_slice() {
+            [tk] => match &tk.item {
+                lex::Items::Ident(ident) => Some(ident.clone()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .filter(|ident| !ident.starts_with('_'))
+        .ok_or_else(|| format!("\"{name}\" cannot be the name of a variable"))?;
+    let value = match value {
+        "true" => Typed::Bool(true),
+        "false" => Typed::Bool(false),
+        _ => value
+            .parse()
+            .map_or_else(|_| Typed::Str(value.to_owned()), Typed::Num),
+    };
+    Ok((ident, value))
+}
+
+fn parse_time(s: &str) -> Result<exprs::DateTime, String> {
+    exprs::DateTime::parse(s)
+        .ok_or_else(|| format!("\"{s}\" is not a date and time like 2024-12-24T23:30"))
+}
+
+fn parse_encoding(s: &str) -> Result<Encoding, String> {
+    Encoding::from_str(s).ok_or_else(|| {
+        format!(
+            "unknown encoding \"{}\" (expected one of {})",
+            s,
+            Encoding::NAMES.join(", ")
+        )
+    })
+}
+
+/// Read the file and convert it into UTF-8, detecting the encoding if not given
+fn read_source(filename: &str, encoding: Option<Encoding>) -> String {
+    let bytes = if filename == "-" {
         use std::io::Read;
-        let mut s = String::new();
+        let mut bytes = Vec::new();
         std::io::stdin()
-            .read_to_string(&mut s)
+            .read_to_end(&mut bytes)
             .unwrap_or_else(|e| die!("Read error: failed to read stdin : {}", e));
-        s
+        bytes
     } else {
-        let name = &opt.filename;
-        std::fs::read_to_string(name)
-            .unwrap_or_else(|e| die!("Read error: failed to read file \"{}\" : {}", name, e))
+        std::fs::read(filename)
+            .unwrap_or_else(|e| die!("Read error: failed to read file \"{}\" : {}", filename, e))
     };
+    if encoding.is_none() {
+        log::debug!("Detected {}", encoding::detect(&bytes).as_str());
+    }
+    encoding::decode(bytes, encoding).unwrap_or_else(|e| {
+        die!(
+            "Read error: failed to decode \"{}\" : {} (specify the encoding with --encoding)",
+            filename,
+            e
+        )
+    })
+}
+
+/// Read and lex the file, reporting errors on failure
+fn try_lex_file(filename: &str, encoding: Option<Encoding>) -> Option<lex::Lexed> {
+    try_lex_source(filename, read_source(filename, encoding))
+}
+
+/// Lex the source read from the file, reporting errors on failure
+fn try_lex_source(filename: &str, s: String) -> Option<lex::Lexed> {
+    let emitter = diag::Emitter::new(filename);
 
-    eprintln!("Info: Lexing");
-    let lexed = match lex::lex(s) {
-        Ok(i) => {
-            eprintln!("Lexed:\n{}", i);
-            i
+    log::info!("Lexing");
+    let start = std::time::Instant::now();
+    let result = lex::lex(s);
+    log::debug!("Lexing took {:?}", start.elapsed());
+    match result {
+        Ok(lexed) => {
+            log::trace!("{} tokens", lexed.tokens.len());
+            Some(lexed)
         }
-        Err(e) => die!("Syntax Error: {}", e),
+        Err(errors) => {
+            for e in &errors {
+                emitter.emit(e);
+            }
+            emitter.summary(errors.len(), 0);
+            None
+        }
+    }
+}
+
+fn lex_file(filename: &str, encoding: Option<Encoding>) -> lex::Lexed {
+    try_lex_file(filename, encoding).unwrap_or_else(|| std::process::exit(exit::LEX_ERROR))
+}
+
+/// Print the lines of the script with their tokens until `head` tokens have been printed,
+/// lexing the lines as they are read
+fn print_head_tokens(filename: &str, head: usize) {
+    use std::io::BufRead;
+
+    let reader: Box<dyn BufRead> = if filename == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        let file = std::fs::File::open(filename)
+            .unwrap_or_else(|e| die!("Read error: failed to read file \"{}\" : {}", filename, e));
+        Box::new(std::io::BufReader::new(file))
     };
+    let emitter = diag::Emitter::new(filename);
+    let mut errors = 0;
+    let mut left = head;
+    let mut lines = lex::lex_lines(reader);
+    while left > 0 {
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let mut line =
+            line.unwrap_or_else(|e| die!("Read error: failed to read \"{}\" : {}", filename, e));
+        for e in &line.errors {
+            emitter.emit(e);
+        }
+        errors += line.errors.len();
+        line.tokens.truncate(left);
+        left -= line.tokens.len();
+        print!("{line}");
+    }
+    if errors > 0 {
+        emitter.summary(errors, 0);
+        std::process::exit(exit::LEX_ERROR);
+    }
+}
+
+/// Read, lex and parse the script, reporting diagnostics on failure.
+/// The lexed source is returned along with the parsed program,
+/// or the exit code for the kind of the errors.
+fn load_script(
+    script: &Script,
+    config: &config::Config,
+    encoding: Option<Encoding>,
+) -> Result<(parse::AST, lex::Lexed), i32> {
+    let source = read_source(&script.filename, encoding);
+    load_source(script, &script.lints.levels(config), source)
+        .map(|(parsed, lexed, _)| (parsed, lexed))
+}
+
+/// Lex and parse the source of the script, reporting diagnostics.
+/// The number of the diagnostics is returned as well.
+fn load_source(
+    script: &Script,
+    levels: &lint::Levels,
+    source: String,
+) -> Result<(parse::AST, lex::Lexed, usize), i32> {
+    let emitter = diag::Emitter::new(&script.filename);
+    let lexed = try_lex_source(&script.filename, source).ok_or(exit::LEX_ERROR)?;
+
+    log::info!("Parsing");
+    let start = std::time::Instant::now();
+    let result = parse::parse(&lexed, levels, &script.defines);
+    log::debug!("Parsing and checking took {:?}", start.elapsed());
+    let (parsed, diags) = match result {
+        Ok((parsed, warnings)) => (Some(parsed), warnings),
+        Err(diags) => (None, diags),
+    };
+    for d in &diags {
+        emitter.emit(d);
+    }
+    let errors = diags.iter().filter(|d| d.is_error()).count();
+    emitter.summary(errors, diags.len() - errors);
+    let Some(parsed) = parsed else {
+        let syntactic = diags
+            .iter()
+            .any(|d| d.is_error() && diag::Diagnostic::code(d).is_syntactic());
+        return Err(if syntactic {
+            exit::PARSE_ERROR
+        } else {
+            exit::SEMANTIC_ERROR
+        });
+    };
+    log::trace!("{} statements", parsed.stmts.len());
+    log::info!("Load completed");
+
+    Ok((parsed, lexed, diags.len()))
+}
+
+/// Load the program to run from the cache, or load the script and cache the program
+/// if it was parsed without any diagnostic
+fn load_program(
+    run: &RunOpts,
+    config: &config::Config,
+    encoding: Option<Encoding>,
+) -> Result<parse::AST, i32> {
+    if run.no_cache {
+        return load_script(&run.script, config, encoding).map(|(parsed, _)| parsed);
+    }
+    let script = &run.script;
+    let source = read_source(&script.filename, encoding);
+    let levels = script.lints.levels(config);
+    let entry = cache::entry(&source, &format!("{:?} {:?}", levels, script.defines));
+    if let Some((entry, parsed)) = entry
+        .as_ref()
+        .and_then(|entry| Some((entry, cache::load(entry)?)))
+    {
+        log::info!("Loaded from the cache {}", entry.display());
+        return Ok(parsed);
+    }
+    let (parsed, _, diags) = load_source(script, &levels, source)?;
+    if let (Some(entry), 0) = (&entry, diags) {
+        cache::store(entry, &parsed);
+    }
+    Ok(parsed)
+}
+
+fn parse_script(
+    script: &Script,
+    config: &config::Config,
+    encoding: Option<Encoding>,
+) -> (parse::AST, lex::Lexed) {
+    load_script(script, config, encoding).unwrap_or_else(|code| std::process::exit(code))
+}
 
-    eprintln!("Info: Parsing");
-    let parsed = parse::parse(lexed);
-    eprintln!("{:?}", parsed.stmts);
-    eprintln!("Info: Load completed");
+/// Load and run the script (returns the exit code if it failed to be loaded)
+fn run_script(
+    run: &RunOpts,
+    opt: &Opt,
+    stop: Option<std::sync::Arc<std::sync::atomic::AtomicBool>>,
+) -> Result<(), i32> {
+    let config = load_config(&run.script.filename);
+    let mut parsed = load_program(run, &config, opt.encoding)?;
+    let mut skip_to = run.scene.clone();
+    if let Some(scene) = &run.scene {
+        let titles: Vec<_> = parsed
+            .stmts
+            .iter()
+            .filter_map(|s| match s {
+                parse::Statement::Scene { title } => Some(title.as_str()),
+                _ => None,
+            })
+            .collect();
+        if !titles.contains(&scene.as_str()) {
+            eprintln!("Error: scene \"{scene}\" was not found");
+            eprintln!("Scenes in the script:");
+            for title in titles {
+                eprintln!("    {title}");
+            }
+            return Err(exit::FAILURE);
+        }
+    }
+    if let Some(entry) = &run.entry {
+        let subs: Vec<_> = parsed
+            .stmts
+            .iter()
+            .filter_map(|s| match s {
+                parse::Statement::Sub { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        if !subs.contains(&entry.as_str()) {
+            eprintln!("Error: sub \"{entry}\" was not found");
+            eprintln!("Subs in the script:");
+            for name in subs {
+                eprintln!("    {name}");
+            }
+            return Err(exit::FAILURE);
+        }
+    }
+    if let Some(cat) = run.load_catalog() {
+        i18n::translate(&mut parsed, &cat);
+        // scenes are still chosen by their titles in the script
+        if let Some(t) = skip_to.as_ref().and_then(|s| cat.get(s)) {
+            skip_to = Some(t.clone());
+        }
+    }
+    let result = runtime::run(
+        parsed,
+        &runtime::Config {
+            seed: opt.seed.or(config.seed),
+            fixed_time: opt.fixed_time,
+            no_wait: opt.no_wait || config.no_wait,
+            stop,
+            replay: None,
+            markdown: false,
+            skip_to,
+            defines: run.script.defines.clone(),
+            args: run.args.clone(),
+            entry: run.entry.clone(),
+            trace: run
+                .trace
+                .clone()
+                .map(|t| t.map_or(runtime::Output::Stderr, runtime::Output::File)),
+            log: if run.no_log {
+                None
+            } else {
+                Some(
+                    run.log_file
+                        .clone()
+                        .map_or(runtime::Output::Stderr, runtime::Output::File),
+                )
+            },
+            dev: run.dev,
+            dump_vars: run
+                .dump_vars
+                .clone()
+                .map(|d| d.map_or(runtime::Output::Stderr, runtime::Output::File)),
+            speaker_colors: config.speaker_colors().unwrap_or_default(),
+            name_width: config.name_width,
+            wait_cooldown: config.wait_cooldown,
+            reorder_bidi: config.reorder_bidi,
+            vertical: config.vertical,
+            max_call_depth: run.max_call_depth.or(config.max_call_depth),
+        },
+    );
+    match result {
+        Ok(0) => Ok(()),
+        Ok(status) => Err(status),
+        Err(e) => {
+            eprintln!("Runtime error: {e}");
+            Err(exit::RUNTIME_ERROR)
+        }
+    }
+}
 
-    runtime::run(parsed);
+/// Run the script, and run it again from the start whenever the file is modified
+fn run_watching(run: &RunOpts, opt: &Opt) -> ! {
+    let filename = &run.script.filename;
+    if filename == "-" {
+        die!("Error: cannot watch stdin");
+    }
+    let changed = watch::spawn(std::path::PathBuf::from(filename));
+    loop {
+        let _ = run_script(run, opt, Some(changed.clone()));
+        log::info!("Waiting for {} to be modified", filename);
+        watch::wait(&changed);
+        log::info!("Reloading");
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn main() {
+    let opt = Opt::from_args();
+    logger::init(opt.quiet, opt.verbose);
+    theme::Theme::init(opt.no_color, opt.high_contrast);
+
+    match &opt.cmd {
+        Command::Run(run) => {
+            ctrlc::set_handler(|| std::process::exit(exit::USER_QUIT))
+                .unwrap_or_else(|e| die!("Error: failed to set the handler of Ctrl-C : {}", e));
+            #[cfg(unix)]
+            runtime::handle_suspend()
+                .unwrap_or_else(|e| die!("Error: failed to set the handler of Ctrl-Z : {}", e));
+            runtime::set_panic_hook(run.script.filename.clone());
+            if run.watch {
+                run_watching(run, &opt);
+            }
+            if let Err(code) = run_script(run, &opt, None) {
+                std::process::exit(code);
+            }
+        }
+        Command::Check(script) => {
+            let _ = parse_script(script, &load_config(&script.filename), opt.encoding);
+        }
+        Command::Ast(script) => {
+            let (parsed, _) = parse_script(script, &load_config(&script.filename), opt.encoding);
+            println!("{:#?}", parsed.stmts);
+        }
+        Command::Disasm(script) => {
+            let (parsed, lexed) =
+                parse_script(script, &load_config(&script.filename), opt.encoding);
+            disasm::print(&parsed, &lexed);
+        }
+        Command::Graph(script) => {
+            let (parsed, _) = parse_script(script, &load_config(&script.filename), opt.encoding);
+            graph::write_dot(&mut std::io::stdout().lock(), &parsed)
+                .unwrap_or_else(|e| die!("Write error: {}", e));
+        }
+        Command::Stats(script) => {
+            let (parsed, lexed) =
+                parse_script(script, &load_config(&script.filename), opt.encoding);
+            print!("{}", stats::Stats::new(&parsed, &lexed));
+        }
+        Command::Export(Export::Renpy(script)) => {
+            let (parsed, _) = parse_script(script, &load_config(&script.filename), opt.encoding);
+            let (out, unsupported) = renpy::export(&parsed, &script.defines);
+            print!("{out}");
+            for s in unsupported {
+                log::warn!(
+                    "\"{}\" has no counterpart in Ren'Py and was left as a comment",
+                    s
+                );
+            }
+        }
+        Command::Export(Export::Md { script, replay }) => {
+            let config = load_config(&script.filename);
+            let (parsed, _) = parse_script(script, &config, opt.encoding);
+            let replay = replay
+                .as_ref()
+                .map(|path| read_source(path, None).lines().map(String::from).collect());
+            println!("# {}\n", i18n::file_key(&script.filename));
+            let result = runtime::run(
+                parsed,
+                &runtime::Config {
+                    seed: opt.seed.or(config.seed),
+                    fixed_time: opt.fixed_time,
+                    no_wait: true,
+                    stop: None,
+                    replay: Some(replay.unwrap_or_default()),
+                    markdown: true,
+                    skip_to: None,
+                    defines: script.defines.clone(),
+                    args: vec![],
+                    entry: None,
+                    trace: None,
+                    log: Some(runtime::Output::Stderr),
+                    dev: false,
+                    dump_vars: None,
+                    speaker_colors: config.speaker_colors().unwrap_or_default(),
+                    name_width: config.name_width,
+                    wait_cooldown: config.wait_cooldown,
+                    reorder_bidi: config.reorder_bidi,
+                    vertical: config.vertical,
+                    max_call_depth: config.max_call_depth,
+                },
+            );
+            if let Err(e) = result {
+                eprintln!("Runtime error: {e}");
+                std::process::exit(exit::RUNTIME_ERROR);
+            }
+        }
+        Command::Import(Import::Csv { filename }) => {
+            let script = dialogue::import_csv(&read_source(filename, opt.encoding))
+                .unwrap_or_else(|e| die!("Error: failed to import \"{}\" : {}", filename, e));
+            print!("{script}");
+        }
+        Command::Minify(script) => {
+            let (_, lexed) = parse_script(script, &load_config(&script.filename), opt.encoding);
+            print!("{}", minify::minify(&lexed));
+        }
+        Command::Tokens {
+            filename,
+            head: Some(head),
+        } => {
+            if opt.encoding.is_some_and(|e| e != Encoding::Utf8) {
+                die!("Error: --head reads only UTF-8 scripts");
+            }
+            print_head_tokens(filename, *head);
+        }
+        Command::Tokens { filename, .. } => {
+            print!("{}", lex_file(filename, opt.encoding));
+        }
+        Command::ExtractStrings { filename, format } => {
+            let lexed = lex_file(filename, opt.encoding);
+            let key = i18n::file_key(filename);
+            let entries = i18n::extract(&lexed, &key);
+            let stdout = std::io::stdout();
+            let mut lock = stdout.lock();
+            match format.as_str() {
+                "csv" => i18n::write_csv(&mut lock, &entries),
+                "text" => i18n::write_text(&mut lock, filename, &entries),
+                _ => i18n::write_po(&mut lock, &key, &entries),
+            }
+            .unwrap_or_else(|e| die!("Write error: {}", e));
+        }
+        Command::Explain { code } => {
+            let c = diag::Code::from_str(code)
+                .unwrap_or_else(|| die!("Error: \"{}\" is not a valid error code", code));
+            println!("{}", c.explain());
+        }
+        Command::Doctor => {
+            if !doctor::run(opt.no_color, opt.high_contrast) {
+                std::process::exit(exit::FAILURE);
+            }
+        }
+        Command::CacheClean => {
+            cache::clean().unwrap_or_else(|e| die!("Error: failed to remove the cache : {}", e));
+        }
+    }
 }
@@ -0,0 +1,135 @@
+//! Minification of scripts, to discourage casual spoiler-reading
+
+use crate::exprs::items::Fmt;
+use crate::lex::{Command, Item, Items, Keywords, Lexed};
+use crate::parse::{Piece, Template};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Return the `n`-th short name ("a", "b", ..., "z", "aa", "ab", ...)
+fn short_name(mut n: usize) -> String {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+    let mut s = Vec::new();
+    loop {
+        s.push(char::from(ALPHABET[n % 26]));
+        if n < 26 {
+            break;
+        }
+        n = n / 26 - 1;
+    }
+    s.iter().rev().collect()
+}
+
+/// Whether `s` would be lexed as something other than an identifier (or is a formatter)
+fn is_reserved(s: &str) -> bool {
+    ["die", "faces"].contains(&s)
+        || Fmt::from_str(s).is_some()
+        || Keywords::DISCRIMINANTS.iter().any(|k| k.as_str() == s)
+        || Command::DISCRIMINANTS.iter().any(|c| c.as_str() == s)
+}
+
+/// Short names given to identifiers in order of appearance
+#[derive(Default)]
+struct Names {
+    map: HashMap<String, String>,
+    next: usize,
+}
+
+impl Names {
+    fn get(&mut self, s: &str) -> &str {
+        let next = &mut self.next;
+        self.map.entry(s.to_owned()).or_insert_with(|| loop {
+            let name = short_name(*next);
+            *next += 1;
+            if !is_reserved(&name) {
+                break name;
+            }
+        })
+    }
+}
+
+const fn is_word(item: &Items) -> bool {
+    matches!(
+        item,
+        Items::Key(_) | Items::Cmd(_) | Items::Num(..) | Items::Ident(_)
+    )
+}
+
+/// Print the tokens of a script in one line,
+/// with the identifiers shortened and without comments and extra spaces.
+///
+/// Identifiers starting with `_` are left as they are because they are reserved.
+/// Placeholders in the template of Statusline are shortened along with the variables.
+pub fn minify(lexed: &Lexed) -> String {
+    let mut names = Names::default();
+    let mut out = String::new();
+
+    let mut prev: Option<&Items> = None;
+    let mut in_pragma = false;
+    for tk in &lexed.tokens {
+        let item = &tk.item;
+        let space = match prev {
+            // the rest of the version, lexed apart from its first number
+            Some(Items::Num(..)) if in_pragma => false,
+            // keywords and commands must be followed by a separator
+            Some(Items::Key(_) | Items::Cmd(_)) => *item != Items::Semi,
+            Some(Items::Ops(_)) => matches!(item, Items::Ops(_)),
+            Some(p) => is_word(p) && is_word(item),
+            None => false,
+        };
+        if space {
+            out.push(' ');
+        }
+
+        match item {
+            Items::Key(k) => out.push_str(k.as_str()),
+            Items::Cmd(c) => out.push_str(c.as_str()),
+            Items::Ops(o) => out.push_str(o.as_str()),
+            Items::Num(n, _) => write!(out, "{n}").unwrap(),
+            // formatters, speakers, points of hooks and pragmas are kept,
+            // and so are variables with their names
+            Items::Ident(s)
+                if in_pragma
+                    || s.starts_with('_')
+                    || Fmt::from_str(s).is_some()
+                    || matches!(prev, Some(Items::Cmd(Command::Say | Command::Hook))) =>
+            {
+                out.push_str(s);
+            }
+            Items::Ident(s) => out.push_str(names.get(s)),
+            Items::Str(s) if matches!(prev, Some(Items::Cmd(Command::Statusline))) => {
+                match Template::parse(s) {
+                    Ok(mut template) => {
+                        for piece in &mut template.0 {
+                            if let Piece::Var(name, _) = piece {
+                                if !name.starts_with('_') {
+                                    *name = names.get(name).to_owned();
+                                }
+                            }
+                        }
+                        write!(out, "\"{template}\"").unwrap();
+                    }
+                    // reported by the parser
+                    Err(_) => write!(out, "\"{s}\"").unwrap(),
+                }
+            }
+            Items::Str(s) => write!(out, "\"{s}\"").unwrap(),
+            Items::Semi => out.push(';'),
+            Items::Comma => out.push(','),
+            Items::Colon => out.push(':'),
+            Items::LParen => out.push('('),
+            Items::RParen => out.push(')'),
+            Items::LBracket => out.push('['),
+            Items::RBracket => out.push(']'),
+        }
+        match item {
+            Items::Cmd(Command::Pragma) => in_pragma = true,
+            Items::Semi => in_pragma = false,
+            _ => {}
+        }
+        prev = Some(item);
+    }
+
+    out.push('\n');
+    out
+}
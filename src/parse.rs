@@ -1,17 +1,24 @@
-use crate::die;
+use crate::diag::{Code, Severity};
 use crate::exprs::Expr;
 use crate::lex;
+use crate::lint::{Level, Levels, Lint};
+use crate::types::{Slot, Typed};
 
 mod exprs;
+mod macros;
+mod pragma;
+mod resolve;
 mod type_check;
 
 use exprs::TryFromTokens;
+use resolve::Resolve;
 use type_check::{TypeCheck, TypeError};
 
 enum ParseError {
     InvalidToken(lex::Token),
     EmptyExpr,
     NoPairParen { lparen: lex::Token },
+    NoPairBracket { lbracket: lex::Token },
     TrailingToken { from: lex::Token },
     TokenExhausted,
     TypeError(TypeError),
@@ -23,17 +30,152 @@ impl From<TypeError> for ParseError {
     }
 }
 
+/// An error found while parsing, with the location it occurred
 #[derive(Debug, Clone)]
+pub struct Error {
+    severity: Severity,
+    code: Code,
+    msg: String,
+    loc_info: lex::LocInfo,
+    notes: Vec<String>,
+}
+
+impl std::error::Error for Error {}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n{}", self.msg, self.loc_info)
+    }
+}
+
+impl crate::diag::Diagnostic for Error {
+    fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    fn code(&self) -> Code {
+        self.code
+    }
+
+    fn message(&self) -> String {
+        self.msg.clone()
+    }
+
+    fn loc_info(&self) -> &lex::LocInfo {
+        &self.loc_info
+    }
+
+    fn notes(&self) -> &[String] {
+        &self.notes
+    }
+}
+
+impl Error {
+    fn new(code: Code, msg: impl Into<String>, loc: &lex::Location, lexed: &lex::Lexed) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            msg: msg.into(),
+            loc_info: lexed.generate_loc_info(loc),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Create an error underlining `span`
+    fn spanned(code: Code, msg: impl Into<String>, span: &lex::Span, lexed: &lex::Lexed) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            msg: msg.into(),
+            loc_info: lexed.generate_span_info(span),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Create an error pointing at the `i`-th token
+    /// (or just after the last token if tokens have been exhausted)
+    fn at(code: Code, msg: impl Into<String>, i: usize, lexed: &lex::Lexed) -> Self {
+        match lexed.tokens.get(i) {
+            Some(tk) => Self::spanned(
+                code,
+                msg,
+                &lex::Span {
+                    from: tk.loc.clone(),
+                    to: tk.next_col_loc(),
+                },
+                lexed,
+            ),
+            None => Self::new(
+                code,
+                msg,
+                &lexed.tokens.last().unwrap().next_col_loc(),
+                lexed,
+            ),
+        }
+    }
+
+    /// Attach a note which helps to fix the error
+    fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Create a diagnostic for `lint` at the `i`-th token according to its level
+    /// (`None` if the lint is allowed)
+    fn lint(
+        levels: &Levels,
+        lint: Lint,
+        msg: impl Into<String>,
+        i: usize,
+        lexed: &lex::Lexed,
+    ) -> Option<Self> {
+        let level = levels.get(lint);
+        let severity = match level {
+            Level::Allow => return None,
+            Level::Warn => Severity::Warning,
+            Level::Deny => Severity::Error,
+        };
+        let mut e = Self::at(lint.code(), msg, i, lexed).with_note(format!(
+            "lint `{}` is set to `{}`",
+            lint.as_str(),
+            level.as_str()
+        ));
+        e.severity = severity;
+        Some(e)
+    }
+
+    pub const fn is_error(&self) -> bool {
+        matches!(self.severity, Severity::Error)
+    }
+
+    fn immutable(name_idx: usize, lexed: &lex::Lexed) -> Self {
+        Self::at(Code::E009, "Variable is immutable", name_idx, lexed)
+            .with_note("declare the variable with `asmut` to modify it")
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum Statement {
     Print {
         args: Vec<Expr>,
+        /// Put between the args
+        sep: String,
+        /// Don't break the line (nor wait), so that the next Print continues it
+        nobreak: bool,
+        align: Align,
+        /// Name shown before the text (by Say)
+        speaker: Option<String>,
     },
     Sub {
         name: String,
+        slot: Slot,
         offset_to_end: usize,
     },
     Call {
         name: String,
+        slot: Slot,
+        /// Variable which receives the value returned by the sub
+        into: Option<(String, Slot)>,
     },
     While {
         cond: Expr,
@@ -41,11 +183,18 @@ pub enum Statement {
     },
     Let {
         name: String,
+        slot: Slot,
         init: Expr,
         is_mut: bool,
+        /// Declared at the top level by Global, so that it is left after the block
+        /// (reaching it again replaces the variable)
+        is_global: bool,
     },
     Modify {
         name: String,
+        slot: Slot,
+        /// Indices of the element to modify, one for each level of arrays (empty for the whole)
+        indices: Vec<Expr>,
         expr: Expr,
     },
     If {
@@ -56,37 +205,244 @@ pub enum Statement {
         cond: Expr,
         offset_to_next: usize,
     },
+    /// Opens a block of arms, with the value its Cases are compared with
+    /// (the arm taken when none matches is an Else)
+    Switch {
+        subject: Expr,
+        offset_to_next: usize,
+    },
+    Case {
+        value: Expr,
+        offset_to_next: usize,
+    },
     Else {
         offset_to_end: usize,
     },
+    /// Beginning of a block whose runtime errors go to the Recover arm
+    Try {
+        offset_to_recover: usize,
+    },
+    Recover {
+        offset_to_end: usize,
+    },
     End,
     Input {
         prompt: Option<String>,
         name: String,
+        slot: Slot,
         as_num: bool,
+        /// Positions of the characters to accept (`#`: digit, `?`: letter, `*`: either)
+        /// and literals inserted between them
+        mask: Option<String>,
+        /// Conditions the value must meet (asked again until all of them are met)
+        valid: Vec<Expr>,
+        /// Shown when the value doesn't meet the conditions
+        invalid_msg: Option<String>,
     },
     Roll {
         count: Expr,
         face: Expr,
         name: String,
+        slot: Slot,
+        /// Don't show the dice even if waits are enabled
+        silent: bool,
+    },
+    /// End the script, with the exit status of the process (0 if omitted)
+    Halt {
+        code: Option<Expr>,
     },
-    Halt,
     Ill,
     Break,
+    /// Leave the innermost sub, with the value for `call ... into`
+    Return {
+        value: Option<Expr>,
+    },
+    Scene {
+        title: String,
+    },
+    Breakpoint,
+    /// Like Print, but for developers
+    Log {
+        args: Vec<Expr>,
+    },
+    /// Empty lines (1 if count is omitted)
+    Newline {
+        count: Option<Expr>,
+    },
+    /// Status line kept on the screen until replaced (removed if the template is empty)
+    Status {
+        template: Template,
+    },
+    /// Save the state to go back to by Restore
+    Checkpoint,
+    /// Go back to the state saved by the last Checkpoint
+    Restore,
+    /// Call the sub at the first wait point after `ms` milliseconds
+    After {
+        ms: Expr,
+        name: String,
+        slot: Slot,
+    },
+    /// Call the sub when `key` is entered while waiting for Enter
+    OnKey {
+        key: String,
+        name: String,
+        slot: Slot,
+    },
+    /// Call the sub when a runtime error occurs, instead of aborting
+    OnError {
+        name: String,
+        slot: Slot,
+    },
+    /// Call the sub around every Print
+    Hook {
+        point: PrintHook,
+        name: String,
+        slot: Slot,
+    },
 }
 
-#[derive(Debug, Clone)]
+/// Alignment of the line printed by Print
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Left => "left",
+            Self::Center => "center",
+            Self::Right => "right",
+        }
+    }
+}
+
+/// When a sub registered by Hook is called
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PrintHook {
+    /// Before the text is shown
+    Before,
+    /// After the text is shown and Enter is pressed
+    After,
+}
+
+impl PrintHook {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Before => "before_print",
+            Self::After => "after_print",
+        }
+    }
+}
+
+/// Part of a template
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Piece {
+    Text(String),
+    /// `{name}`, replaced with the value of the variable
+    Var(String, Slot),
+}
+
+/// Text with variables embedded as `{name}` (`{{` and `}}` are literal braces)
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Template(pub Vec<Piece>);
+
+impl Template {
+    /// Parse the text of a template, or return why it is malformed
+    pub fn parse(s: &str) -> Result<Self, &'static str> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) if c != '{' && lex::is_ident_char(c) => name.push(c),
+                            Some(_) => return Err("Placeholder must be a name of variable"),
+                            None => return Err("Placeholder is not closed by `}`"),
+                        }
+                    }
+                    if name.is_empty() {
+                        return Err("Placeholder must be a name of variable");
+                    }
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(Piece::Var(lex::nfc(&name), Slot::UNRESOLVED));
+                }
+                '}' => return Err("`}` without `{` (write `}}` for the literal brace)"),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Self(pieces))
+    }
+
+    /// Names of the variables in order of appearance
+    pub fn vars(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().filter_map(|p| match p {
+            Piece::Var(name, _) => Some(name.as_str()),
+            Piece::Text(_) => None,
+        })
+    }
+}
+
+impl std::fmt::Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for piece in &self.0 {
+            match piece {
+                Piece::Text(s) => write!(f, "{}", s.replace('{', "{{").replace('}', "}}"))?,
+                Piece::Var(name, _) => write!(f, "{{{name}}}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct AST {
     pub stmts: Vec<Statement>,
+    /// Row of the source where each statement starts (0 for the reserved statement)
+    pub rows: Vec<usize>,
+    /// Names of the variables by their slots, for diagnostics and the debugger
+    pub names: Names,
+    /// Variables declared by Global, with the values they hold from the start
+    /// until their statements are reached
+    pub globals: Vec<(Slot, Typed)>,
+}
+
+/// Names of the variables declared in each block in order, by the index of the opening statement
+pub type Names = std::collections::BTreeMap<usize, Vec<String>>;
+
+/// The variables defined on the command line, declared after the internal ones
+/// (a name defined again replaces the earlier one)
+pub fn defines_in_effect(defines: &[(String, Typed)]) -> impl Iterator<Item = &(String, Typed)> {
+    defines
+        .iter()
+        .enumerate()
+        .filter(move |(k, (name, _))| defines[k + 1..].iter().all(|(n, _)| n != name))
+        .map(|(_, define)| define)
 }
 
 macro_rules! die_cont {
-    ($msg: expr, $i: expr, $lexed: ident) => {
-        die!(
-            "Error: {}\n{}",
-            $msg,
-            $lexed.generate_loc_info(&$lexed.tokens[$i].loc)
-        )
+    ($code: expr, $msg: expr, $i: expr, $lexed: ident) => {
+        return Err(Error::at($code, $msg, $i, &$lexed))
     };
 }
 
@@ -94,16 +450,8 @@ macro_rules! die_cont {
 macro_rules! expects {
     ($msg: expr, $($pat: pat)|+, $i: ident, $lexed: ident) => {
         {
-            if $lexed.tokens.len() <= $i {
-                // tokens has been exhausted
-                let last_token = &$lexed.tokens.last().unwrap();
-                die!(
-                    "Error: {}\n{}",
-                    $msg,
-                    $lexed.generate_loc_info(&last_token.next_col_loc())
-                );
-            } else if !matches!(&$lexed.tokens[$i].item, $($pat)|+) {
-                die_cont!($msg, $i, $lexed);
+            if $lexed.tokens.len() <= $i || !matches!(&$lexed.tokens[$i].item, $($pat)|+) {
+                die_cont!(Code::E003, $msg, $i, $lexed);
             }
             $i += 1;
         }
@@ -118,66 +466,116 @@ macro_rules! expects_semi {
 }
 
 fn parse_expr_from_tokens(tks: &[lex::Token], stack: &ScopeStack) -> Result<Expr, ParseError> {
-    if tks.is_empty() {
-        return Err(ParseError::EmptyExpr);
+    let span = span_of(tks).ok_or(ParseError::EmptyExpr)?;
+
+    let mut it = tks.iter().peekable();
+    let content = crate::exprs::items::Rel::try_from_tokens(&mut it)?;
+
+    if let Some(tk) = it.next() {
+        return Err(ParseError::TrailingToken { from: tk.clone() });
     }
 
-    let expr = Expr::try_from_tokens(&mut tks.iter().peekable())?;
+    let mut expr = Expr {
+        content: Box::new(content),
+        span,
+    };
 
     let _ = expr.check_type(stack)?;
+    expr.resolve(stack);
 
     Ok(expr)
 }
 
-fn die_by_expr_parse_error(e: ParseError, i: usize, lexed: &lex::Lexed) -> ! {
+/// Return the span covering all of `tks`
+fn span_of(tks: &[lex::Token]) -> Option<lex::Span> {
+    Some(lex::Span {
+        from: tks.first()?.loc.clone(),
+        to: tks.last()?.next_col_loc(),
+    })
+}
+
+/// Convert `ParseError` into `Error`,
+/// pointing at `span` of the expression (or the `i`-th token if the expression is empty)
+fn expr_parse_error(
+    e: ParseError,
+    span: Option<&lex::Span>,
+    i: usize,
+    lexed: &lex::Lexed,
+) -> Error {
+    let at_expr = |code: Code, msg: String| match span {
+        Some(span) => Error::spanned(code, msg, span, lexed),
+        None => Error::at(code, msg, i, lexed),
+    };
     match e {
-        ParseError::EmptyExpr => {
-            die_cont!("Expr is empty", i, lexed);
-        }
-        ParseError::InvalidToken(tk) => {
-            die!(
-                "Error: {}\n{}",
-                "Failed to parse expr because of this token",
-                lexed.generate_loc_info(&tk.loc)
-            );
-        }
+        ParseError::EmptyExpr => Error::at(Code::E004, "Expr is empty", i, lexed),
+        ParseError::InvalidToken(tk) => Error::new(
+            Code::E004,
+            "Failed to parse expr because of this token",
+            &tk.loc,
+            lexed,
+        ),
         ParseError::NoPairParen { lparen: tk } => {
-            die!(
-                "Error: {}\n{}",
-                "Paren doesn't have its pair",
-                lexed.generate_loc_info(&tk.loc)
-            );
+            Error::new(Code::E004, "Paren doesn't have its pair", &tk.loc, lexed)
         }
-        ParseError::TrailingToken { from: tk } => {
-            die!(
-                "Error: {}\n{}",
-                "Trailing token from here",
-                lexed.generate_loc_info(&tk.loc)
-            );
+        ParseError::NoPairBracket { lbracket: tk } => {
+            Error::new(Code::E004, "Bracket doesn't have its pair", &tk.loc, lexed)
         }
-        ParseError::TokenExhausted => {
-            die_cont!("Expression abruptly ended", i, lexed);
+        ParseError::TrailingToken { from: tk } => {
+            Error::new(Code::E004, "Trailing token from here", &tk.loc, lexed)
         }
+        ParseError::TokenExhausted => at_expr(Code::E004, "Expression abruptly ended".to_owned()),
         ParseError::TypeError(te) => match te {
             TypeError::VarNotFound(name) => {
-                die_cont!(format!("Variable {} was not found", name), i, lexed);
+                at_expr(Code::E005, format!("Variable {name} was not found"))
             }
-            TypeError::UnaryUndefined(ty) => {
-                //TODO: show operator (such as '<=')
-                die_cont!(
-                    format!("Unary operator is not defined for {}", ty),
-                    i,
-                    lexed
-                );
+            TypeError::UnaryUndefined { op, ty, span } => Error::spanned(
+                Code::E008,
+                format!("Unary operator `{op}` is not defined for {ty}"),
+                &span,
+                lexed,
+            ),
+            TypeError::BinaryUndefined { op, l, r, span } => Error::spanned(
+                Code::E008,
+                format!("Binary operator `{op}` is not defined for {l} and {r}"),
+                &span,
+                lexed,
+            ),
+            TypeError::EmptyArray { span } => {
+                Error::spanned(Code::E008, "Empty array has no type", &span, lexed)
+                    .with_note("arrays take the type of their elements, so give at least one")
             }
-            TypeError::BinaryUndefined(l, r) => {
-                //TODO: show operator (such as '-' or '+')
-                die_cont!(
-                    format!("Unary operator is not defined for {} and {}", l, r),
-                    i,
-                    lexed
-                );
+            TypeError::SubInArray { span } => {
+                Error::spanned(Code::E008, "Array cannot hold subroutines", &span, lexed)
             }
+            TypeError::ElementsDiffer { first, found, span } => Error::spanned(
+                Code::E008,
+                format!(
+                    "Elements of an array must be of one type, but {first} and {found} were given"
+                ),
+                &span,
+                lexed,
+            ),
+            TypeError::IndexUndefined { ty, index, span } => Error::spanned(
+                Code::E008,
+                format!("Cannot index {ty} with {index}"),
+                &span,
+                lexed,
+            ),
+            TypeError::FmtUndefined { fmt, found, span } => Error::spanned(
+                Code::E008,
+                format!(
+                    "`{}` takes {}, but ({}) was given",
+                    fmt.as_str(),
+                    type_check::signature(fmt),
+                    found
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                &span,
+                lexed,
+            ),
         },
     }
 }
@@ -188,28 +586,49 @@ macro_rules! parse_expr {
     ($($end_pat: pat)|+, $i: ident, $tks: ident, $lexed: ident, $stack: ident) => {
         {
             let mut j = $i;
-            while j < $tks.len()
-                && !matches!(
-                    $tks[j].item,
-                    $($end_pat)|+
-                )
-            {
+            // the end inside parentheses (e.g. a comma between args) doesn't count,
+            // but a semicolon always ends the expression
+            let mut depth = 0_usize;
+            while j < $tks.len() {
+                let item = &$tks[j].item;
+                if *item == lex::Items::Semi || (depth == 0 && matches!(item, $($end_pat)|+)) {
+                    break;
+                }
+                match item {
+                    lex::Items::LParen | lex::Items::LBracket => depth += 1,
+                    lex::Items::RParen | lex::Items::RBracket => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
                 j += 1;
             }
-            let expr = parse_expr_from_tokens(&$tks[$i..j], &$stack).unwrap_or_else(
-                |e| die_by_expr_parse_error(e, $i, &$lexed)
-            );
+            let expr = parse_expr_from_tokens(&$tks[$i..j], &$stack)
+                .map_err(|e| expr_parse_error(e, span_of(&$tks[$i..j]).as_ref(), $i, &$lexed))?;
             $i = j;
             expr
         }
     }
 }
 
+// parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, { ... }) where the block evaluates to `Statement`.
+// On error, the error is recorded and `i` is moved past the next semicolon.
 macro_rules! parse_stmt {
-    ($i: ident, $stmts: ident, $proc: block) => {{
+    ($i: ident, $stmts: ident, $rows: ident, $errors: ident, $lexed: ident, $stack: ident, $proc: block) => {{
+        let start = $i;
+        let depth = $stack.depth();
         $i += 1;
-        let inst_obj = $proc;
-        $stmts.push(inst_obj);
+        #[allow(clippy::redundant_closure_call)]
+        let inst_obj: Result<Statement, Error> = (|| Ok($proc))();
+        match inst_obj {
+            Ok(inst_obj) => $stmts.push(inst_obj),
+            Err(e) => {
+                $errors.push(e);
+                // keep the block structure so that the following statements can be checked
+                $stack.recover(depth, &$lexed.tokens[start].item, $stmts.len(), start);
+                $i = skip_stmt(start, &$lexed.tokens);
+                $stmts.push(Statement::Ill);
+            }
+        }
+        $rows.push($lexed.tokens[start].loc.row);
     }};
 }
 
@@ -218,37 +637,61 @@ macro_rules! expects_type {
         match $expr.check_type(&$stack) {
             Ok(t) => {
                 if t != $ty {
-                    die_cont!(format!("Expected {}, found {}", $ty, t), $i, $lexed)
+                    return Err(Error::spanned(
+                        Code::E008,
+                        format!("Expected {}, found {}", $ty, t),
+                        &$expr.span,
+                        &$lexed,
+                    ));
                 }
             }
             Err(e) => {
-                die_by_expr_parse_error(e.into(), $i, &$lexed);
+                return Err(expr_parse_error(e.into(), Some(&$expr.span), $i, &$lexed));
             }
         }
     };
 }
 
+/// Return the index of the token next to the first semicolon from `start`
+fn skip_stmt(start: usize, tks: &[lex::Token]) -> usize {
+    let mut i = start;
+    while i < tks.len() && tks[i].item != lex::Items::Semi {
+        i += 1;
+    }
+    i + 1
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum Type {
     Bool,
     Num,
     Str,
+    /// Array of the elements of the type
+    Array(Box<Self>),
     Sub,
 }
 
-impl std::fmt::Display for Type {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        write!(f, "{}", self.typename())
+impl Type {
+    /// Value held by a global before its statement is reached (None for Sub, which has none)
+    const fn zero(&self) -> Option<Typed> {
+        match self {
+            Self::Bool => Some(Typed::Bool(false)),
+            Self::Num => Some(Typed::Num(0)),
+            Self::Str => Some(Typed::Str(String::new())),
+            Self::Array(_) => Some(Typed::Array(Vec::new())),
+            Self::Sub => None,
+        }
     }
 }
 
-impl Type {
-    const fn typename(&self) -> &str {
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
-            Self::Bool => "Bool",
-            Self::Num => "Num",
-            Self::Str => "Str",
-            Self::Sub => "Sub",
+            Self::Bool => write!(f, "Bool"),
+            Self::Num => write!(f, "Num"),
+            Self::Str => write!(f, "Str"),
+            Self::Array(elem) => write!(f, "[{elem}]"),
+            Self::Sub => write!(f, "Sub"),
         }
     }
 }
@@ -257,6 +700,7 @@ impl Type {
 struct TypeInfo {
     ty: Type,
     is_mut: bool,
+    slot: Slot,
 }
 
 type VarMap = std::collections::HashMap<String, TypeInfo>;
@@ -264,52 +708,68 @@ type VarMap = std::collections::HashMap<String, TypeInfo>;
 struct Scope {
     map: VarMap,
     ret_idx: usize,
+    /// Index of the token which opened this scope
+    opener: usize,
+    /// Index of the statement which opens the block at runtime
+    /// (the If for all the arms of an If, 0 for the top level)
+    id: usize,
 }
 
 impl Scope {
-    fn new(ret_idx: usize) -> Self {
+    fn new(ret_idx: usize, opener: usize, id: usize) -> Self {
         Self {
             map: VarMap::new(),
             ret_idx,
+            opener,
+            id,
         }
     }
 
-    fn add_var(&mut self, name: String, var: TypeInfo) -> bool {
-        let exists = self.map.contains_key(&name);
-        if !exists {
-            // variable with the same name already exists
-            self.map.insert(name, var);
-        }
-
-        !exists
-    }
-
     fn get_type_info(&self, name: &str) -> Option<&TypeInfo> {
         self.map.get(name)
     }
 }
 
+/// The internal variables, at the first slots of the top level
+pub const INTERNALS: [&str; 6] = [
+    "_wait",
+    "_vertical",
+    "_argc",
+    "_error_message",
+    "_error_line",
+    "_print_length",
+];
+
 struct ScopeStack {
     scopes: Vec<Scope>,
+    /// Names of the variables declared in each block, by the ids of the scopes
+    names: Names,
 }
 
 impl ScopeStack {
     fn new() -> Self {
-        let mut internals = Scope::new(0);
-        internals.add_var(
-            String::from("_wait"),
-            TypeInfo {
-                ty: Type::Bool,
-                is_mut: true,
-            },
-        );
-        Self {
-            scopes: vec![internals],
+        let mut stack = Self {
+            scopes: vec![Scope::new(0, 0, 0)],
+            names: Names::new(),
+        };
+        for name in INTERNALS {
+            let (ty, is_mut) = match name {
+                "_wait" | "_vertical" => (Type::Bool, true),
+                "_error_message" => (Type::Str, false),
+                _ => (Type::Num, false),
+            };
+            stack.add_var(String::from(name), ty, is_mut);
         }
+        stack
     }
 
-    fn push(&mut self, ret_idx: usize) {
-        self.scopes.push(Scope::new(ret_idx))
+    fn push(&mut self, ret_idx: usize, opener: usize, id: usize) {
+        self.scopes.push(Scope::new(ret_idx, opener, id));
+    }
+
+    /// Id of the innermost scope
+    fn top_id(&self) -> usize {
+        self.scopes.last().unwrap().id
     }
 
     fn pop(&mut self) -> Option<usize> {
@@ -322,12 +782,66 @@ impl ScopeStack {
         }
     }
 
-    fn get_top_mut(&mut self) -> &mut Scope {
-        self.scopes.last_mut().unwrap()
+    /// Declare a variable in the innermost scope, giving it the next slot of the block
+    /// (None if the name is already declared there)
+    fn add_var(&mut self, name: String, ty: Type, is_mut: bool) -> Option<Slot> {
+        let top = self.scopes.len() - 1;
+        self.add_var_at(top, name, ty, is_mut)
+    }
+
+    /// Declare a variable at the top level, from any block
+    fn add_global(&mut self, name: String, ty: Type, is_mut: bool) -> Option<Slot> {
+        self.add_var_at(0, name, ty, is_mut)
+    }
+
+    fn add_var_at(&mut self, depth: usize, name: String, ty: Type, is_mut: bool) -> Option<Slot> {
+        let top = &mut self.scopes[depth];
+        if top.map.contains_key(&name) {
+            return None;
+        }
+        let names = self.names.entry(top.id).or_default();
+        let slot = Slot {
+            scope: top.id,
+            index: names.len(),
+        };
+        names.push(name.clone());
+        top.map.insert(name, TypeInfo { ty, is_mut, slot });
+        Some(slot)
+    }
+
+    const fn depth(&self) -> usize {
+        self.scopes.len()
     }
 
-    fn add_var(&mut self, name: String, info: TypeInfo) -> bool {
-        self.get_top_mut().add_var(name, info)
+    /// Restore the depth of the stack to the one expected after a statement starting with `item`,
+    /// which failed to be parsed.
+    /// Scopes pushed here return to `ill_idx`, the index of the placeholder for the statement.
+    fn recover(&mut self, depth: usize, item: &lex::Items, ill_idx: usize, opener: usize) {
+        use lex::{Command, Items};
+        let expected = match item {
+            Items::Cmd(
+                Command::Sub | Command::While | Command::If | Command::Switch | Command::Try,
+            ) => depth + 1,
+            Items::Cmd(Command::End) => depth - 1,
+            _ => depth,
+        };
+        while self.depth() > expected && self.pop().is_some() {}
+        while self.depth() < expected {
+            self.push(ill_idx, opener, ill_idx);
+        }
+    }
+
+    /// Statement opening the innermost sub, which Return leaves
+    /// (Ill if a block inside it failed to be parsed)
+    fn enclosing_sub<'s>(&self, stmts: &'s [Statement]) -> Option<&'s Statement> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|sc| match stmts.get(sc.id) {
+                Some(stmt @ Statement::Sub { .. }) => Some(stmt),
+                Some(stmt @ Statement::Ill) if sc.id != 0 => Some(stmt),
+                _ => None,
+            })
     }
 
     fn get_type_info(&self, name: &str) -> Option<&TypeInfo> {
@@ -340,11 +854,42 @@ impl ScopeStack {
     }
 }
 
-pub fn parse(lexed: crate::lex::Lexed) -> AST {
+/// Parse `lexed` into `AST`.
+/// On success, lint warnings are returned along with `AST`;
+/// on failure, all the diagnostics (including warnings) are returned.
+#[allow(clippy::too_many_lines)]
+pub fn parse(
+    lexed: &crate::lex::Lexed,
+    lints: &Levels,
+    defines: &[(String, Typed)],
+) -> Result<(AST, Vec<Error>), Vec<Error>> {
     use lex::{Items, Keywords};
 
+    pragma::check(lexed)?;
+    let expanded = macros::expand(lexed)?;
+    let lexed = &*expanded;
+
     let mut stmts = vec![Statement::Ill];
+    let mut rows = vec![0];
     let mut scope_stack = ScopeStack::new();
+    for (name, val) in defines_in_effect(defines) {
+        let ty = match val {
+            Typed::Num(_) => Type::Num,
+            Typed::Bool(_) => Type::Bool,
+            Typed::Str(_) => Type::Str,
+            Typed::Sub(_) => Type::Sub,
+            Typed::Array(_) => unreachable!("arrays are not defined on the command line"),
+        };
+        scope_stack.add_var(name.clone(), ty, false);
+    }
+    let mut errors = Vec::new();
+    let mut globals = Vec::new();
+    // for unused-sub
+    let mut declared_subs = Vec::new();
+    let mut called_subs = std::collections::HashSet::new();
+    // type of the values returned by each sub, and the calls taking them
+    let mut sub_returns = std::collections::HashMap::new();
+    let mut calls_into = Vec::new();
 
     let tks = &lexed.tokens;
 
@@ -352,216 +897,445 @@ pub fn parse(lexed: crate::lex::Lexed) -> AST {
     while i < tks.len() {
         if let Items::Cmd(inst) = &tks[i].item {
             match inst {
-                lex::Command::Print => parse_stmt!(i, stmts, {
-                    // "Print" (expr {"," expr}) ";"
-                    let mut args = Vec::new();
-                    while i < tks.len() {
-                        match &tks[i].item {
-                            Items::Semi => break,
-                            Items::Comma => {
-                                i += 1;
-                            }
-                            _ => {
-                                let expr = parse_expr!(
-                                    Items::Comma | Items::Semi,
-                                    i,
-                                    tks,
-                                    lexed,
-                                    scope_stack
-                                );
-
-                                match expr.check_type(&scope_stack) {
-                                    Ok(ty) => {
-                                        if ty == Type::Sub {
-                                            die_cont!(
-                                                "Value of type Sub cannot be printed",
+                lex::Command::Print
+                | lex::Command::Say
+                | lex::Command::Log
+                | lex::Command::Center
+                | lex::Command::Right => {
+                    parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                        // ("Print" | "Log" | "Center" | "Right") (expr {"," expr}) ";"
+                        // "Say" (name | string) ":" (expr {"," expr}) ";"
+                        // ("Sep" string) ("NoBreak") may precede ";" of Print and Say
+                        let speaker = if *inst == lex::Command::Say {
+                            let speaker = match tks.get(i).map(|tk| &tk.item) {
+                                Some(Items::Ident(s) | Items::Str(s)) => s.clone(),
+                                _ => {
+                                    die_cont!(Code::E003, "Name of the speaker expected", i, lexed)
+                                }
+                            };
+                            i += 1;
+                            expects!("\":\" expected", Items::Colon, i, lexed);
+                            Some(speaker)
+                        } else {
+                            None
+                        };
+                        let mut args = Vec::new();
+                        while i < tks.len() {
+                            match &tks[i].item {
+                                Items::Semi | Items::Key(Keywords::Sep | Keywords::NoBreak) => {
+                                    break
+                                }
+                                Items::Comma => {
+                                    i += 1;
+                                }
+                                _ => {
+                                    let expr = parse_expr!(
+                                        Items::Comma
+                                            | Items::Semi
+                                            | Items::Key(Keywords::Sep | Keywords::NoBreak),
+                                        i,
+                                        tks,
+                                        lexed,
+                                        scope_stack
+                                    );
+
+                                    match expr.check_type(&scope_stack) {
+                                        Ok(ty) => {
+                                            if ty == Type::Sub {
+                                                return Err(Error::spanned(
+                                                    Code::E008,
+                                                    "Value of type Sub cannot be printed",
+                                                    &expr.span,
+                                                    lexed,
+                                                ));
+                                            }
+                                            args.push(expr);
+                                        }
+                                        Err(e) => {
+                                            return Err(expr_parse_error(
+                                                e.into(),
+                                                Some(&expr.span),
                                                 i,
-                                                lexed
-                                            )
+                                                lexed,
+                                            ))
                                         }
-                                        args.push(expr);
                                     }
-                                    Err(e) => die_by_expr_parse_error(e.into(), i, &lexed),
                                 }
                             }
                         }
-                    }
-                    expects_semi!(i, lexed);
-                    Statement::Print { args }
-                }),
+                        if *inst == lex::Command::Log {
+                            expects_semi!(i, lexed);
+                            Statement::Log { args }
+                        } else if matches!(inst, lex::Command::Center | lex::Command::Right) {
+                            expects_semi!(i, lexed);
+                            Statement::Print {
+                                args,
+                                sep: " ".to_owned(),
+                                nobreak: false,
+                                align: if *inst == lex::Command::Center {
+                                    Align::Center
+                                } else {
+                                    Align::Right
+                                },
+                                speaker,
+                            }
+                        } else {
+                            let sep = if tks.get(i).map(|tk| &tk.item)
+                                == Some(&Items::Key(Keywords::Sep))
+                            {
+                                i += 1;
+                                if let Some(Items::Str(s)) = tks.get(i).map(|tk| &tk.item) {
+                                    i += 1;
+                                    s.clone()
+                                } else {
+                                    die_cont!(
+                                        Code::E003,
+                                        "String of the separator expected",
+                                        i,
+                                        lexed
+                                    );
+                                }
+                            } else {
+                                " ".to_owned()
+                            };
+                            let nobreak = tks.get(i).map(|tk| &tk.item)
+                                == Some(&Items::Key(Keywords::NoBreak));
+                            if nobreak {
+                                i += 1;
+                            }
+                            expects_semi!(i, lexed);
+                            Statement::Print {
+                                args,
+                                sep,
+                                nobreak,
+                                align: Align::Left,
+                                speaker,
+                            }
+                        }
+                    });
+                }
 
-                lex::Command::Sub => parse_stmt!(i, stmts, {
+                lex::Command::Sub => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
                     // "Sub" name ";"
 
-                    if let Items::Ident(name) = &tks[i].item {
+                    if let Some(Items::Ident(name)) = tks.get(i).map(|tk| &tk.item) {
+                        let name_idx = i;
                         i += 1;
                         expects_semi!(i, lexed);
 
                         // add this sub to var table
-                        let success = scope_stack.add_var(
-                            name.clone(),
-                            TypeInfo {
-                                ty: Type::Sub,
-                                is_mut: false,
-                            },
-                        );
-
-                        if !success {
-                            die_cont!("Conflicting subroutine name", i, lexed);
-                        }
+                        let Some(slot) = scope_stack.add_var(name.clone(), Type::Sub, false) else {
+                            die_cont!(Code::E006, "Conflicting subroutine name", name_idx, lexed);
+                        };
+                        declared_subs.push((name.clone(), name_idx));
 
                         // create new scope
-                        scope_stack.push(stmts.len());
+                        scope_stack.push(stmts.len(), name_idx - 1, stmts.len());
 
                         Statement::Sub {
                             name: name.clone(),
+                            slot,
                             offset_to_end: 0,
                         }
                     } else {
-                        die_cont!("Expected subroutine name", i, lexed)
+                        die_cont!(Code::E003, "Expected subroutine name", i, lexed)
                     }
                 }),
 
-                lex::Command::Call => parse_stmt!(i, stmts, {
-                    // "Call" name ";"
-                    if let Items::Ident(name) = &tks[i].item {
+                lex::Command::Call => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Call" name ("Into" name) ";"
+                    if let Some(Items::Ident(name)) = tks.get(i).map(|tk| &tk.item) {
+                        let name_idx = i;
                         i += 1;
+
+                        let slot = match scope_stack.get_type_info(name) {
+                            Some(info) if info.ty == Type::Sub => info.slot,
+                            _ => die_cont!(
+                                Code::E005,
+                                format!("Subroutine \"{}\" was not found", name),
+                                name_idx,
+                                lexed
+                            ),
+                        };
+
+                        let into = if tks.get(i).map(|tk| &tk.item)
+                            == Some(&Items::Key(Keywords::Into))
+                        {
+                            i += 1;
+                            let var_idx = i;
+                            let var = if let Some(Items::Ident(n)) = tks.get(i).map(|tk| &tk.item) {
+                                i += 1;
+                                n.clone()
+                            } else {
+                                die_cont!(Code::E003, "Ident expected", i, lexed)
+                            };
+                            let Some(info) = scope_stack.get_type_info(&var) else {
+                                die_cont!(
+                                    Code::E005,
+                                    format!("Variable \"{}\" was not found", var),
+                                    var_idx,
+                                    lexed
+                                );
+                            };
+                            if !info.is_mut {
+                                return Err(Error::immutable(var_idx, lexed));
+                            }
+                            calls_into.push((slot, name.clone(), info.ty.clone(), var_idx));
+                            Some((var, info.slot))
+                        } else {
+                            None
+                        };
                         expects_semi!(i, lexed);
 
-                        let info = scope_stack.get_type_info(name);
-                        if info.is_none() || info.unwrap().ty != Type::Sub {
-                            die_cont!(format!("Subroutine \"{}\" was not found", name), i, lexed);
-                        }
+                        called_subs.insert(name.clone());
 
-                        Statement::Call { name: name.clone() }
+                        Statement::Call {
+                            name: name.clone(),
+                            slot,
+                            into,
+                        }
                     } else {
-                        die_cont!("Expected subroutine name", i, lexed)
+                        die_cont!(Code::E003, "Expected subroutine name", i, lexed)
                     }
                 }),
 
-                lex::Command::While => parse_stmt!(i, stmts, {
+                lex::Command::While => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
                     // "While" cond ";"
-
-                    scope_stack.push(stmts.len());
+                    let cmd_idx = i - 1;
 
                     let expr = parse_expr!(Items::Semi, i, tks, lexed, scope_stack);
                     expects_type!(expr, Type::Bool, scope_stack, i, lexed);
 
                     expects_semi!(i, lexed);
 
+                    scope_stack.push(stmts.len(), cmd_idx, stmts.len());
+
                     Statement::While {
                         cond: expr,
                         offset_to_end: 0,
                     }
                 }),
 
-                lex::Command::Let => parse_stmt!(i, stmts, {
-                    // "Let" name "Be" expr ("AsMut") ";"
-
-                    if let Items::Ident(name) = &tks[i].item {
-                        i += 1;
-                        if name.starts_with('_') {
-                            die_cont!("Identifier starts with _ is reserved", i, lexed);
-                        }
-                        expects!("\"Be\" expected", Items::Key(Keywords::Be), i, lexed);
+                lex::Command::Let | lex::Command::Global => {
+                    parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                        // ("Let" | "Global") name "Be" expr ("AsMut") ";"
+                        let is_global = *inst == lex::Command::Global;
+
+                        if let Some(Items::Ident(name)) = tks.get(i).map(|tk| &tk.item) {
+                            let name_idx = i;
+                            i += 1;
+                            if name.starts_with('_') {
+                                return Err(Error::at(
+                                    Code::E007,
+                                    "Identifier starts with _ is reserved",
+                                    name_idx,
+                                    lexed,
+                                )
+                                .with_note("identifiers starting with _ are used internally"));
+                            }
+                            expects!("\"Be\" expected", Items::Key(Keywords::Be), i, lexed);
 
-                        let init = parse_expr!(
-                            Items::Semi | Items::Key(Keywords::AsMut),
-                            i,
-                            tks,
-                            lexed,
-                            scope_stack
-                        );
+                            let init = parse_expr!(
+                                Items::Semi | Items::Key(Keywords::AsMut),
+                                i,
+                                tks,
+                                lexed,
+                                scope_stack
+                            );
 
-                        let init_ty = match init.check_type(&scope_stack) {
-                            Ok(t) => t,
-                            Err(e) => die_by_expr_parse_error(e.into(), i, &lexed),
-                        };
+                            let init_ty = match init.check_type(&scope_stack) {
+                                Ok(t) => t,
+                                Err(e) => {
+                                    return Err(expr_parse_error(
+                                        e.into(),
+                                        Some(&init.span),
+                                        i,
+                                        lexed,
+                                    ))
+                                }
+                            };
 
-                        expects!(
-                            "\"AsMut\" or semicolon expected",
-                            Items::Semi | Items::Key(Keywords::AsMut),
-                            i,
-                            lexed
-                        );
+                            expects!(
+                                "\"AsMut\" or semicolon expected",
+                                Items::Semi | Items::Key(Keywords::AsMut),
+                                i,
+                                lexed
+                            );
+
+                            let is_mut = {
+                                if tks[i - 1].item == Items::Key(Keywords::AsMut) {
+                                    expects_semi!(i, lexed);
+                                    true
+                                } else {
+                                    false
+                                }
+                            };
 
-                        let is_mut = {
-                            if tks[i - 1].item == Items::Key(Keywords::AsMut) {
-                                expects_semi!(i, lexed);
-                                true
+                            let shadows = scope_stack.get_type_info(name).is_some();
+                            let zero = init_ty.zero();
+                            let slot = if is_global {
+                                if zero.is_none() {
+                                    return Err(Error::spanned(
+                                        Code::E008,
+                                        "A global cannot hold a Sub",
+                                        &init.span,
+                                        lexed,
+                                    )
+                                    .with_note(
+                                        "globals exist from the start, before any value is given",
+                                    ));
+                                }
+                                if shadows {
+                                    return Err(Error::at(
+                                        Code::E006,
+                                        "Conflicting variable name",
+                                        name_idx,
+                                        lexed,
+                                    )
+                                    .with_note(
+                                        "the variable visible here would hide the global one",
+                                    ));
+                                }
+                                scope_stack.add_global(name.clone(), init_ty, is_mut)
                             } else {
-                                false
+                                scope_stack.add_var(name.clone(), init_ty, is_mut)
+                            };
+                            let Some(slot) = slot else {
+                                die_cont!(Code::E006, "Conflicting variable name", name_idx, lexed);
+                            };
+                            if is_global {
+                                globals.extend(zero.map(|zero| (slot, zero)));
+                            }
+                            if shadows {
+                                errors.extend(Error::lint(
+                                    lints,
+                                    Lint::Shadowing,
+                                    format!(
+                                        "Variable \"{name}\" shadows a variable in an outer block"
+                                    ),
+                                    name_idx,
+                                    lexed,
+                                ));
                             }
-                        };
 
-                        let success = scope_stack.add_var(
-                            name.clone(),
-                            TypeInfo {
-                                ty: init_ty,
+                            Statement::Let {
+                                name: name.clone(),
+                                slot,
+                                init,
                                 is_mut,
-                            },
-                        );
-
-                        if !success {
-                            die_cont!("Conflicting variable name", i, lexed);
-                        }
-
-                        Statement::Let {
-                            name: name.clone(),
-                            init,
-                            is_mut,
+                                is_global,
+                            }
+                        } else {
+                            die_cont!(Code::E003, "Ident expected", i, lexed)
                         }
-                    } else {
-                        die_cont!("Ident expected", i, lexed)
-                    }
-                }),
+                    });
+                }
 
-                lex::Command::Modify => parse_stmt!(i, stmts, {
-                    // "Modify" name "To" expr ";"
+                lex::Command::Modify => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Modify" name {"[" expr "]"} "To" expr ";"
 
-                    if let Items::Ident(name) = &tks[i].item {
+                    if let Some(Items::Ident(name)) = tks.get(i).map(|tk| &tk.item) {
+                        let name_idx = i;
                         i += 1;
 
+                        let mut indices = Vec::new();
+                        while tks.get(i).map(|tk| &tk.item) == Some(&Items::LBracket) {
+                            i += 1;
+                            indices.push(parse_expr!(Items::RBracket, i, tks, lexed, scope_stack));
+                            expects!("\"]\" expected", Items::RBracket, i, lexed);
+                        }
+
                         expects!("To expected", Items::Key(Keywords::To), i, lexed);
 
                         let expr = parse_expr!(Items::Semi, i, tks, lexed, scope_stack);
                         expects_semi!(i, lexed);
 
                         let var_tinfo = scope_stack.get_type_info(name);
-                        if let Some(info) = var_tinfo {
-                            // TODO: better error message (maybe)
+                        let slot = if let Some(info) = var_tinfo {
                             let expr_ty = match expr.check_type(&scope_stack) {
                                 Ok(t) => t,
-                                Err(e) => die_by_expr_parse_error(e.into(), i, &lexed),
+                                Err(e) => {
+                                    return Err(expr_parse_error(
+                                        e.into(),
+                                        Some(&expr.span),
+                                        i,
+                                        lexed,
+                                    ))
+                                }
                             };
 
-                            if info.ty != expr_ty {
-                                die_cont!("Type mismatch", i, lexed);
+                            let mut target_ty = &info.ty;
+                            for index in &indices {
+                                let index_ty = index.check_type(&scope_stack).map_err(|e| {
+                                    expr_parse_error(e.into(), Some(&index.span), i, lexed)
+                                })?;
+                                match (target_ty, index_ty) {
+                                    (Type::Array(elem), Type::Num) => target_ty = elem,
+                                    (ty, index_ty) => {
+                                        return Err(expr_parse_error(
+                                            TypeError::IndexUndefined {
+                                                ty: ty.clone(),
+                                                index: index_ty,
+                                                span: index.span.clone(),
+                                            }
+                                            .into(),
+                                            None,
+                                            i,
+                                            lexed,
+                                        ))
+                                    }
+                                }
+                            }
+
+                            if *target_ty != expr_ty {
+                                let target = if indices.is_empty() {
+                                    format!("\"{name}\"")
+                                } else {
+                                    format!("this element of \"{name}\"")
+                                };
+                                return Err(Error::spanned(
+                                    Code::E008,
+                                    format!(
+                                        "Type mismatch: {target} is {target_ty}, but the value is {expr_ty}"
+                                    ),
+                                    &expr.span,
+                                    lexed,
+                                ));
                             }
 
                             if !info.is_mut {
-                                die_cont!("Variable is immutable", i, lexed);
+                                return Err(Error::immutable(name_idx, lexed));
                             }
+                            info.slot
                         } else {
-                            die_cont!(format!("Variable \"{}\" was not found", name), i, lexed);
-                        }
+                            die_cont!(
+                                Code::E005,
+                                format!("Variable \"{}\" was not found", name),
+                                name_idx,
+                                lexed
+                            );
+                        };
 
                         Statement::Modify {
                             name: name.clone(),
+                            slot,
+                            indices,
                             expr,
                         }
                     } else {
-                        die_cont!("Ident expected", i, lexed);
+                        die_cont!(Code::E003, "Ident expected", i, lexed);
                     }
                 }),
 
-                lex::Command::If => parse_stmt!(i, stmts, {
+                lex::Command::If => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
                     // "If" cond ";"
+                    let cmd_idx = i - 1;
 
                     let cond = parse_expr!(Items::Semi, i, tks, lexed, scope_stack);
+                    expects_type!(cond, Type::Bool, scope_stack, i, lexed);
                     expects_semi!(i, lexed);
 
-                    scope_stack.push(stmts.len());
+                    scope_stack.push(stmts.len(), cmd_idx, stmts.len());
 
                     Statement::If {
                         cond,
@@ -569,39 +1343,46 @@ pub fn parse(lexed: crate::lex::Lexed) -> AST {
                     }
                 }),
 
-                lex::Command::Else => parse_stmt!(i, stmts, {
+                lex::Command::Else => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
                     // "Else" ("If" cond) ";"
-
-                    let inst_obj = if let Items::Cmd(lex::Command::If) = &tks[i].item {
+                    let cmd_idx = i - 1;
+                    // the arms are in the block of the If at runtime
+                    let if_id = scope_stack.top_id();
+
+                    let inst_obj = if matches!(
+                        tks.get(i).map(|tk| &tk.item),
+                        Some(Items::Cmd(lex::Command::If))
+                    ) {
                         // "Else" "If" cond ";"
                         i += 1;
 
-                        let prev_idx = scope_stack.pop().unwrap_or_else(|| {
-                            die_cont!("A stray Else-If detected.", i, lexed);
-                        });
+                        let prev_idx = scope_stack.pop().ok_or_else(|| {
+                            Error::at(Code::E010, "A stray Else-If detected.", cmd_idx, lexed)
+                        })?;
 
                         let offset_to_next = stmts.len() - prev_idx;
 
-                        let prev = stmts[prev_idx].clone();
-                        stmts[prev_idx] = match prev {
-                            Statement::If { cond, .. } => Statement::If {
-                                cond: cond.clone(),
-                                offset_to_next,
-                            },
-                            Statement::ElIf { cond, .. } => Statement::ElIf {
-                                cond: cond.clone(),
-                                offset_to_next,
-                            },
+                        match &mut stmts[prev_idx] {
+                            Statement::If {
+                                offset_to_next: o, ..
+                            }
+                            | Statement::ElIf {
+                                offset_to_next: o, ..
+                            } => *o = offset_to_next,
+                            // the statement failed to be parsed, already reported
+                            Statement::Ill => {}
                             _ => {
                                 die_cont!(
+                                    Code::E010,
                                     "Cannot find corresponding Element for Else-If",
-                                    i,
+                                    cmd_idx,
                                     lexed
                                 );
                             }
-                        };
+                        }
 
                         let cond = parse_expr!(Items::Semi, i, tks, lexed, scope_stack);
+                        expects_type!(cond, Type::Bool, scope_stack, i, lexed);
                         expects_semi!(i, lexed);
 
                         Statement::ElIf {
@@ -612,125 +1393,415 @@ pub fn parse(lexed: crate::lex::Lexed) -> AST {
                         // "Else" ";"
                         expects_semi!(i, lexed);
 
-                        let prev_idx = scope_stack.pop().unwrap_or_else(|| {
-                            die_cont!("A stray Else detected.", i, lexed);
-                        });
+                        let prev_idx = scope_stack.pop().ok_or_else(|| {
+                            Error::at(Code::E010, "A stray Else detected.", cmd_idx, lexed)
+                        })?;
 
                         let offset_to_next = stmts.len() - prev_idx;
 
-                        let prev = stmts[prev_idx].clone();
-                        stmts[prev_idx] = match prev {
-                            Statement::If { cond, .. } => Statement::If {
-                                cond: cond.clone(),
-                                offset_to_next,
-                            },
-                            Statement::ElIf { cond, .. } => Statement::ElIf {
-                                cond: cond.clone(),
-                                offset_to_next,
-                            },
+                        match &mut stmts[prev_idx] {
+                            Statement::If {
+                                offset_to_next: o, ..
+                            }
+                            | Statement::ElIf {
+                                offset_to_next: o, ..
+                            } => *o = offset_to_next,
+                            // the statement failed to be parsed, already reported
+                            Statement::Ill => {}
                             _ => {
-                                die_cont!("Cannot find corresponding Element for Else", i, lexed);
+                                die_cont!(
+                                    Code::E010,
+                                    "Cannot find corresponding Element for Else",
+                                    cmd_idx,
+                                    lexed
+                                );
                             }
-                        };
+                        }
                         Statement::Else { offset_to_end: 0 }
                     };
 
-                    scope_stack.push(stmts.len());
+                    scope_stack.push(stmts.len(), cmd_idx, if_id);
 
                     inst_obj
                 }),
 
-                lex::Command::End => parse_stmt!(i, stmts, {
+                lex::Command::Switch => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Switch" expr ";"
+                    let cmd_idx = i - 1;
+
+                    let subject = parse_expr!(Items::Semi, i, tks, lexed, scope_stack);
+                    if subject.check_type(&scope_stack).ok() == Some(Type::Sub) {
+                        return Err(Error::spanned(
+                            Code::E008,
+                            "Switch cannot compare subroutines",
+                            &subject.span,
+                            lexed,
+                        ));
+                    }
+                    expects_semi!(i, lexed);
+
+                    scope_stack.push(stmts.len(), cmd_idx, stmts.len());
+
+                    Statement::Switch {
+                        subject,
+                        offset_to_next: 0,
+                    }
+                }),
+
+                lex::Command::Case | lex::Command::Default => {
+                    parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                        // "Case" expr ";" | "Default" ";"
+                        let cmd_idx = i - 1;
+                        let is_case = tks[cmd_idx].item == Items::Cmd(lex::Command::Case);
+                        let what = if is_case { "Case" } else { "Default" };
+                        // the arms are in the block of the Switch at runtime
+                        let switch_id = scope_stack.top_id();
+
+                        let prev_idx = scope_stack.pop().ok_or_else(|| {
+                            Error::at(
+                                Code::E010,
+                                format!("A stray {what} detected."),
+                                cmd_idx,
+                                lexed,
+                            )
+                        })?;
+                        let offset_to_next = stmts.len() - prev_idx;
+
+                        match &mut stmts[prev_idx] {
+                            Statement::Switch { .. } if offset_to_next > 1 => {
+                                die_cont!(
+                                    Code::E010,
+                                    "Statements before the first Case are never run",
+                                    cmd_idx,
+                                    lexed
+                                );
+                            }
+                            Statement::Switch {
+                                offset_to_next: o, ..
+                            }
+                            | Statement::Case {
+                                offset_to_next: o, ..
+                            } => *o = offset_to_next,
+                            // the statement failed to be parsed, already reported
+                            Statement::Ill => {}
+                            _ => {
+                                die_cont!(
+                                    Code::E010,
+                                    format!("Cannot find corresponding Switch for {}", what),
+                                    cmd_idx,
+                                    lexed
+                                );
+                            }
+                        }
+
+                        let inst_obj = if is_case {
+                            let value = parse_expr!(Items::Semi, i, tks, lexed, scope_stack);
+                            let value_ty = value.check_type(&scope_stack).map_err(|e| {
+                                expr_parse_error(e.into(), Some(&value.span), i, lexed)
+                            })?;
+                            if let Statement::Switch { subject, .. } = &stmts[switch_id] {
+                                if let Ok(subject_ty) = subject.check_type(&scope_stack) {
+                                    if value_ty != subject_ty {
+                                        return Err(Error::spanned(
+                                            Code::E008,
+                                            format!(
+                                                "Type mismatch: the subject of Switch is {subject_ty}, but this is {value_ty}"
+                                            ),
+                                            &value.span,
+                                            lexed,
+                                        ));
+                                    }
+                                }
+                            }
+                            expects_semi!(i, lexed);
+                            Statement::Case {
+                                value,
+                                offset_to_next: 0,
+                            }
+                        } else {
+                            expects_semi!(i, lexed);
+                            Statement::Else { offset_to_end: 0 }
+                        };
+
+                        scope_stack.push(stmts.len(), cmd_idx, switch_id);
+
+                        inst_obj
+                    });
+                }
+
+                lex::Command::Macro => {
+                    // definitions are taken out by macros::expand, so this came from an argument
+                    errors.push(Error::at(
+                        Code::E003,
+                        "Macro cannot be defined here",
+                        i,
+                        lexed,
+                    ));
+                    i = skip_stmt(i, tks);
+                }
+
+                // checked by pragma::check
+                lex::Command::Pragma => i = skip_stmt(i, tks),
+
+                lex::Command::Hook => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Hook" ("before_print" | "after_print") "Call" name ";"
+                    let point = match tks.get(i).map(|tk| &tk.item) {
+                        Some(Items::Ident(p)) if p == PrintHook::Before.as_str() => {
+                            PrintHook::Before
+                        }
+                        Some(Items::Ident(p)) if p == PrintHook::After.as_str() => PrintHook::After,
+                        _ => die_cont!(
+                            Code::E003,
+                            "\"before_print\" or \"after_print\" expected",
+                            i,
+                            lexed
+                        ),
+                    };
+                    i += 1;
+                    expects!(
+                        "\"Call\" expected",
+                        Items::Cmd(lex::Command::Call),
+                        i,
+                        lexed
+                    );
+
+                    let name_idx = i;
+                    let name = if let Some(Items::Ident(n)) = tks.get(i).map(|tk| &tk.item) {
+                        i += 1;
+                        n.clone()
+                    } else {
+                        die_cont!(Code::E003, "Expected subroutine name", i, lexed)
+                    };
+                    let slot = match scope_stack.get_type_info(&name) {
+                        Some(info) if info.ty == Type::Sub => info.slot,
+                        _ => die_cont!(
+                            Code::E005,
+                            format!("Subroutine \"{}\" was not found", name),
+                            name_idx,
+                            lexed
+                        ),
+                    };
+                    called_subs.insert(name.clone());
+
+                    expects_semi!(i, lexed);
+                    Statement::Hook { point, name, slot }
+                }),
+
+                lex::Command::Try => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Try" ";"
+                    let cmd_idx = i - 1;
+                    expects_semi!(i, lexed);
+
+                    scope_stack.push(stmts.len(), cmd_idx, stmts.len());
+
+                    Statement::Try {
+                        offset_to_recover: 0,
+                    }
+                }),
+
+                lex::Command::Recover => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Recover" ";"
+                    let cmd_idx = i - 1;
+                    expects_semi!(i, lexed);
+
+                    let prev_idx = scope_stack.pop().ok_or_else(|| {
+                        Error::at(Code::E010, "A stray Recover detected.", cmd_idx, lexed)
+                    })?;
+
+                    let offset_to_recover = stmts.len() - prev_idx;
+
+                    stmts[prev_idx] = match stmts[prev_idx] {
+                        Statement::Try { .. } => Statement::Try { offset_to_recover },
+                        // the statement failed to be parsed, already reported
+                        Statement::Ill => Statement::Ill,
+                        _ => {
+                            die_cont!(
+                                Code::E010,
+                                "Cannot find corresponding Try for Recover",
+                                cmd_idx,
+                                lexed
+                            );
+                        }
+                    };
+
+                    scope_stack.push(stmts.len(), cmd_idx, stmts.len());
+
+                    Statement::Recover { offset_to_end: 0 }
+                }),
+
+                lex::Command::End => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
                     // "End" ";"
+                    let cmd_idx = i - 1;
                     expects_semi!(i, lexed);
 
                     // Pop stack and assign end index
-                    let prev_idx = scope_stack.pop().unwrap_or_else(|| {
-                        die_cont!("A stray End detected.", i, lexed);
-                    });
+                    let prev_idx = scope_stack.pop().ok_or_else(|| {
+                        Error::at(Code::E010, "A stray End detected.", cmd_idx, lexed)
+                    })?;
 
                     let offset_to_end = stmts.len() - prev_idx;
 
-                    let prev = stmts[prev_idx].clone();
-                    stmts[prev_idx] = match prev {
-                        Statement::Sub { name, .. } => Statement::Sub {
-                            name,
-                            offset_to_end,
-                        },
-                        Statement::While { cond, .. } => Statement::While {
-                            cond,
-                            offset_to_end,
-                        },
-                        Statement::If { ref cond, .. } => Statement::If {
-                            cond: cond.clone(),
-                            offset_to_next: offset_to_end,
-                        },
-                        Statement::ElIf { ref cond, .. } => Statement::ElIf {
-                            cond: cond.clone(),
-                            offset_to_next: offset_to_end,
-                        },
-                        Statement::Else { .. } => Statement::Else { offset_to_end },
+                    // patched in place, without copying the expressions
+                    match &mut stmts[prev_idx] {
+                        Statement::Sub {
+                            offset_to_end: o, ..
+                        }
+                        | Statement::While {
+                            offset_to_end: o, ..
+                        }
+                        | Statement::If {
+                            offset_to_next: o, ..
+                        }
+                        | Statement::ElIf {
+                            offset_to_next: o, ..
+                        }
+                        | Statement::Switch {
+                            offset_to_next: o, ..
+                        }
+                        | Statement::Case {
+                            offset_to_next: o, ..
+                        }
+                        | Statement::Else { offset_to_end: o }
+                        | Statement::Recover { offset_to_end: o } => *o = offset_to_end,
+                        Statement::Try { .. } => {
+                            die_cont!(
+                                Code::E010,
+                                "Try must have Recover before End",
+                                cmd_idx,
+                                lexed
+                            );
+                        }
+                        // the statement failed to be parsed, already reported
+                        Statement::Ill => {}
                         _ => {
-                            die_cont!("Cannot find corresponding Element for End", i, lexed);
+                            die_cont!(
+                                Code::E010,
+                                "Cannot find corresponding Element for End",
+                                cmd_idx,
+                                lexed
+                            );
                         }
-                    };
+                    }
 
                     Statement::End
                 }),
 
-                lex::Command::Input => parse_stmt!(i, stmts, {
-                    // "Input" (prompt) "To" name ";"
+                lex::Command::Input => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Input" (prompt) ("Masked" mask) "To" name
+                    // ("Where" cond {"And" cond} ("Else" message)) ";"
 
-                    let prompt = if let Items::Str(prompt) = &tks[i].item {
+                    let prompt = if let Some(Items::Str(prompt)) = tks.get(i).map(|tk| &tk.item) {
                         i += 1;
                         Some(prompt.clone())
                     } else {
                         None
                     };
 
+                    let mask =
+                        if tks.get(i).map(|tk| &tk.item) == Some(&Items::Key(Keywords::Masked)) {
+                            i += 1;
+                            if let Some(Items::Str(mask)) = tks.get(i).map(|tk| &tk.item) {
+                                if !mask.contains(&['#', '?', '*'][..]) {
+                                    die_cont!(
+                                    Code::E003,
+                                    "Mask must have `#`, `?` or `*` for the characters to accept",
+                                    i,
+                                    lexed
+                                );
+                                }
+                                i += 1;
+                                Some(mask.clone())
+                            } else {
+                                die_cont!(Code::E003, "String of the mask expected", i, lexed)
+                            }
+                        } else {
+                            None
+                        };
+
                     expects!("\"To\" expected", Items::Key(Keywords::To), i, lexed);
 
-                    let name = if let Items::Ident(n) = &tks[i].item {
+                    let name_idx = i;
+                    let name = if let Some(Items::Ident(n)) = tks.get(i).map(|tk| &tk.item) {
                         i += 1;
                         n.clone()
                     } else {
-                        die_cont!("Ident expected", i, lexed)
+                        die_cont!(Code::E003, "Ident expected", i, lexed)
                     };
 
-                    let as_num = if let Some(info) = scope_stack.get_type_info(&name) {
+                    let (as_num, slot) = if let Some(info) = scope_stack.get_type_info(&name) {
                         if !info.is_mut {
-                            die_cont!("Variable is immutable", i, lexed);
+                            return Err(Error::immutable(name_idx, lexed));
                         }
-                        match info.ty {
+                        let as_num = match info.ty {
+                            Type::Num if mask.is_some() => {
+                                die_cont!(Code::E008, "Masked input must be Str", name_idx, lexed)
+                            }
                             Type::Num => true,
                             Type::Str => false,
-                            _ => die_cont!("Expected Num or Str", i, lexed),
-                        }
+                            _ => die_cont!(Code::E008, "Expected Num or Str", name_idx, lexed),
+                        };
+                        (as_num, info.slot)
                     } else {
-                        die_cont!(format!("Variable \"{}\" was not found", name), i, lexed)
+                        die_cont!(
+                            Code::E005,
+                            format!("Variable \"{}\" was not found", name),
+                            name_idx,
+                            lexed
+                        )
                     };
 
+                    let mut valid = Vec::new();
+                    let mut invalid_msg = None;
+                    if tks.get(i).map(|tk| &tk.item) == Some(&Items::Key(Keywords::Where)) {
+                        loop {
+                            i += 1;
+                            let cond = parse_expr!(
+                                Items::Key(Keywords::And) | Items::Cmd(lex::Command::Else),
+                                i,
+                                tks,
+                                lexed,
+                                scope_stack
+                            );
+                            expects_type!(cond, Type::Bool, scope_stack, i, lexed);
+                            valid.push(cond);
+                            if tks.get(i).map(|tk| &tk.item) != Some(&Items::Key(Keywords::And)) {
+                                break;
+                            }
+                        }
+                        if tks.get(i).map(|tk| &tk.item) == Some(&Items::Cmd(lex::Command::Else)) {
+                            i += 1;
+                            if let Some(Items::Str(msg)) = tks.get(i).map(|tk| &tk.item) {
+                                i += 1;
+                                invalid_msg = Some(msg.clone());
+                            } else {
+                                die_cont!(
+                                    Code::E003,
+                                    "Message for invalid inputs expected",
+                                    i,
+                                    lexed
+                                )
+                            }
+                        }
+                    }
+
                     expects_semi!(i, lexed);
                     Statement::Input {
                         prompt,
                         name,
+                        slot,
                         as_num,
+                        mask,
+                        valid,
+                        invalid_msg,
                     }
                 }),
 
-                lex::Command::Roll => parse_stmt!(i, stmts, {
-                    // "Roll" n "Dice" "With" k "Face" "To" name ";"
+                lex::Command::Roll => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Roll" n "Dice" "With" k "Face" "To" name ("Silent") ";"
 
                     let count = parse_expr!(Items::Key(Keywords::Dice), i, tks, lexed, scope_stack);
 
-                    let count_ty = match count.check_type(&scope_stack) {
-                        Ok(t) => t,
-                        Err(e) => die_by_expr_parse_error(e.into(), i, &lexed),
-                    };
-
-                    if count_ty != Type::Num {
-                        die_cont!("Expected Num Expr", i, lexed);
-                    }
+                    expects_type!(count, Type::Num, scope_stack, i, lexed);
 
                     expects!("\"Dice\" expected", Items::Key(Keywords::Dice), i, lexed);
 
@@ -738,56 +1809,377 @@ pub fn parse(lexed: crate::lex::Lexed) -> AST {
 
                     let face = parse_expr!(Items::Key(Keywords::Face), i, tks, lexed, scope_stack);
 
-                    let face_ty = match count.check_type(&scope_stack) {
-                        Ok(t) => t,
-                        Err(e) => die_by_expr_parse_error(e.into(), i, &lexed),
-                    };
-
-                    if face_ty != Type::Num {
-                        die_cont!("Expected Num Expr", i, lexed);
-                    }
+                    expects_type!(face, Type::Num, scope_stack, i, lexed);
 
                     expects!("\"Face\" expected", Items::Key(Keywords::Face), i, lexed);
 
                     expects!("\"To\" expected", Items::Key(Keywords::To), i, lexed);
 
-                    let name = if let Items::Ident(n) = &tks[i].item {
+                    let name_idx = i;
+                    let name = if let Some(Items::Ident(n)) = tks.get(i).map(|tk| &tk.item) {
                         i += 1;
                         n.clone()
                     } else {
-                        die_cont!("Ident expected", i, lexed)
+                        die_cont!(Code::E003, "Ident expected", i, lexed)
                     };
 
-                    if let Some(info) = scope_stack.get_type_info(&name) {
+                    let slot = if let Some(info) = scope_stack.get_type_info(&name) {
                         if !matches!(info.ty, Type::Num) {
-                            die_cont!("Expected Num", i, lexed)
+                            die_cont!(Code::E008, "Expected Num", name_idx, lexed)
                         }
                         if !info.is_mut {
-                            die_cont!("Variable is immutable", i, lexed);
+                            return Err(Error::immutable(name_idx, lexed));
                         }
+                        info.slot
                     } else {
-                        die_cont!(format!("Variable \"{}\" was not found", name), i, lexed)
+                        die_cont!(
+                            Code::E005,
+                            format!("Variable \"{}\" was not found", name),
+                            name_idx,
+                            lexed
+                        )
                     };
 
+                    let silent =
+                        tks.get(i).map(|tk| &tk.item) == Some(&Items::Key(Keywords::Silent));
+                    if silent {
+                        i += 1;
+                    }
+
                     expects_semi!(i, lexed);
-                    Statement::Roll { count, face, name }
+                    Statement::Roll {
+                        count,
+                        face,
+                        name,
+                        slot,
+                        silent,
+                    }
                 }),
 
-                lex::Command::Halt => parse_stmt!(i, stmts, {
-                    // "Halt" ";"
+                lex::Command::Halt => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Halt" (code) ";"
+                    let code = if tks.get(i).map(|tk| &tk.item) == Some(&Items::Semi) {
+                        None
+                    } else {
+                        let code = parse_expr!(Items::Semi, i, tks, lexed, scope_stack);
+                        expects_type!(code, Type::Num, scope_stack, i, lexed);
+                        Some(code)
+                    };
                     expects_semi!(i, lexed);
-                    Statement::Halt
+                    Statement::Halt { code }
                 }),
 
-                lex::Command::Break => parse_stmt!(i, stmts, {
+                lex::Command::Break => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
                     // "Break" ";"
                     expects_semi!(i, lexed);
                     Statement::Break
                 }),
+
+                lex::Command::Return => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Return" (expr) ";"
+                    let cmd_idx = i - 1;
+                    let sub = match scope_stack.enclosing_sub(&stmts) {
+                        Some(Statement::Sub { slot, .. }) => Some(*slot),
+                        // failed to be parsed, so the type of the values is not checked
+                        Some(_) => None,
+                        None => {
+                            die_cont!(Code::E010, "Return must be inside a Sub", cmd_idx, lexed)
+                        }
+                    };
+
+                    let value = if tks.get(i).map(|tk| &tk.item) == Some(&Items::Semi) {
+                        None
+                    } else {
+                        let value = parse_expr!(Items::Semi, i, tks, lexed, scope_stack);
+                        let ty = value
+                            .check_type(&scope_stack)
+                            .map_err(|e| expr_parse_error(e.into(), Some(&value.span), i, lexed))?;
+                        if let Some(sub) = sub {
+                            // the first value decides the type
+                            let ret_ty = sub_returns.entry(sub).or_insert_with(|| ty.clone());
+                            if *ret_ty != ty {
+                                return Err(Error::spanned(
+                                    Code::E008,
+                                    format!(
+                                        "Type mismatch: the Sub returns {ret_ty} elsewhere, but this is {ty}"
+                                    ),
+                                    &value.span,
+                                    lexed,
+                                ));
+                            }
+                        }
+                        Some(value)
+                    };
+                    expects_semi!(i, lexed);
+                    Statement::Return { value }
+                }),
+
+                lex::Command::Scene => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Scene" title ";"
+                    let title = if let Some(Items::Str(title)) = tks.get(i).map(|tk| &tk.item) {
+                        i += 1;
+                        title.clone()
+                    } else {
+                        die_cont!(Code::E003, "Title of the scene expected", i, lexed)
+                    };
+                    expects_semi!(i, lexed);
+                    Statement::Scene { title }
+                }),
+
+                lex::Command::Breakpoint => {
+                    parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                        // "Breakpoint" ";"
+                        expects_semi!(i, lexed);
+                        Statement::Breakpoint
+                    });
+                }
+
+                lex::Command::Newline => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Newline" (count) ";"
+                    let count = if tks.get(i).map(|tk| &tk.item) == Some(&Items::Semi) {
+                        None
+                    } else {
+                        let count = parse_expr!(Items::Semi, i, tks, lexed, scope_stack);
+                        expects_type!(count, Type::Num, scope_stack, i, lexed);
+                        Some(count)
+                    };
+                    expects_semi!(i, lexed);
+                    Statement::Newline { count }
+                }),
+
+                lex::Command::Checkpoint => {
+                    parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                        // "Checkpoint" ";"
+                        expects_semi!(i, lexed);
+                        Statement::Checkpoint
+                    });
+                }
+
+                lex::Command::Restore => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "Restore" "Checkpoint" ";"
+                    expects!(
+                        "\"Checkpoint\" expected",
+                        Items::Cmd(lex::Command::Checkpoint),
+                        i,
+                        lexed
+                    );
+                    expects_semi!(i, lexed);
+                    Statement::Restore
+                }),
+
+                lex::Command::After => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "After" ms "Call" name ";"
+                    let ms =
+                        parse_expr!(Items::Cmd(lex::Command::Call), i, tks, lexed, scope_stack);
+                    expects_type!(ms, Type::Num, scope_stack, i, lexed);
+                    expects!(
+                        "\"Call\" expected",
+                        Items::Cmd(lex::Command::Call),
+                        i,
+                        lexed
+                    );
+
+                    let name_idx = i;
+                    let name = if let Some(Items::Ident(n)) = tks.get(i).map(|tk| &tk.item) {
+                        i += 1;
+                        n.clone()
+                    } else {
+                        die_cont!(Code::E003, "Expected subroutine name", i, lexed)
+                    };
+                    let slot = match scope_stack.get_type_info(&name) {
+                        Some(info) if info.ty == Type::Sub => info.slot,
+                        _ => die_cont!(
+                            Code::E005,
+                            format!("Subroutine \"{}\" was not found", name),
+                            name_idx,
+                            lexed
+                        ),
+                    };
+                    called_subs.insert(name.clone());
+
+                    expects_semi!(i, lexed);
+                    Statement::After { ms, name, slot }
+                }),
+
+                lex::Command::OnKey => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "OnKey" key "Call" name ";"
+                    let key = match tks.get(i).map(|tk| &tk.item) {
+                        Some(Items::Str(key)) if !key.trim().is_empty() => key.trim().to_owned(),
+                        Some(Items::Str(_)) => {
+                            die_cont!(Code::E003, "Key must not be empty", i, lexed)
+                        }
+                        _ => die_cont!(Code::E003, "String of the key expected", i, lexed),
+                    };
+                    i += 1;
+                    expects!(
+                        "\"Call\" expected",
+                        Items::Cmd(lex::Command::Call),
+                        i,
+                        lexed
+                    );
+
+                    let name_idx = i;
+                    let name = if let Some(Items::Ident(n)) = tks.get(i).map(|tk| &tk.item) {
+                        i += 1;
+                        n.clone()
+                    } else {
+                        die_cont!(Code::E003, "Expected subroutine name", i, lexed)
+                    };
+                    let slot = match scope_stack.get_type_info(&name) {
+                        Some(info) if info.ty == Type::Sub => info.slot,
+                        _ => die_cont!(
+                            Code::E005,
+                            format!("Subroutine \"{}\" was not found", name),
+                            name_idx,
+                            lexed
+                        ),
+                    };
+                    called_subs.insert(name.clone());
+
+                    expects_semi!(i, lexed);
+                    Statement::OnKey { key, name, slot }
+                }),
+
+                lex::Command::OnError => parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                    // "OnError" "Call" name ";"
+                    expects!(
+                        "\"Call\" expected",
+                        Items::Cmd(lex::Command::Call),
+                        i,
+                        lexed
+                    );
+
+                    let name_idx = i;
+                    let name = if let Some(Items::Ident(n)) = tks.get(i).map(|tk| &tk.item) {
+                        i += 1;
+                        n.clone()
+                    } else {
+                        die_cont!(Code::E003, "Expected subroutine name", i, lexed)
+                    };
+                    let slot = match scope_stack.get_type_info(&name) {
+                        Some(info) if info.ty == Type::Sub => info.slot,
+                        _ => die_cont!(
+                            Code::E005,
+                            format!("Subroutine \"{}\" was not found", name),
+                            name_idx,
+                            lexed
+                        ),
+                    };
+                    called_subs.insert(name.clone());
+
+                    expects_semi!(i, lexed);
+                    Statement::OnError { name, slot }
+                }),
+
+                lex::Command::Statusline => {
+                    parse_stmt!(i, stmts, rows, errors, lexed, scope_stack, {
+                        // "Statusline" template ";"
+                        let template_idx = i;
+                        let mut template = if let Some(Items::Str(s)) =
+                            tks.get(i).map(|tk| &tk.item)
+                        {
+                            i += 1;
+                            match Template::parse(s) {
+                                Ok(template) => template,
+                                Err(msg) => die_cont!(Code::E003, msg, template_idx, lexed),
+                            }
+                        } else {
+                            die_cont!(Code::E003, "Template of the status line expected", i, lexed)
+                        };
+                        for piece in &mut template.0 {
+                            let Piece::Var(name, slot) = piece else {
+                                continue;
+                            };
+                            match scope_stack.get_type_info(name) {
+                                Some(TypeInfo { ty: Type::Sub, .. }) => die_cont!(
+                                    Code::E008,
+                                    format!("Sub \"{}\" cannot be shown", name),
+                                    template_idx,
+                                    lexed
+                                ),
+                                Some(info) => *slot = info.slot,
+                                None => die_cont!(
+                                    Code::E005,
+                                    format!("Variable \"{}\" was not found", name),
+                                    template_idx,
+                                    lexed
+                                ),
+                            }
+                        }
+                        expects_semi!(i, lexed);
+                        Statement::Status { template }
+                    });
+                }
             }
         } else {
-            die_cont!("Line must begin with Command", i, lexed);
+            errors.push(Error::at(
+                Code::E003,
+                "Line must begin with Command",
+                i,
+                lexed,
+            ));
+            i = skip_stmt(i, tks);
+        }
+    }
+
+    // blocks left open at the end of the file
+    for sc in scope_stack.scopes.iter().skip(1) {
+        errors.push(Error::at(
+            Code::E010,
+            "This block is not closed by End",
+            sc.opener,
+            lexed,
+        ));
+    }
+
+    for (name, name_idx) in declared_subs {
+        if !called_subs.contains(&name) {
+            errors.extend(Error::lint(
+                lints,
+                Lint::UnusedSub,
+                format!("Subroutine \"{name}\" is never called"),
+                name_idx,
+                lexed,
+            ));
         }
     }
-    AST { stmts }
+
+    // a sub may return after the calls (e.g. calling itself), so the types are checked here
+    for (sub, name, ty, var_idx) in calls_into {
+        match sub_returns.get(&sub) {
+            Some(ret_ty) if *ret_ty == ty => {}
+            Some(ret_ty) => errors.push(Error::at(
+                Code::E008,
+                format!("Type mismatch: the variable is {ty}, but \"{name}\" returns {ret_ty}"),
+                var_idx,
+                lexed,
+            )),
+            None => errors.push(
+                Error::at(
+                    Code::E008,
+                    format!("Subroutine \"{name}\" returns no value"),
+                    var_idx,
+                    lexed,
+                )
+                .with_note("return a value from the subroutine with `return <expr>;`"),
+            ),
+        }
+    }
+
+    // lints are found apart from the other errors, so put them in order of appearance
+    errors.sort_by_key(|e| (e.loc_info.loc.row, e.loc_info.loc.col));
+
+    if errors.iter().any(Error::is_error) {
+        Err(errors)
+    } else {
+        Ok((
+            AST {
+                stmts,
+                rows,
+                names: scope_stack.names,
+                globals,
+            },
+            errors,
+        ))
+    }
 }
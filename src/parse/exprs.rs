@@ -1,7 +1,9 @@
 use std::iter::Peekable;
 
-use crate::exprs::{items::*, Expr};
+#[allow(clippy::wildcard_imports)]
+use crate::exprs::items::*;
 use crate::lex::{self, Items, Token};
+use crate::types::Slot;
 
 use super::ParseError;
 
@@ -30,25 +32,6 @@ pub(super) trait TryFromTokens<'a> {
         Self: Sized;
 }
 
-impl<'a> TryFromTokens<'a> for Expr {
-    fn can_start_with(item: &Items) -> bool {
-        Rel::can_start_with(item)
-    }
-    fn try_from_tokens<T>(tks: &mut Peekable<T>) -> Result<Self>
-    where
-        T: Iterator<Item = &'a Token>,
-        Self: Sized,
-    {
-        let expr = Rel::try_from_tokens(tks)?;
-
-        if let Some(tk) = tks.next() {
-            return Err(ParseError::TrailingToken { from: tk.clone() });
-        }
-
-        Ok(Self { content: expr })
-    }
-}
-
 impl<'a> TryFromTokens<'a> for Rel {
     fn can_start_with(item: &Items) -> bool {
         AddSub::can_start_with(item)
@@ -68,15 +51,15 @@ impl<'a> TryFromTokens<'a> for Rel {
                 item: Items::Ops(Ops::Rel(op)),
                 ..
             }) => {
-                let _ = tks.next().unwrap();
+                let span = tks.next().unwrap().span();
                 let rop = AddSub::try_from_tokens(tks)?;
                 match op {
-                    RelOps::Equal => Self::Equal(lop, rop),
-                    RelOps::NotEqual => Self::NotEqual(lop, rop),
-                    RelOps::LessEqual => Self::LessEqual(lop, rop),
-                    RelOps::GreaterEqual => Self::GreaterEqual(lop, rop),
-                    RelOps::LessThan => Self::LessThan(lop, rop),
-                    RelOps::GreaterThan => Self::GreaterThan(lop, rop),
+                    RelOps::Equal => Self::Equal(lop, rop, span),
+                    RelOps::NotEqual => Self::NotEqual(lop, rop, span),
+                    RelOps::LessEqual => Self::LessEqual(lop, rop, span),
+                    RelOps::GreaterEqual => Self::GreaterEqual(lop, rop, span),
+                    RelOps::LessThan => Self::LessThan(lop, rop, span),
+                    RelOps::GreaterThan => Self::GreaterThan(lop, rop, span),
                 }
             }
             _ => Self::Single(lop),
@@ -104,11 +87,11 @@ impl<'a> TryFromTokens<'a> for AddSub {
                 ..
             }) => match op {
                 AriOps::Add | AriOps::Sub => {
-                    let _ = tks.next().unwrap();
+                    let span = tks.next().unwrap().span();
                     let rop = Self::try_from_tokens(tks)?;
                     match op {
-                        AriOps::Add => Self::Add(lop, Box::new(rop)),
-                        AriOps::Sub => Self::Sub(lop, Box::new(rop)),
+                        AriOps::Add => Self::Add(lop, Box::new(rop), span),
+                        AriOps::Sub => Self::Sub(lop, Box::new(rop), span),
                         _ => unreachable!(),
                     }
                 }
@@ -139,12 +122,12 @@ impl<'a> TryFromTokens<'a> for MulDiv {
                 ..
             }) => match op {
                 AriOps::Mul | AriOps::Div | AriOps::Mod => {
-                    let _ = tks.next().unwrap();
+                    let span = tks.next().unwrap().span();
                     let rop = Self::try_from_tokens(tks)?;
                     match op {
-                        AriOps::Mul => Self::Mul(lop, Box::new(rop)),
-                        AriOps::Div => Self::Div(lop, Box::new(rop)),
-                        AriOps::Mod => Self::Mod(lop, Box::new(rop)),
+                        AriOps::Mul => Self::Mul(lop, Box::new(rop), span),
+                        AriOps::Div => Self::Div(lop, Box::new(rop), span),
+                        AriOps::Mod => Self::Mod(lop, Box::new(rop), span),
                         _ => unreachable!(),
                     }
                 }
@@ -181,8 +164,8 @@ impl<'a> TryFromTokens<'a> for Node {
                     AriOps::Add | AriOps::Sub => {
                         let operand = Self::try_from_tokens(tks)?;
                         match op {
-                            AriOps::Add => Self::Plus(Box::new(operand)),
-                            AriOps::Sub => Self::Minus(Box::new(operand)),
+                            AriOps::Add => Self::Plus(Box::new(operand), tk.span()),
+                            AriOps::Sub => Self::Minus(Box::new(operand), tk.span()),
                             _ => unreachable!(),
                         }
                     }
@@ -206,6 +189,7 @@ impl<'a> TryFromTokens<'a> for Core {
                 | Items::Ident(_)
                 | Items::Key(Keywords::True | Keywords::False)
                 | Items::LParen
+                | Items::LBracket
         )
     }
 
@@ -218,10 +202,49 @@ impl<'a> TryFromTokens<'a> for Core {
         ensure_start!(tks);
 
         let tk = tks.next().unwrap();
-        Ok(match &tk.item {
+        let mut core = match &tk.item {
             Items::Str(s) => Self::Str(s.clone()),
             Items::Num(n, _) => Self::Num(*n),
-            Items::Ident(s) => Self::Ident(s.clone()),
+            Items::Ident(s) => match (Fmt::from_str(s), tks.peek()) {
+                (
+                    Some(fmt),
+                    Some(Token {
+                        item: Items::LParen,
+                        ..
+                    }),
+                ) => {
+                    // fmt "(" [rel {"," rel}] ")"
+                    let lparen = tks.next().unwrap();
+                    let mut args = Vec::new();
+                    if let Some(Token {
+                        item: Items::RParen,
+                        ..
+                    }) = tks.peek()
+                    {
+                        tks.next();
+                        return Ok(Self::Fmt(fmt, args, tk.span()));
+                    }
+                    loop {
+                        args.push(Rel::try_from_tokens(tks)?);
+                        match tks.next() {
+                            Some(Token {
+                                item: Items::Comma, ..
+                            }) => {}
+                            Some(Token {
+                                item: Items::RParen,
+                                ..
+                            }) => break,
+                            _ => {
+                                return Err(ParseError::NoPairParen {
+                                    lparen: lparen.clone(),
+                                })
+                            }
+                        }
+                    }
+                    Self::Fmt(fmt, args, tk.span())
+                }
+                _ => Self::Ident(s.clone(), Slot::UNRESOLVED),
+            },
             Items::Key(Keywords::True) => Self::True,
             Items::Key(Keywords::False) => Self::False,
             Items::LParen => {
@@ -236,7 +259,49 @@ impl<'a> TryFromTokens<'a> for Core {
                     _ => Err(ParseError::NoPairParen { lparen: tk.clone() })?,
                 }
             }
+            Items::LBracket => {
+                // "[" [rel {"," rel}] "]"
+                let mut elems = Vec::new();
+                if let Some(Token {
+                    item: Items::RBracket,
+                    ..
+                }) = tks.peek()
+                {
+                    tks.next();
+                } else {
+                    loop {
+                        elems.push(Rel::try_from_tokens(tks)?);
+                        match tks.next() {
+                            Some(Token {
+                                item: Items::Comma, ..
+                            }) => {}
+                            Some(Token {
+                                item: Items::RBracket,
+                                ..
+                            }) => break,
+                            _ => {
+                                return Err(ParseError::NoPairBracket {
+                                    lbracket: tk.clone(),
+                                })
+                            }
+                        }
+                    }
+                }
+                Self::Array(elems, tk.span())
+            }
             _ => todo!("{:?}", &tk.item),
-        })
+        };
+
+        // core {"[" rel "]"}
+        while let Some(lbracket) = tks.next_if(|tk| tk.item == Items::LBracket) {
+            let index = Rel::try_from_tokens(tks)?;
+            if tks.next_if(|tk| tk.item == Items::RBracket).is_none() {
+                return Err(ParseError::NoPairBracket {
+                    lbracket: lbracket.clone(),
+                });
+            }
+            core = Self::Index(Box::new(core), Box::new(index), lbracket.span());
+        }
+        Ok(core)
     }
 }
@@ -0,0 +1,426 @@
+//! Macros, expanded into their bodies before parsing
+//!
+//! ```text
+//! macro hurt(amount);
+//!     modify hp to hp - amount;
+//! end;
+//! hurt(5);
+//! ```
+//!
+//! Arguments replace the parameters as tokens, in parentheses unless they are single tokens,
+//! so that `hurt(1 + 2)` keeps its precedence and `inc(hp)` can name a variable to modify.
+//! They are put at the places of the parameters, so that expressions are located in the body.
+//! A body declaring variables or subs is put in a block of its own,
+//! and the declared names must not appear in the arguments, which would refer to them instead.
+
+use super::Error;
+use crate::diag::Code;
+use crate::lex::{Command, Items, Keywords, Lexed, Token};
+use std::borrow::Cow;
+use std::collections::{hash_map::Entry, HashMap};
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+    /// Names declared by `let`, `global` and `sub` in the body
+    declared: Vec<String>,
+}
+
+/// Whether the `i`-th token begins a statement
+fn at_stmt_start(tks: &[Token], i: usize) -> bool {
+    i == 0 || tks[i - 1].item == Items::Semi
+}
+
+fn error(code: Code, msg: impl Into<String>, tk: &Token, lexed: &Lexed) -> Error {
+    Error::spanned(code, msg, &tk.span(), lexed)
+}
+
+/// Take the definitions out of the tokens, and expand the macros in the rest
+pub(super) fn expand(lexed: &Lexed) -> Result<Cow<'_, Lexed>, Vec<Error>> {
+    if !lexed
+        .tokens
+        .iter()
+        .any(|tk| tk.item == Items::Cmd(Command::Macro))
+    {
+        return Ok(Cow::Borrowed(lexed));
+    }
+
+    let mut errors = Vec::new();
+    let mut macros = HashMap::new();
+    let mut rest = Vec::new();
+    let tks = &lexed.tokens;
+    let mut i = 0;
+    while i < tks.len() {
+        if tks[i].item == Items::Cmd(Command::Macro) && at_stmt_start(tks, i) {
+            let next = end_of_definition(lexed, i, &mut errors);
+            if let Some((name, m)) = define(lexed, i, next, &mut errors) {
+                match macros.entry(name) {
+                    Entry::Occupied(e) => errors.push(error(
+                        Code::E006,
+                        format!("Macro \"{}\" is already defined", e.key()),
+                        &tks[i + 1],
+                        lexed,
+                    )),
+                    Entry::Vacant(e) => {
+                        e.insert(m);
+                    }
+                }
+            }
+            i = next;
+        } else {
+            rest.push(tks[i].clone());
+            i += 1;
+        }
+    }
+
+    let mut tokens = Vec::new();
+    expand_in(
+        &rest,
+        &macros,
+        &mut Vec::new(),
+        lexed,
+        &mut tokens,
+        &mut errors,
+    );
+    if errors.is_empty() {
+        Ok(Cow::Owned(lexed.with_tokens(tokens)))
+    } else {
+        Err(errors)
+    }
+}
+
+/// Return the index of the token next to the `end;` closing the macro defined at `start`
+fn end_of_definition(lexed: &Lexed, start: usize, errors: &mut Vec<Error>) -> usize {
+    let tks = &lexed.tokens;
+    let mut i = super::skip_stmt(start, tks);
+    let mut depth = 0_usize;
+    while i < tks.len() {
+        if at_stmt_start(tks, i) {
+            match tks[i].item {
+                Items::Cmd(
+                    Command::Sub | Command::While | Command::If | Command::Switch | Command::Try,
+                ) => {
+                    depth += 1;
+                }
+                Items::Cmd(Command::Macro) => {
+                    errors.push(error(
+                        Code::E010,
+                        "Macros cannot be defined in a macro",
+                        &tks[i],
+                        lexed,
+                    ));
+                }
+                Items::Cmd(Command::End) if depth == 0 => return super::skip_stmt(i, tks),
+                Items::Cmd(Command::End) => depth -= 1,
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    errors.push(error(
+        Code::E010,
+        "This macro is not closed by End",
+        &tks[start],
+        lexed,
+    ));
+    tks.len()
+}
+
+/// Read the definition in `start..next`
+#[allow(clippy::option_if_let_else)]
+fn define(
+    lexed: &Lexed,
+    start: usize,
+    next: usize,
+    errors: &mut Vec<Error>,
+) -> Option<(String, Macro)> {
+    // "Macro" name "(" [param {"," param}] ")" ";" body "End" ";"
+    let tks = &lexed.tokens[..next];
+    let expected = |msg: &str, i: usize| match tks.get(i) {
+        Some(tk) => error(Code::E003, msg, tk, lexed),
+        None => error(Code::E003, msg, &tks[start], lexed),
+    };
+
+    let mut i = start + 1;
+    let Some(Items::Ident(name)) = tks.get(i).map(|tk| &tk.item) else {
+        errors.push(expected("Macro name expected", i));
+        return None;
+    };
+    i += 1;
+    if tks.get(i).map(|tk| &tk.item) != Some(&Items::LParen) {
+        errors.push(expected("\"(\" expected", i));
+        return None;
+    }
+    i += 1;
+
+    let mut params: Vec<String> = Vec::new();
+    if tks.get(i).map(|tk| &tk.item) == Some(&Items::RParen) {
+        i += 1;
+    } else {
+        loop {
+            let Some(Items::Ident(param)) = tks.get(i).map(|tk| &tk.item) else {
+                errors.push(expected("Parameter name expected", i));
+                return None;
+            };
+            if param.starts_with('_') {
+                errors.push(
+                    error(
+                        Code::E007,
+                        "Identifier starts with _ is reserved",
+                        &tks[i],
+                        lexed,
+                    )
+                    .with_note("identifiers starting with _ are used internally"),
+                );
+                return None;
+            }
+            if params.contains(param) {
+                errors.push(error(
+                    Code::E006,
+                    format!("Parameter \"{param}\" is declared twice"),
+                    &tks[i],
+                    lexed,
+                ));
+                return None;
+            }
+            params.push(param.clone());
+            i += 1;
+            match tks.get(i).map(|tk| &tk.item) {
+                Some(Items::Comma) => i += 1,
+                Some(Items::RParen) => {
+                    i += 1;
+                    break;
+                }
+                _ => {
+                    errors.push(expected("\",\" or \")\" expected", i));
+                    return None;
+                }
+            }
+        }
+    }
+    if tks.get(i).map(|tk| &tk.item) != Some(&Items::Semi) {
+        errors.push(expected("\";\" expected", i));
+        return None;
+    }
+
+    // without "End" ";"
+    let body = tks[i + 1..next.saturating_sub(2).max(i + 1)].to_vec();
+    let mut declared = Vec::new();
+    for (j, tk) in body.iter().enumerate() {
+        if !(at_stmt_start(&body, j)
+            && matches!(
+                tk.item,
+                Items::Cmd(Command::Let | Command::Global | Command::Sub)
+            ))
+        {
+            continue;
+        }
+        if let Some(Items::Ident(n)) = body.get(j + 1).map(|tk| &tk.item) {
+            if params.contains(n) {
+                errors.push(error(
+                    Code::E006,
+                    format!("Parameter \"{n}\" is declared again in the macro"),
+                    &body[j + 1],
+                    lexed,
+                ));
+                return None;
+            }
+            declared.push(n.clone());
+        }
+    }
+    Some((
+        name.clone(),
+        Macro {
+            params,
+            body,
+            declared,
+        },
+    ))
+}
+
+/// Expand the macros used in `tks` into `out`, with `stack` the names of the macros being expanded
+fn expand_in(
+    tks: &[Token],
+    macros: &HashMap<String, Macro>,
+    stack: &mut Vec<String>,
+    lexed: &Lexed,
+    out: &mut Vec<Token>,
+    errors: &mut Vec<Error>,
+) {
+    let mut i = 0;
+    while i < tks.len() {
+        let m = match &tks[i].item {
+            Items::Ident(name)
+                if at_stmt_start(tks, i)
+                    && tks.get(i + 1).map(|tk| &tk.item) == Some(&Items::LParen) =>
+            {
+                macros.get(name).map(|m| (name, m))
+            }
+            _ => None,
+        };
+        let Some((name, m)) = m else {
+            out.push(tks[i].clone());
+            i += 1;
+            continue;
+        };
+        let call = &tks[i];
+        let (args, next) = match read_args(tks, i + 2, lexed) {
+            Ok(ok) => ok,
+            Err(e) => {
+                errors.push(e);
+                i = super::skip_stmt(i, tks);
+                continue;
+            }
+        };
+        i = next;
+
+        if args.len() != m.params.len() {
+            errors.push(error(
+                Code::E012,
+                format!(
+                    "Macro \"{}\" takes {} argument(s) but {} were given",
+                    name,
+                    m.params.len(),
+                    args.len()
+                ),
+                call,
+                lexed,
+            ));
+            continue;
+        }
+        if stack.contains(name) {
+            let chain: Vec<_> = stack.iter().chain(std::iter::once(name)).cloned().collect();
+            errors.push(
+                error(
+                    Code::E012,
+                    format!("Macro \"{name}\" is expanded recursively"),
+                    call,
+                    lexed,
+                )
+                .with_note(format!("expanded as {}", chain.join(" -> "))),
+            );
+            continue;
+        }
+        if let Some(tk) = args
+            .iter()
+            .flatten()
+            .find(|tk| matches!(&tk.item, Items::Ident(n) if m.declared.contains(n)))
+        {
+            errors.push(
+                error(
+                    Code::E012,
+                    format!("This would refer to the one declared in macro \"{name}\""),
+                    tk,
+                    lexed,
+                )
+                .with_note("rename the variable or the sub in the macro"),
+            );
+            continue;
+        }
+
+        let mut body = Vec::new();
+        for tk in &m.body {
+            let arg = match &tk.item {
+                Items::Ident(n) => m.params.iter().position(|p| p == n).map(|k| &args[k]),
+                _ => None,
+            };
+            match arg {
+                Some(arg) if arg.len() == 1 => body.push(with_item(tk, arg[0].item.clone())),
+                Some(arg) => {
+                    body.push(with_item(tk, Items::LParen));
+                    body.extend(arg.iter().map(|a| with_item(tk, a.item.clone())));
+                    body.push(in_place_of(tk, Items::RParen));
+                }
+                None => body.push(tk.clone()),
+            }
+        }
+        // the names declared in the body are left with its block
+        let block = !m.declared.is_empty();
+        if block {
+            out.push(with_item(call, Items::Cmd(Command::If)));
+            out.push(with_item(call, Items::Key(Keywords::True)));
+            out.push(with_item(call, Items::Semi));
+        }
+        stack.push(name.clone());
+        expand_in(&body, macros, stack, lexed, out, errors);
+        stack.pop();
+        if block {
+            out.push(with_item(call, Items::Cmd(Command::End)));
+            out.push(with_item(call, Items::Semi));
+        }
+    }
+}
+
+fn with_item(tk: &Token, item: Items) -> Token {
+    Token {
+        loc: tk.loc.clone(),
+        item,
+    }
+}
+
+/// Put `item` so that it ends where `tk` ends
+fn in_place_of(tk: &Token, item: Items) -> Token {
+    let mut new = with_item(tk, item);
+    new.loc.col += tk.item.len().saturating_sub(new.item.len());
+    new
+}
+
+/// Read the arguments from `start` (next to "(") until ")" ";",
+/// and return them with the index of the token next to ";"
+fn read_args(
+    tks: &[Token],
+    start: usize,
+    lexed: &Lexed,
+) -> Result<(Vec<Vec<Token>>, usize), Error> {
+    let mut args = vec![Vec::new()];
+    let mut depth = 0_usize;
+    let mut i = start;
+    loop {
+        let Some(tk) = tks.get(i) else {
+            return Err(error(
+                Code::E004,
+                "Unpaired parenthesis",
+                &tks[start - 1],
+                lexed,
+            ));
+        };
+        match tk.item {
+            Items::LParen | Items::LBracket => depth += 1,
+            Items::RParen if depth == 0 => break,
+            Items::RParen | Items::RBracket => depth = depth.saturating_sub(1),
+            Items::Comma if depth == 0 => {
+                args.push(Vec::new());
+                i += 1;
+                continue;
+            }
+            Items::Semi => {
+                return Err(error(
+                    Code::E004,
+                    "Unpaired parenthesis",
+                    &tks[start - 1],
+                    lexed,
+                ));
+            }
+            _ => {}
+        }
+        args.last_mut().unwrap().push(tk.clone());
+        i += 1;
+    }
+    if tks.get(i + 1).map(|tk| &tk.item) != Some(&Items::Semi) {
+        let tk = tks.get(i + 1).unwrap_or(&tks[i]);
+        return Err(error(Code::E003, "\";\" expected", tk, lexed));
+    }
+    if args.len() == 1 && args[0].is_empty() {
+        args.clear();
+    }
+    if let Some(pos) = args.iter().position(Vec::is_empty) {
+        // the comma before or after it
+        let tk = tks[start..=i]
+            .iter()
+            .filter(|tk| tk.item == Items::Comma)
+            .nth(pos.saturating_sub(1))
+            .unwrap_or(&tks[i]);
+        return Err(error(Code::E004, "Argument is empty", tk, lexed));
+    }
+    Ok((args, i + 2))
+}
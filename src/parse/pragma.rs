@@ -0,0 +1,120 @@
+//! `pragma novelint 0.3;`, telling the version of the language a script is written for
+//!
+//! Versions are compatible as in Cargo: `0.3` runs on 0.3.x, and `1.2` on 1.x from 1.2.
+//! They are checked before anything else, so that syntax from other versions
+//! is reported as the version mismatch instead of as errors of its own.
+
+use super::Error;
+use crate::diag::Code;
+use crate::lex::{Command, Items, Lexed, Span};
+
+/// The name written after `pragma`
+const LANGUAGE: &str = "novelint";
+
+/// Major, minor and patch
+type Version = (u64, u64, u64);
+
+fn parse_version(s: &str) -> Option<Version> {
+    let mut nums = s.split('.').map(|n| n.parse().ok());
+    let version = (
+        nums.next()??,
+        nums.next().unwrap_or(Some(0))?,
+        nums.next().unwrap_or(Some(0))?,
+    );
+    nums.next().is_none().then_some(version)
+}
+
+/// The version of this interpreter
+fn current() -> Version {
+    parse_version(env!("CARGO_PKG_VERSION")).unwrap()
+}
+
+/// Whether a script written for `required` runs on `current`
+const fn is_compatible(required: Version, current: Version) -> bool {
+    let (major, minor, _) = required;
+    if major != current.0 {
+        false
+    } else if major == 0 {
+        minor == current.1 && required.2 <= current.2
+    } else {
+        minor < current.1 || (minor == current.1 && required.2 <= current.2)
+    }
+}
+
+/// Check the pragmas of the script against the version of this interpreter
+pub(super) fn check(lexed: &Lexed) -> Result<(), Vec<Error>> {
+    let tks = &lexed.tokens;
+    let mut errors = Vec::new();
+    for (i, tk) in tks.iter().enumerate() {
+        let at_stmt_start = i == 0 || tks[i - 1].item == Items::Semi;
+        if !(at_stmt_start && tk.item == Items::Cmd(Command::Pragma)) {
+            continue;
+        }
+        // "Pragma" "novelint" version ";"
+        match tks.get(i + 1).map(|tk| &tk.item) {
+            Some(Items::Ident(name)) if name == LANGUAGE => {}
+            _ => {
+                errors.push(Error::at(
+                    Code::E003,
+                    format!("\"{LANGUAGE}\" expected"),
+                    i + 1,
+                    lexed,
+                ));
+                continue;
+            }
+        }
+        // "0.3" is lexed as 0 and ".3"
+        let end = super::skip_stmt(i, tks) - 1;
+        let text: Option<String> = tks[i + 2..end.min(tks.len())]
+            .iter()
+            .map(|tk| match &tk.item {
+                Items::Num(n, _) => Some(n.to_string()),
+                Items::Ident(s) => Some(s.clone()),
+                _ => None,
+            })
+            .collect();
+        let Some(required) = text.as_deref().and_then(parse_version) else {
+            errors.push(
+                Error::at(Code::E003, "Version expected", i + 2, lexed)
+                    .with_note(format!("write it like `pragma {LANGUAGE} 0.1;`")),
+            );
+            continue;
+        };
+
+        let current = current();
+        if !is_compatible(required, current) {
+            let which = if required > current {
+                "a newer"
+            } else {
+                "an older"
+            };
+            let span = Span {
+                from: tks[i + 2].loc.clone(),
+                to: tks[end - 1].next_col_loc(),
+            };
+            errors.push(
+                Error::spanned(
+                    Code::E013,
+                    format!(
+                        "This script requires {} {} ({})",
+                        which,
+                        LANGUAGE,
+                        text.unwrap()
+                    ),
+                    &span,
+                    lexed,
+                )
+                .with_note(format!(
+                    "this is {} {}",
+                    LANGUAGE,
+                    env!("CARGO_PKG_VERSION")
+                )),
+            );
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
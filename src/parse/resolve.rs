@@ -0,0 +1,96 @@
+//! Resolution of the names in expressions to the slots of the variables they refer to,
+//! so that the runtime indexes the variables instead of looking them up by name
+
+use super::ScopeStack;
+#[allow(clippy::wildcard_imports)]
+use crate::exprs::{items::*, Expr};
+
+pub(super) trait Resolve {
+    /// Give each name the slot of the variable visible from the top of `stack`
+    /// (names not found are left unresolved, as reported by the type check)
+    fn resolve(&mut self, stack: &ScopeStack);
+}
+
+impl Resolve for Expr {
+    fn resolve(&mut self, stack: &ScopeStack) {
+        self.content.resolve(stack);
+    }
+}
+
+impl Resolve for Rel {
+    fn resolve(&mut self, stack: &ScopeStack) {
+        match self {
+            Self::Single(i) => i.resolve(stack),
+            Self::Equal(l, r, _)
+            | Self::NotEqual(l, r, _)
+            | Self::LessEqual(l, r, _)
+            | Self::GreaterEqual(l, r, _)
+            | Self::LessThan(l, r, _)
+            | Self::GreaterThan(l, r, _) => {
+                l.resolve(stack);
+                r.resolve(stack);
+            }
+        }
+    }
+}
+
+impl Resolve for AddSub {
+    fn resolve(&mut self, stack: &ScopeStack) {
+        match self {
+            Self::Single(i) => i.resolve(stack),
+            Self::Add(l, r, _) | Self::Sub(l, r, _) => {
+                l.resolve(stack);
+                r.resolve(stack);
+            }
+        }
+    }
+}
+
+impl Resolve for MulDiv {
+    fn resolve(&mut self, stack: &ScopeStack) {
+        match self {
+            Self::Single(i) => i.resolve(stack),
+            Self::Mul(l, r, _) | Self::Div(l, r, _) | Self::Mod(l, r, _) => {
+                l.resolve(stack);
+                r.resolve(stack);
+            }
+        }
+    }
+}
+
+impl Resolve for Node {
+    fn resolve(&mut self, stack: &ScopeStack) {
+        match self {
+            Self::Single(i) => i.resolve(stack),
+            Self::Plus(i, _) | Self::Minus(i, _) => i.resolve(stack),
+        }
+    }
+}
+
+impl Resolve for Core {
+    fn resolve(&mut self, stack: &ScopeStack) {
+        match self {
+            Self::Ident(name, slot) => {
+                if let Some(ti) = stack.get_type_info(name) {
+                    *slot = ti.slot;
+                }
+            }
+            Self::Paren(i) => i.resolve(stack),
+            Self::Array(elems, _) => {
+                for elem in elems {
+                    elem.resolve(stack);
+                }
+            }
+            Self::Index(array, index, _) => {
+                array.resolve(stack);
+                index.resolve(stack);
+            }
+            Self::Fmt(_, args, _) => {
+                for arg in args {
+                    arg.resolve(stack);
+                }
+            }
+            Self::Str(_) | Self::Num(_) | Self::True | Self::False => {}
+        }
+    }
+}
@@ -1,10 +1,64 @@
 use super::{ScopeStack, Type};
+#[allow(clippy::wildcard_imports)]
 use crate::exprs::{items::*, Expr};
+use crate::lex::Span;
 
 pub(super) enum TypeError {
     VarNotFound(String),
-    UnaryUndefined(Type),
-    BinaryUndefined(Type, Type),
+    UnaryUndefined {
+        op: &'static str,
+        ty: Type,
+        span: Span,
+    },
+    BinaryUndefined {
+        op: &'static str,
+        l: Type,
+        r: Type,
+        span: Span,
+    },
+    EmptyArray {
+        span: Span,
+    },
+    SubInArray {
+        span: Span,
+    },
+    ElementsDiffer {
+        first: Type,
+        found: Type,
+        span: Span,
+    },
+    IndexUndefined {
+        ty: Type,
+        index: Type,
+        span: Span,
+    },
+    FmtUndefined {
+        fmt: Fmt,
+        found: Vec<Type>,
+        span: Span,
+    },
+}
+
+/// Return the types of the args `fmt` takes
+pub(super) const fn signature(fmt: Fmt) -> &'static str {
+    match fmt {
+        Fmt::Pad => "(Num, Num)",
+        Fmt::Width => "(Num or Bool or Str, Num)",
+        Fmt::Hex | Fmt::Group | Fmt::Arg => "(Num)",
+        Fmt::Len => "(Str or array)",
+        Fmt::Substr => "(Str, Num, Num)",
+        Fmt::NowHour | Fmt::TodayWeekday => "()",
+        Fmt::DateStr => "() or (Str)",
+    }
+}
+
+fn binary_undefined(op: &'static str, l: Type, r: Type, span: &Span) -> TypeError {
+    TypeError::BinaryUndefined {
+        op,
+        l,
+        r,
+        span: span.clone(),
+    }
 }
 
 type Result = std::result::Result<Type, TypeError>;
@@ -23,19 +77,27 @@ impl TypeCheck for Rel {
     fn check_type(&self, stack: &ScopeStack) -> Result {
         match self {
             Self::Single(i) => i.check_type(stack),
-            Self::Equal(l, r)
-            | Self::NotEqual(l, r)
-            | Self::LessEqual(l, r)
-            | Self::GreaterEqual(l, r)
-            | Self::LessThan(l, r)
-            | Self::GreaterThan(l, r) => {
+            Self::Equal(l, r, span)
+            | Self::NotEqual(l, r, span)
+            | Self::LessEqual(l, r, span)
+            | Self::GreaterEqual(l, r, span)
+            | Self::LessThan(l, r, span)
+            | Self::GreaterThan(l, r, span) => {
                 let l_ty = l.check_type(stack)?;
                 let r_ty = r.check_type(stack)?;
 
                 if l_ty == r_ty {
                     Ok(Type::Bool)
                 } else {
-                    Err(TypeError::BinaryUndefined(l_ty, r_ty))
+                    let op = match self {
+                        Self::Equal(..) => "==",
+                        Self::NotEqual(..) => "!=",
+                        Self::LessEqual(..) => "<=",
+                        Self::GreaterEqual(..) => ">=",
+                        Self::LessThan(..) => "<",
+                        _ => ">",
+                    };
+                    Err(binary_undefined(op, l_ty, r_ty, span))
                 }
             }
         }
@@ -46,24 +108,24 @@ impl TypeCheck for AddSub {
     fn check_type(&self, stack: &ScopeStack) -> Result {
         match self {
             Self::Single(i) => i.check_type(stack),
-            Self::Add(l, r) => {
+            Self::Add(l, r, span) => {
                 let l_ty = l.check_type(stack)?;
                 let r_ty = r.check_type(stack)?;
 
                 if l_ty == r_ty && l_ty != Type::Sub {
                     Ok(l_ty)
                 } else {
-                    Err(TypeError::BinaryUndefined(l_ty, r_ty))
+                    Err(binary_undefined("+", l_ty, r_ty, span))
                 }
             }
-            Self::Sub(l, r) => {
+            Self::Sub(l, r, span) => {
                 let l_ty = l.check_type(stack)?;
                 let r_ty = r.check_type(stack)?;
 
                 if l_ty == r_ty && l_ty == Type::Num {
                     Ok(l_ty)
                 } else {
-                    Err(TypeError::BinaryUndefined(l_ty, r_ty))
+                    Err(binary_undefined("-", l_ty, r_ty, span))
                 }
             }
         }
@@ -74,24 +136,29 @@ impl TypeCheck for MulDiv {
     fn check_type(&self, stack: &ScopeStack) -> Result {
         match self {
             Self::Single(i) => i.check_type(stack),
-            Self::Mul(l, r) => {
+            Self::Mul(l, r, span) => {
                 let l_ty = l.check_type(stack)?;
                 let r_ty = r.check_type(stack)?;
 
                 match (&l_ty, &r_ty) {
                     (Type::Num, Type::Num) => Ok(Type::Num),
                     (Type::Num, Type::Str) | (Type::Str, Type::Num) => Ok(Type::Str),
-                    _ => Err(TypeError::BinaryUndefined(l_ty, r_ty)),
+                    _ => Err(binary_undefined("*", l_ty, r_ty, span)),
                 }
             }
-            Self::Div(l, r) | Self::Mod(l, r) => {
+            Self::Div(l, r, span) | Self::Mod(l, r, span) => {
                 let l_ty = l.check_type(stack)?;
                 let r_ty = r.check_type(stack)?;
 
                 if l_ty == r_ty && l_ty == Type::Num {
                     Ok(l_ty)
                 } else {
-                    Err(TypeError::BinaryUndefined(l_ty, r_ty))
+                    let op = if matches!(self, Self::Div(..)) {
+                        "/"
+                    } else {
+                        "%"
+                    };
+                    Err(binary_undefined(op, l_ty, r_ty, span))
                 }
             }
         }
@@ -102,12 +169,20 @@ impl TypeCheck for Node {
     fn check_type(&self, stack: &ScopeStack) -> Result {
         match self {
             Self::Single(i) => i.check_type(stack),
-            Self::Plus(i) | Self::Minus(i) => {
+            Self::Plus(i, span) | Self::Minus(i, span) => {
                 let ty = i.check_type(stack)?;
                 if matches!(ty, Type::Num | Type::Str) {
                     Ok(ty)
                 } else {
-                    Err(TypeError::UnaryUndefined(ty))
+                    Err(TypeError::UnaryUndefined {
+                        op: if matches!(self, Self::Plus(..)) {
+                            "+"
+                        } else {
+                            "-"
+                        },
+                        ty,
+                        span: span.clone(),
+                    })
                 }
             }
         }
@@ -115,16 +190,73 @@ impl TypeCheck for Node {
 }
 
 impl TypeCheck for Core {
+    #[allow(clippy::option_if_let_else)]
     fn check_type(&self, stack: &ScopeStack) -> Result {
         match self {
             Self::Str(_) => Ok(Type::Str),
             Self::Num(_) => Ok(Type::Num),
-            Self::Ident(name) => stack
+            Self::Ident(name, _) => stack
                 .get_type_info(name)
                 .map(|ti| ti.ty.clone())
                 .ok_or_else(|| TypeError::VarNotFound(name.clone())),
             Self::True | Self::False => Ok(Type::Bool),
             Self::Paren(i) => i.check_type(stack),
+            Self::Array(elems, span) => {
+                let mut types = elems.iter().map(|e| e.check_type(stack));
+                let Some(first) = types.next().transpose()? else {
+                    return Err(TypeError::EmptyArray { span: span.clone() });
+                };
+                // arrays are printed and compared, which subs cannot be
+                if first == Type::Sub {
+                    return Err(TypeError::SubInArray { span: span.clone() });
+                }
+                for ty in types {
+                    let ty = ty?;
+                    if ty != first {
+                        return Err(TypeError::ElementsDiffer {
+                            first,
+                            found: ty,
+                            span: span.clone(),
+                        });
+                    }
+                }
+                Ok(Type::Array(Box::new(first)))
+            }
+            Self::Index(array, index, span) => {
+                match (array.check_type(stack)?, index.check_type(stack)?) {
+                    (Type::Array(elem), Type::Num) => Ok(*elem),
+                    (ty, index) => Err(TypeError::IndexUndefined {
+                        ty,
+                        index,
+                        span: span.clone(),
+                    }),
+                }
+            }
+            Self::Fmt(fmt, args, span) => {
+                let found = args
+                    .iter()
+                    .map(|a| a.check_type(stack))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                let ret = match (fmt, found.as_slice()) {
+                    (Fmt::Pad, [Type::Num, Type::Num])
+                    | (Fmt::Hex | Fmt::Group | Fmt::Arg, [Type::Num])
+                    | (Fmt::Substr, [Type::Str, Type::Num, Type::Num])
+                    | (Fmt::DateStr, [] | [Type::Str]) => Some(Type::Str),
+                    (Fmt::Width, [ty, Type::Num]) if *ty != Type::Sub => Some(Type::Str),
+                    (Fmt::Len, [Type::Str | Type::Array(_)])
+                    | (Fmt::NowHour | Fmt::TodayWeekday, []) => Some(Type::Num),
+                    _ => None,
+                };
+                if let Some(ret) = ret {
+                    Ok(ret)
+                } else {
+                    Err(TypeError::FmtUndefined {
+                        fmt: *fmt,
+                        found,
+                        span: span.clone(),
+                    })
+                }
+            }
         }
     }
 }
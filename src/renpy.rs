@@ -0,0 +1,543 @@
+//! Export to Ren'Py scripts
+
+#[allow(clippy::wildcard_imports)]
+use crate::exprs::items::*;
+use crate::exprs::Expr;
+use crate::parse::{Align, Piece, Statement, Template, AST};
+use crate::types::Typed;
+use std::fmt::Write;
+
+/// Helpers keeping the semantics of novelang operators
+/// (integer division truncating towards zero, string inversion by characters)
+const PRELUDE: &str = "\
+init python:
+    import datetime
+    import unicodedata
+
+    def _nvl_graphemes(s):
+        # close to grapheme clusters: marks and joined characters stay with the one before
+        g = []
+        for c in s:
+            if g and (unicodedata.combining(c) or c in \"\\u200d\\ufe0f\" or g[-1].endswith(\"\\u200d\")):
+                g[-1] += c
+            else:
+                g.append(c)
+        return g
+
+    def _nvl_div(a, b):
+        q = abs(a) // abs(b)
+        return q if (a >= 0) == (b >= 0) else -q
+
+    def _nvl_rem(a, b):
+        return a - b * _nvl_div(a, b)
+
+    def _nvl_neg(v):
+        return \"\".join(reversed(_nvl_graphemes(v))) if isinstance(v, str) else -v
+
+    # arrays are values, so a copy is changed and other variables given the list keep it as it was
+    def _nvl_set(a, path, v):
+        a = list(a)
+        a[path[0]] = v if len(path) == 1 else _nvl_set(a[path[0]], path[1:], v)
+        return a
+
+    def _nvl_pad(n, w):
+        return str(n).zfill(w)
+
+    def _nvl_width(v, w):
+        s = str(v)
+        pad = \" \" * max(abs(w) - len(_nvl_graphemes(s)), 0)
+        return pad + s if w >= 0 else s + pad
+
+    def _nvl_hex(n):
+        return format(n, \"x\")
+
+    def _nvl_group(n):
+        return format(n, \",\")
+
+    def _nvl_len(v):
+        return len(v) if isinstance(v, list) else len(_nvl_graphemes(v))
+
+    def _nvl_substr(s, start, n):
+        start = max(start, 0)
+        return \"\".join(_nvl_graphemes(s)[start:start + max(n, 0)])
+
+    def _nvl_now_hour():
+        return datetime.datetime.now().hour
+
+    def _nvl_today_weekday():
+        return (datetime.date.today().weekday() + 1) % 7
+
+    def _nvl_date_str(format=\"%Y-%m-%d\"):
+        return datetime.datetime.now().strftime(format)
+
+    # a game is not given arguments as a script is
+    _argc = 0
+
+    def _nvl_arg(n):
+        return \"\"
+
+    # runtime errors are not caught, so these are never set
+    _error_message = \"\"
+    _error_line = 0
+
+screen _nvl_status(status):
+    frame:
+        xalign 0.5
+        text status
+";
+
+fn py_str(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Convert into a Python expression
+trait ToPython {
+    fn to_py(&self) -> String;
+}
+
+impl ToPython for Expr {
+    fn to_py(&self) -> String {
+        self.content.to_py()
+    }
+}
+
+impl ToPython for Rel {
+    fn to_py(&self) -> String {
+        let (l, op, r) = match self {
+            Self::Single(i) => return i.to_py(),
+            Self::Equal(l, r, _) => (l, "==", r),
+            Self::NotEqual(l, r, _) => (l, "!=", r),
+            Self::LessEqual(l, r, _) => (l, "<=", r),
+            Self::GreaterEqual(l, r, _) => (l, ">=", r),
+            Self::LessThan(l, r, _) => (l, "<", r),
+            Self::GreaterThan(l, r, _) => (l, ">", r),
+        };
+        format!("{} {} {}", l.to_py(), op, r.to_py())
+    }
+}
+
+impl ToPython for AddSub {
+    fn to_py(&self) -> String {
+        match self {
+            Self::Single(i) => i.to_py(),
+            Self::Add(l, r, _) => format!("({} + {})", l.to_py(), r.to_py()),
+            Self::Sub(l, r, _) => format!("({} - {})", l.to_py(), r.to_py()),
+        }
+    }
+}
+
+impl ToPython for MulDiv {
+    fn to_py(&self) -> String {
+        match self {
+            Self::Single(i) => i.to_py(),
+            Self::Mul(l, r, _) => format!("({} * {})", l.to_py(), r.to_py()),
+            Self::Div(l, r, _) => format!("_nvl_div({}, {})", l.to_py(), r.to_py()),
+            Self::Mod(l, r, _) => format!("_nvl_rem({}, {})", l.to_py(), r.to_py()),
+        }
+    }
+}
+
+impl ToPython for Node {
+    fn to_py(&self) -> String {
+        match self {
+            Self::Single(i) => i.to_py(),
+            Self::Plus(i, _) => i.to_py(),
+            Self::Minus(i, _) => format!("_nvl_neg({})", i.to_py()),
+        }
+    }
+}
+
+impl ToPython for Core {
+    fn to_py(&self) -> String {
+        match self {
+            Self::Str(s) => py_str(s),
+            Self::Num(n) => n.to_string(),
+            Self::Ident(i, _) => i.clone(),
+            Self::True => "True".to_owned(),
+            Self::False => "False".to_owned(),
+            Self::Paren(i) => format!("({})", i.to_py()),
+            Self::Array(elems, _) => format!(
+                "[{}]",
+                elems
+                    .iter()
+                    .map(ToPython::to_py)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::Index(array, index, _) => format!("{}[{}]", array.to_py(), index.to_py()),
+            Self::Fmt(fmt, args, _) => format!(
+                "_nvl_{}({})",
+                fmt.as_str(),
+                args.iter()
+                    .map(ToPython::to_py)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// Convert into a string interpolating the variables when shown (`[name]`)
+fn interpolated(template: &Template) -> String {
+    let mut s = String::new();
+    for piece in &template.0 {
+        match piece {
+            Piece::Text(t) => s.push_str(&t.replace('[', "[[").replace('{', "{{")),
+            Piece::Var(name, _) => write!(s, "[{name}]").unwrap(),
+        }
+    }
+    py_str(&s)
+}
+
+/// Return the literal if `expr` is just a string
+fn as_str_literal(expr: &Expr) -> Option<&str> {
+    if let Rel::Single(AddSub::Single(MulDiv::Single(Node::Single(Core::Str(s))))) = &*expr.content
+    {
+        Some(s)
+    } else {
+        None
+    }
+}
+
+struct Exporter<'a> {
+    stmts: &'a [Statement],
+    out: String,
+    /// Subroutines found, to be written as labels: name and range of the body
+    subs: Vec<(String, usize, usize)>,
+    /// Number of statements written in each open block (to put `pass` in empty ones)
+    /// and whether it is a loop
+    blocks: Vec<(usize, bool)>,
+    /// Statements which have no counterpart
+    unsupported: Vec<&'static str>,
+    /// Whether the last Print didn't break the line
+    extend: bool,
+}
+
+impl Exporter<'_> {
+    fn line(&mut self, s: &str) {
+        if let Some((n, _)) = self.blocks.last_mut() {
+            *n += 1;
+        }
+        self.out.push_str(&"    ".repeat(self.blocks.len() + 1));
+        self.out.push_str(s);
+        self.out.push('\n');
+    }
+
+    fn open(&mut self, s: &str, is_loop: bool) {
+        self.line(s);
+        self.blocks.push((0, is_loop));
+    }
+
+    fn close(&mut self) {
+        if self.blocks.last().is_some_and(|(n, _)| *n == 0) {
+            self.line("pass");
+        }
+        self.blocks.pop();
+    }
+
+    /// Write statements in `from..to` as the body of a label
+    #[allow(clippy::option_if_let_else, clippy::too_many_lines)]
+    fn body(&mut self, from: usize, to: usize) {
+        let mut i = from;
+        while i < to {
+            match &self.stmts[i] {
+                Statement::Print {
+                    args,
+                    sep,
+                    nobreak,
+                    align,
+                    speaker,
+                } => {
+                    let text = if let Some(lits) =
+                        args.iter().map(as_str_literal).collect::<Option<Vec<_>>>()
+                    {
+                        lits.join(sep)
+                    } else {
+                        let vals: Vec<_> =
+                            args.iter().map(|a| format!("str({})", a.to_py())).collect();
+                        self.line(&format!(
+                            "$ _nvl_say = {}.join([{}])",
+                            py_str(sep),
+                            vals.join(", ")
+                        ));
+                        "[_nvl_say!q]".to_owned()
+                    };
+                    // a line left open is shown without waiting and continued by `extend`
+                    let text = if *nobreak { text + "{nw}" } else { text };
+                    // Ren'Py has a character for centered text, but none for the right
+                    let say = if self.extend {
+                        "extend ".to_owned()
+                    } else if *align == Align::Center {
+                        "centered ".to_owned()
+                    } else if let Some(speaker) = speaker {
+                        format!("{} ", py_str(speaker))
+                    } else {
+                        String::new()
+                    };
+                    self.line(&format!("{}{}", say, py_str(&text)));
+                    self.extend = *nobreak;
+                }
+                Statement::Sub {
+                    name,
+                    offset_to_end,
+                    ..
+                } => {
+                    self.subs.push((name.clone(), i + 1, i + offset_to_end));
+                    i += offset_to_end + 1;
+                    continue;
+                }
+                Statement::Call { name, into, .. } => {
+                    self.line(&format!("call {name}"));
+                    if let Some((var, _)) = into {
+                        self.line(&format!("$ {var} = _return"));
+                    }
+                }
+                Statement::While { cond, .. } => {
+                    self.open(&format!("while {}:", cond.to_py()), true);
+                }
+                Statement::Modify {
+                    name,
+                    indices,
+                    expr,
+                    ..
+                } if !indices.is_empty() => {
+                    let indices: Vec<_> = indices.iter().map(ToPython::to_py).collect();
+                    self.line(&format!(
+                        "$ {} = _nvl_set({}, [{}], {})",
+                        name,
+                        name,
+                        indices.join(", "),
+                        expr.to_py()
+                    ));
+                }
+                Statement::Let { name, init, .. }
+                | Statement::Modify {
+                    name, expr: init, ..
+                } => {
+                    self.line(&format!("$ {} = {}", name, init.to_py()));
+                }
+                Statement::If { cond, .. } => self.open(&format!("if {}:", cond.to_py()), false),
+                Statement::ElIf { cond, .. } => {
+                    self.close();
+                    self.open(&format!("elif {}:", cond.to_py()), false);
+                }
+                // the subject is kept in a variable for each depth, so that switches can be nested
+                Statement::Switch { subject, .. } => {
+                    let var = format!("_nvl_switch{}", self.blocks.len());
+                    self.line(&format!("$ {} = {}", var, subject.to_py()));
+                    self.open("if False:", false);
+                }
+                Statement::Case { value, .. } => {
+                    self.close();
+                    let var = format!("_nvl_switch{}", self.blocks.len());
+                    self.open(&format!("elif {} == {}:", var, value.to_py()), false);
+                }
+                Statement::Else { .. } | Statement::Recover { .. } => {
+                    self.close();
+                    self.open("else:", false);
+                }
+                // errors cannot be caught in the script language of Ren'Py,
+                // so Recover becomes an Else which is never taken
+                Statement::Try { .. } => {
+                    self.unsupported.push("try");
+                    self.open("if True: # unsupported: try", false);
+                }
+                Statement::End => self.close(),
+                Statement::Input {
+                    prompt,
+                    name,
+                    as_num,
+                    mask,
+                    valid,
+                    invalid_msg,
+                    ..
+                } => {
+                    let prompt = py_str(prompt.as_deref().unwrap_or(""));
+                    // only the length can be kept, as `allow` doesn't care about positions
+                    let input = match mask {
+                        Some(mask) => {
+                            format!("renpy.input({}, length={})", prompt, mask.chars().count())
+                        }
+                        None => format!("renpy.input({prompt})"),
+                    };
+                    let read = if *as_num {
+                        format!("$ {name} = int({input})")
+                    } else {
+                        format!("$ {name} = {input}")
+                    };
+                    self.line(&read);
+                    if !valid.is_empty() {
+                        let conds: Vec<_> = valid.iter().map(ToPython::to_py).collect();
+                        self.open(&format!("while not ({}):", conds.join(" and ")), false);
+                        if let Some(msg) = invalid_msg {
+                            self.line(&py_str(msg));
+                        }
+                        self.line(&read);
+                        self.close();
+                    }
+                }
+                Statement::Roll {
+                    count, face, name, ..
+                } => self.line(&format!(
+                    "$ {} = sum(renpy.random.randint(1, {}) for _ in range({}))",
+                    name,
+                    face.to_py(),
+                    count.to_py()
+                )),
+                Statement::Halt { code: None } => self.line("$ renpy.full_restart()"),
+                Statement::Halt { code: Some(code) } => {
+                    self.line(&format!("$ renpy.quit(status={})", code.to_py()));
+                }
+                Statement::Break => {
+                    if self.blocks.iter().any(|(_, is_loop)| *is_loop) {
+                        // the script language of Ren'Py has no way to leave a loop
+                        self.unsupported.push("break");
+                        self.line("pass # unsupported: break");
+                    } else {
+                        // leaving the subroutine
+                        self.line("return");
+                    }
+                }
+                Statement::Return { value: None } => self.line("return"),
+                Statement::Return { value: Some(value) } => {
+                    self.line(&format!("return {}", value.to_py()));
+                }
+                Statement::Scene { title } => self.line(&format!("# scene: {title}")),
+                Statement::Log { args } => {
+                    let vals: Vec<_> = args.iter().map(|a| format!("str({})", a.to_py())).collect();
+                    self.line(&format!("$ renpy.log(\" \".join([{}]))", vals.join(", ")));
+                }
+                Statement::Breakpoint | Statement::Ill => {}
+                // rollback of Ren'Py is up to the player, and cannot be started by the script
+                Statement::Checkpoint => self.line("pass # checkpoint"),
+                Statement::OnKey { key, name, .. } => {
+                    // keys of Ren'Py are bound with keysyms such as "K_m", so only letters are kept
+                    if key.chars().count() == 1 && key.chars().all(|c| c.is_ascii_alphabetic()) {
+                        self.line(&format!(
+                            "$ config.underlay.append(renpy.Keymap(K_{}=Function(renpy.call_in_new_context, {})))",
+                            key.to_ascii_lowercase(),
+                            py_str(name)
+                        ));
+                    } else {
+                        self.unsupported.push("onkey");
+                        self.line("pass # unsupported: onkey");
+                    }
+                }
+                Statement::After { .. } => {
+                    self.unsupported.push("after");
+                    self.line("pass # unsupported: after");
+                }
+                Statement::Hook { .. } => {
+                    self.unsupported.push("hook");
+                    self.line("pass # unsupported: hook");
+                }
+                // errors of Ren'Py are shown by its own screen
+                Statement::OnError { .. } => {
+                    self.unsupported.push("onerror");
+                    self.line("pass # unsupported: onerror");
+                }
+                Statement::Restore => {
+                    self.unsupported.push("restore checkpoint");
+                    self.line("pass # unsupported: restore checkpoint");
+                }
+                // the text window has no room for empty lines, but an open line ends here
+                Statement::Newline { .. } => self.extend = false,
+                // the screen is shown again whenever the variables change
+                Statement::Status { template } if template.0.is_empty() => {
+                    self.line("hide screen _nvl_status");
+                }
+                Statement::Status { template } => {
+                    self.line(&format!(
+                        "show screen _nvl_status({})",
+                        interpolated(template)
+                    ));
+                }
+            }
+            i += 1;
+        }
+    }
+
+    fn label(&mut self, name: &str, from: usize, to: usize) {
+        writeln!(self.out, "\nlabel {name}:").unwrap();
+        self.body(from, to);
+        self.line("return");
+    }
+}
+
+/// Convert `prog` into a Ren'Py script.
+/// The names of statements which could not be converted are returned along with it.
+pub fn export(prog: &AST, defines: &[(String, Typed)]) -> (String, Vec<&'static str>) {
+    let mut ex = Exporter {
+        stmts: &prog.stmts,
+        out: String::from(PRELUDE),
+        subs: Vec::new(),
+        blocks: Vec::new(),
+        unsupported: Vec::new(),
+        extend: false,
+    };
+
+    for (name, val) in defines {
+        let val = match val {
+            Typed::Num(n) => n.to_string(),
+            Typed::Bool(true) => "True".to_owned(),
+            Typed::Bool(false) => "False".to_owned(),
+            Typed::Str(s) => py_str(s),
+            Typed::Array(_) | Typed::Sub(_) => unreachable!(),
+        };
+        write!(ex.out, "\ndefine {name} = {val}").unwrap();
+    }
+
+    // variables initialized with a literal at the top level become defaults
+    let mut depth = 0;
+    let mut defaults = Vec::new();
+    for stmt in &prog.stmts {
+        match stmt {
+            Statement::Sub { .. }
+            | Statement::While { .. }
+            | Statement::If { .. }
+            | Statement::Switch { .. }
+            | Statement::Try { .. } => depth += 1,
+            Statement::End => depth -= 1,
+            _ => {}
+        }
+        if let (0, Statement::Let { name, init, .. }) = (depth, stmt) {
+            if defaults.contains(name) {
+                continue;
+            }
+            defaults.push(name.clone());
+            if let Rel::Single(AddSub::Single(MulDiv::Single(Node::Single(
+                c @ (Core::Str(_) | Core::Num(_) | Core::True | Core::False),
+            )))) = &*init.content
+            {
+                write!(ex.out, "\ndefault {} = {}", name, c.to_py()).unwrap();
+            }
+        }
+    }
+    // the other globals hold the zero value of their type until reached
+    for (slot, zero) in &prog.globals {
+        let name = &prog.names[&slot.scope][slot.index];
+        if defaults.contains(name) {
+            continue;
+        }
+        defaults.push(name.clone());
+        let zero = match zero {
+            Typed::Num(_) => "0".to_owned(),
+            Typed::Bool(_) => "False".to_owned(),
+            Typed::Str(_) => "\"\"".to_owned(),
+            Typed::Array(_) => "[]".to_owned(),
+            Typed::Sub(_) => unreachable!("globals cannot hold subs"),
+        };
+        write!(ex.out, "\ndefault {name} = {zero}").unwrap();
+    }
+    ex.out.push('\n');
+
+    ex.label("start", 1, prog.stmts.len());
+    let mut done = 0;
+    while done < ex.subs.len() {
+        let (name, from, to) = ex.subs[done].clone();
+        ex.label(&name, from, to);
+        done += 1;
+    }
+
+    (ex.out, ex.unsupported)
+}
@@ -1,305 +1,830 @@
+mod animation;
+mod bidi;
+mod render;
 mod variable;
+mod vertical;
 
 use crate::die;
 use crate::exprs;
-use crate::parse::{Statement, AST};
-use crate::types::{IntType, Typed};
+use crate::parse::{self, Align, Piece, PrintHook, Statement, Template, AST};
+use crate::types::{IntType, Slot, Typed};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use variable::{ModifyError, Variable};
 
-type VarTable = std::collections::HashMap<String, Variable>;
+/// Variables of a block by the indices of their slots (None until declared)
+type VarTable = Vec<Option<Variable>>;
+
+/// Return a runtime error from the current function
+macro_rules! bail {
+    ($( $x:expr ),*) => {
+        return Err(format!($($x,)*))
+    };
+}
 
 /// Represents a scope
+#[derive(Clone)]
 struct Scope {
     kind: ScopeKind,
     ret_idx: usize,
+    /// Index of the statement opening the block, which the slots of its variables refer to
+    id: usize,
     vars: VarTable,
 }
 
 impl Scope {
-    fn new(kind: ScopeKind, ret_idx: usize) -> Self {
+    const fn new(kind: ScopeKind, ret_idx: usize, id: usize) -> Self {
         Self {
             kind,
             ret_idx,
+            id,
             vars: VarTable::new(),
         }
     }
 }
 
+/// Slot of an internal variable
+fn internal(name: &str) -> Slot {
+    Slot {
+        scope: 0,
+        index: parse::INTERNALS.iter().position(|n| *n == name).unwrap(),
+    }
+}
+
+#[derive(Clone)]
 enum ScopeKind {
     Branch,
     Loop,
     Sub,
+    /// Sub called by a key while waiting for Enter
+    OnKey,
+    /// Sub called by a runtime error
+    OnError,
+    /// Body of a Try, returning to the Recover on an error
+    Try,
+    /// Sub called around a Print by Hook
+    Hook,
+    /// Arms of a Switch, with the value compared by its Cases
+    Switch(Typed),
+    /// Sub called by `call ... into`, with the variable receiving its return value
+    CallInto(String, Slot),
+}
+
+impl ScopeKind {
+    /// Whether the scope is of a called sub, which Return leaves
+    const fn is_sub(&self) -> bool {
+        matches!(
+            self,
+            Self::Sub | Self::CallInto(..) | Self::OnKey | Self::OnError | Self::Hook
+        )
+    }
+}
+
+/// Options given from the command line
+#[allow(clippy::struct_excessive_bools)] // independent options
+pub struct Config {
+    /// Seed for the random number generator (random if not specified)
+    pub seed: Option<u64>,
+    /// Date and time seen by the script instead of the current ones
+    pub fixed_time: Option<exprs::DateTime>,
+    /// Ignore `_wait`
+    pub no_wait: bool,
+    /// Stop running when this is set (used in watch mode)
+    pub stop: Option<std::sync::Arc<std::sync::atomic::AtomicBool>>,
+    /// Inputs to use instead of reading stdin, one per Input
+    pub replay: Option<Vec<String>>,
+    /// Write the playthrough as a Markdown document instead of interacting
+    pub markdown: bool,
+    /// Run without printing or waiting until the scene with this title begins
+    pub skip_to: Option<String>,
+    /// Read-only variables given from the command line
+    pub defines: Vec<(String, Typed)>,
+    /// Arguments given after `--`, seen as `_argc` and `arg(n)`
+    pub args: Vec<String>,
+    /// Sub to call first instead of running from the top
+    pub entry: Option<String>,
+    /// Log executed instructions
+    pub trace: Option<Output>,
+    /// Where to write Log (discarded if None)
+    pub log: Option<Output>,
+    /// Developer mode (stop at Breakpoint)
+    pub dev: bool,
+    /// Write the variables in JSON when the program ends
+    pub dump_vars: Option<Output>,
+    /// Colors of the names of speakers
+    pub speaker_colors: std::collections::HashMap<String, crossterm::style::Color>,
+    /// Width of the box the names of speakers are padded to
+    pub name_width: Option<usize>,
+    /// Milliseconds after "Proceed with Enter" is shown during which Enter is ignored
+    pub wait_cooldown: Option<u64>,
+    /// Put right-to-left text in the visual order before printing
+    pub reorder_bidi: bool,
+    /// Initial value of `_vertical`
+    pub vertical: bool,
+    /// Calls which may be nested before a stack overflow
+    pub max_call_depth: Option<usize>,
+}
+
+/// Destination of developer output
+pub enum Output {
+    Stderr,
+    File(std::path::PathBuf),
+}
+
+impl Output {
+    fn open(&self) -> Box<dyn std::io::Write> {
+        match self {
+            Self::File(path) => Box::new(std::io::BufWriter::new(
+                std::fs::File::create(path).unwrap_or_else(|e| {
+                    die!("Error: failed to create \"{}\" : {}", path.display(), e);
+                }),
+            )),
+            Self::Stderr => Box::new(std::io::stderr()),
+        }
+    }
 }
 
-/// Represents the store for runtime state
-pub struct Runtime {
-    stack: Vec<Scope>,
+/// The state saved by Checkpoint (the random number generator goes on, so that retries differ)
+struct Snapshot {
+    /// Index of the Checkpoint
+    ip: usize,
+    call_stack: Vec<Scope>,
+    sub_depth: usize,
     globals: VarTable,
-    internals: VarTable,
+    scene: Option<String>,
+    status: Template,
+    if_eval: bool,
+    breaking: bool,
+    /// Handlers registered by `after`, `onkey`, `onerror` and `hook`
+    timers: Vec<(std::time::Instant, String, usize)>,
+    keys: Vec<(String, String, usize)>,
+    error_handler: Option<(String, usize)>,
+    print_hooks: Vec<(PrintHook, String, usize)>,
 }
 
-impl crate::exprs::VarsMap for Runtime {
-    fn get(&self, name: &str) -> Option<&Typed> {
-        self.get_var(name).map(Variable::get)
+/// The state of a running script
+pub struct Machine<'a> {
+    /// Index of the statement to execute
+    ip: usize,
+    call_stack: Vec<Scope>,
+    /// Number of the scopes of called subs in `call_stack`, limited by `max_call_depth`
+    sub_depth: usize,
+    /// Variables at the top level, after the internal ones and the ones defined on the command line
+    globals: VarTable,
+    rng: rand::rngs::StdRng,
+    seed: u64,
+    /// Whether the condition of an If or Else-If was not met and the next branch is to be tried
+    if_eval: bool,
+    /// Whether a Break was fired and the While it jumped to has to be left
+    breaking: bool,
+    /// Where the output goes
+    mode: render::Mode,
+    /// Whether the last Print didn't break the line
+    line_open: bool,
+    /// Page of vertical text being written
+    page: vertical::Page,
+    /// Template of the status line and the text shown last
+    status: Template,
+    status_shown: Option<String>,
+    /// Height of the terminal whose last row is kept for the status line
+    status_rows: Option<u16>,
+    /// Title of the scene being played, shown on the status line of the terminal
+    scene: Option<String>,
+    /// Scene chosen from the jump menu of `--dev`, to fast-forward to from the beginning
+    jump_to: Option<String>,
+    checkpoint: Option<Snapshot>,
+    /// When to call subs scheduled by After, with their names and indices
+    timers: Vec<(std::time::Instant, String, usize)>,
+    /// Keys bound by `onkey`, with the names and indices of the subs
+    keys: Vec<(String, String, usize)>,
+    /// Sub registered by `onerror`, with its name and index
+    error_handler: Option<(String, usize)>,
+    /// Subs registered by `hook`, with their names and indices
+    print_hooks: Vec<(PrintHook, String, usize)>,
+    /// Print whose `before_print` sub has been called, to be shown when it returns
+    hooked_print: Option<usize>,
+    /// Print that has been shown and only waits for Enter again, after the sub of a key returned
+    resume_wait: Option<usize>,
+    /// Exit status given by Halt
+    exit_status: i32,
+    replay: Option<std::collections::VecDeque<String>>,
+    skip_to: Option<String>,
+    trace: Option<Box<dyn std::io::Write>>,
+    log: Option<Box<dyn std::io::Write>>,
+    started: std::time::Instant,
+    config: &'a Config,
+}
+
+impl crate::exprs::VarsMap for Machine<'_> {
+    fn get(&self, slot: Slot) -> Option<&Typed> {
+        self.var(slot).map(Variable::get)
+    }
+
+    fn now(&self) -> Option<exprs::DateTime> {
+        self.config.fixed_time.or_else(exprs::DateTime::now)
+    }
+
+    fn args(&self) -> &[String] {
+        &self.config.args
     }
 }
 
-impl Runtime {
-    fn new() -> Self {
+impl<'a> Machine<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        use rand::SeedableRng;
+
         // internal variables
         // - "_wait": whether wait is enabled
+        // - "_vertical": whether Print writes vertically
+        // - "_argc": number of the arguments given after `--`
+        // - "_error_message", "_error_line": the runtime error passed to the sub of `onerror`
+        // - "_print_length": length of the text passed to the subs of `hook`
+        // and the variables defined on the command line
 
-        let internals = {
-            let mut vt = VarTable::new();
-            vt.insert("_wait".to_owned(), Variable::new_mut(Typed::Bool(false)));
-            vt
-        };
+        // in the slots given by the parser
+        let mut globals: VarTable = parse::INTERNALS
+            .iter()
+            .map(|name| {
+                Some(match *name {
+                    "_wait" => Variable::new_mut(Typed::Bool(false)),
+                    "_vertical" => Variable::new_mut(Typed::Bool(config.vertical)),
+                    "_argc" => Variable::new(Typed::Num(
+                        std::convert::TryFrom::try_from(config.args.len()).unwrap_or(IntType::MAX),
+                    )),
+                    "_error_message" => Variable::new(Typed::Str(String::new())),
+                    _ => Variable::new(Typed::Num(0)),
+                })
+            })
+            .collect();
+        globals.extend(
+            parse::defines_in_effect(&config.defines)
+                .map(|(_, val)| Some(Variable::new(val.clone()))),
+        );
+
+        // the seed is always decided here, so that it can be reported
+        let seed = config.seed.unwrap_or_else(rand::random);
 
         Self {
-            stack: vec![],
-            globals: VarTable::new(),
-            internals,
+            ip: 1, // index 0 is reserved (unreachable)
+            call_stack: vec![],
+            sub_depth: 0,
+            globals,
+            rng: rand::rngs::StdRng::seed_from_u64(seed),
+            seed,
+            if_eval: false,
+            breaking: false,
+            mode: render::Mode::of_stdout(),
+            line_open: false,
+            page: vertical::Page::default(),
+            status: Template(Vec::new()),
+            status_shown: None,
+            status_rows: None,
+            scene: None,
+            jump_to: None,
+            checkpoint: None,
+            timers: Vec::new(),
+            keys: Vec::new(),
+            error_handler: None,
+            print_hooks: vec![],
+            hooked_print: None,
+            resume_wait: None,
+            exit_status: 0,
+            replay: config.replay.clone().map(Into::into),
+            skip_to: config.skip_to.clone(),
+            trace: config.trace.as_ref().map(Output::open),
+            log: config.log.as_ref().map(Output::open),
+            started: std::time::Instant::now(),
+            config,
         }
     }
 
-    /// Declare a variable
-    /// Aborts when the variable is already declared in the scope
-    fn decl_var(&mut self, name: &str, val: Variable) {
-        let var_table = if self.stack.is_empty() {
+    /// Declare a variable in the current scope
+    /// Fails when the variable is already declared in the scope
+    fn decl_var(&mut self, name: &str, slot: Slot, val: Variable) -> Result<(), String> {
+        let var_table = if self.call_stack.is_empty() {
             &mut self.globals
         } else {
-            &mut self.stack.last_mut().unwrap().vars
+            &mut self.call_stack.last_mut().unwrap().vars
         };
-        if var_table.insert(name.to_owned(), val).is_some() {
-            die!("Runtime error: variable {} is already declared", name);
+        if var_table.len() <= slot.index {
+            var_table.resize_with(slot.index + 1, || None);
+        }
+        if var_table[slot.index].replace(val).is_some() {
+            bail!("variable {} is already declared", name);
         }
+        Ok(())
     }
 
-    /// Modify a variable
-    /// Aborts on error (the variable doesn't exists, differ in type, or is immutable)
-    fn modify_var(&mut self, name: &str, val: Typed) {
+    /// Declare a variable at the top level from any block, replacing the one declared before
+    fn decl_global(&mut self, slot: Slot, val: Variable) {
+        if self.globals.len() <= slot.index {
+            self.globals.resize_with(slot.index + 1, || None);
+        }
+        self.globals[slot.index] = Some(val);
+    }
+
+    /// Declare every global with the zero value of its type,
+    /// so that it can be used even if its statement is never reached
+    fn hoist_globals(&mut self, prog: &AST) {
+        for (slot, zero) in &prog.globals {
+            // whether it may be modified has been checked by the parser
+            self.decl_global(*slot, Variable::new_mut(zero.clone()));
+        }
+    }
+
+    /// Modify a variable, or its element at `indices`
+    /// Fails on error (the variable doesn't exists, differ in type, is immutable, or the index is out of range)
+    fn modify_var(
+        &mut self,
+        name: &str,
+        slot: Slot,
+        indices: &[IntType],
+        val: Typed,
+    ) -> Result<(), String> {
         // no check for internals as already done in the parse phase.
 
-        let var = self.get_var_mut(name).unwrap_or_else(|| {
-            die!("Runtime error: variable was not found");
-        });
+        let Some(var) = self.var_mut(slot) else {
+            bail!("variable was not found");
+        };
 
-        match var.modify(val) {
-            Ok(_) => {}
+        match var.modify(indices, val) {
+            Ok(_) => Ok(()),
             Err(ModifyError::TypeDiffers) => {
-                die!("Runtime error: Type differs");
+                bail!("Type differs");
             }
             Err(ModifyError::Immutable) => {
-                die!("Runtime error: variable {} is immutable", name);
+                bail!("variable {} is immutable", name);
+            }
+            Err(ModifyError::OutOfRange { index, len }) => {
+                bail!(
+                    "index {} is out of range for {} of {} element(s)",
+                    index,
+                    name,
+                    len
+                );
             }
         }
     }
 
     /// Pop the current scope
     fn pop(&mut self) -> Option<Scope> {
-        self.stack.pop()
+        let scope = self.call_stack.pop()?;
+        if scope.kind.is_sub() {
+            self.sub_depth -= 1;
+        }
+        Some(scope)
     }
 
-    /// Push a new scope
-    fn push(&mut self, kind: ScopeKind, ret_idx: usize) {
-        self.stack.push(Scope::new(kind, ret_idx))
+    /// Push a new scope for the block opened by the statement at `id`
+    fn push(&mut self, kind: ScopeKind, ret_idx: usize, id: usize) {
+        if kind.is_sub() {
+            self.sub_depth += 1;
+        }
+        self.call_stack.push(Scope::new(kind, ret_idx, id));
     }
 
-    fn vars_iter(&self) -> impl Iterator<Item = &VarTable> {
-        use std::iter::once;
-        once(&self.internals)
-            .chain(self.stack.iter().map(|f| &f.vars).rev())
-            .chain(once(&self.globals))
+    /// Leave the scopes from the `pos`-th one on, and return the outermost of them
+    fn unwind(&mut self, pos: usize) -> Option<Scope> {
+        let left = self.call_stack.split_off(pos);
+        self.sub_depth -= left.iter().filter(|s| s.kind.is_sub()).count();
+        left.into_iter().next()
     }
 
-    fn vars_iter_mut(&mut self) -> impl Iterator<Item = &mut VarTable> {
-        use std::iter::once;
-        once(&mut self.internals)
-            .chain(self.stack.iter_mut().map(|f| &mut f.vars).rev())
-            .chain(once(&mut self.globals))
+    /// Get the variable in `slot`, in the innermost scope of its block
+    fn var(&self, slot: Slot) -> Option<&Variable> {
+        let var_table = if slot.scope == 0 {
+            &self.globals
+        } else {
+            &self
+                .call_stack
+                .iter()
+                .rev()
+                .find(|s| s.id == slot.scope)?
+                .vars
+        };
+        var_table.get(slot.index)?.as_ref()
     }
 
-    // get the highest variable in the stack with the specified name
-    pub fn get_var(&self, name: &str) -> Option<&Variable> {
-        self.vars_iter() // Iterator<Item = &mut VarTable>
-            .map(|t| t.get(name)) // Iterator<Item = Option<&Variable>>
-            .find(Option::is_some) // Option<Option<&Variable>>
-            .flatten() // Option<&Variable>
+    fn var_mut(&mut self, slot: Slot) -> Option<&mut Variable> {
+        let var_table = if slot.scope == 0 {
+            &mut self.globals
+        } else {
+            &mut self
+                .call_stack
+                .iter_mut()
+                .rev()
+                .find(|s| s.id == slot.scope)?
+                .vars
+        };
+        var_table.get_mut(slot.index)?.as_mut()
     }
 
-    fn get_var_mut(&mut self, name: &str) -> Option<&mut Variable> {
-        self.vars_iter_mut()
-            .map(|t| t.get_mut(name))
-            .find(Option::is_some)
-            .flatten()
+    /// Set an internal variable, which cannot be modified by the script
+    fn set_internal(&mut self, name: &str, val: Typed) {
+        self.globals[internal(name).index] = Some(Variable::new(val));
+    }
+
+    /// The declared variables with their names, by scope from the innermost
+    /// (with the internal ones and the ones defined on the command line if `internals`)
+    fn named_vars<'p>(&self, prog: &'p AST, internals: bool) -> Vec<Vec<(&'p str, &Variable)>> {
+        let reserved = if internals {
+            0
+        } else {
+            parse::INTERNALS.len() + parse::defines_in_effect(&self.config.defines).count()
+        };
+        self.call_stack
+            .iter()
+            .rev()
+            .map(|s| (s.id, &s.vars, 0))
+            .chain(std::iter::once((0, &self.globals, reserved)))
+            .map(|(id, vars, skip)| {
+                prog.names.get(&id).map_or_else(Vec::new, |names| {
+                    names
+                        .iter()
+                        .zip(vars)
+                        .skip(skip)
+                        .filter_map(|(name, var)| Some((name.as_str(), var.as_ref()?)))
+                        .collect()
+                })
+            })
+            .collect()
     }
 
     fn eval(&self, expr: &exprs::Expr) -> Result<Typed, exprs::EvalError> {
         expr.eval_on(self)
     }
-}
 
-fn exec_print(idx: usize, runtime: &Runtime, wait: bool, args: &[exprs::Expr]) {
-    use std::io::Write;
-    let stdout = std::io::stdout();
-    let mut lock = stdout.lock();
+    fn wait_enabled(&self) -> bool {
+        !self.config.markdown
+            && !self.config.no_wait
+            && self.skip_to.is_none()
+            && matches!(
+                self.var(internal("_wait")).map(Variable::get),
+                Some(Typed::Bool(true))
+            )
+    }
 
-    write!(lock, "{:04} :", idx).unwrap();
-    for arg in args {
-        let val = arg.eval_on(runtime).unwrap_or_else(|e| {
-            die!("Runtime error: Failed to eval arg of Print: {:?}", e);
-        });
-        match val {
-            Typed::Num(n) => write!(lock, " {}", n),
-            Typed::Bool(b) => write!(lock, " {}", b),
-            Typed::Str(s) => write!(lock, " {}", s),
-            _ => unimplemented!(),
+    fn vertical_enabled(&self) -> bool {
+        !self.config.markdown
+            && matches!(
+                self.var(internal("_vertical")).map(Variable::get),
+                Some(Typed::Bool(true))
+            )
+    }
+
+    /// Log an executed instruction if tracing
+    fn trace(&mut self, idx: usize, kind: &str, detail: std::fmt::Arguments<'_>) {
+        use std::io::Write;
+        if let Some(w) = &mut self.trace {
+            let line = format!("{idx:04} {kind:<6} {detail}");
+            writeln!(w, "{}", line.trim_end()).unwrap_or_else(|e| {
+                die!("Error: failed to write trace : {}", e);
+            });
         }
-        .unwrap();
     }
-    writeln!(lock).unwrap();
-    let _ = lock.flush();
 
-    if wait {
-        write!(lock, "[Proceed with Enter⏎ ]").unwrap();
-        let _ = lock.flush();
-        let _ = read_line_from_stdin();
-        {
-            use crossterm::cursor;
-            use crossterm::execute;
-            use crossterm::terminal;
-            execute!(
-                lock,
-                cursor::MoveToPreviousLine(1),
-                terminal::Clear(terminal::ClearType::CurrentLine)
-            )
-            .unwrap();
+    /// Write a message of Log with the time since the start and the row in the source
+    fn write_log(&mut self, row: usize, msg: &str) {
+        use std::io::Write;
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if let Some(w) = &mut self.log {
+            writeln!(w, "[{elapsed:9.3}s] line {row}: {msg}").unwrap_or_else(|e| {
+                die!("Error: failed to write log : {}", e);
+            });
         }
     }
-}
 
-fn get_int_input(prompt: Option<&str>) -> IntType {
-    use std::io::Write;
-    let stdout = std::io::stdout();
-    let mut lock = stdout.lock();
-    loop {
-        write!(lock, "{} > ", prompt.unwrap_or("Provide an integer")).unwrap();
-        let _ = lock.flush();
-        if let Ok(i) = read_line_from_stdin().parse() {
-            return i;
+    /// Write the visible variables, the index of the last statement and the seed in JSON
+    /// (the index is past the last statement if the script ran to the end)
+    fn dump_vars(&self, prog: &AST, out: &Output, error: Option<&String>) -> std::io::Result<()> {
+        use std::io::Write;
+        let mut vars = std::collections::BTreeMap::new();
+        // innermost first, as they hide the outer ones
+        for scope in self.named_vars(prog, false) {
+            for (name, var) in scope {
+                if !matches!(var.get(), Typed::Sub(_)) {
+                    vars.entry(name).or_insert_with(|| var.get());
+                }
+            }
         }
-        writeln!(lock, "!! Provided input is invalid").unwrap();
-        let _ = lock.flush();
+
+        let mut w = out.open();
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"index\": {},", self.ip)?;
+        writeln!(w, "  \"seed\": {},", self.seed)?;
+        match error {
+            Some(e) => writeln!(w, "  \"error\": {},", json_str(e))?,
+            None => writeln!(w, "  \"error\": null,")?,
+        }
+        write!(w, "  \"variables\": {{")?;
+        for (n, (name, val)) in vars.iter().enumerate() {
+            let val = json_value(val);
+            let sep = if n == 0 { "" } else { "," };
+            write!(w, "{}\n    {}: {}", sep, json_str(name), val)?;
+        }
+        if !vars.is_empty() {
+            write!(w, "\n  ")?;
+        }
+        writeln!(w, "}}")?;
+        writeln!(w, "}}")?;
+        w.flush()
     }
-}
 
-fn unwrap_bool(val: &Typed) -> bool {
-    if let Typed::Bool(b) = val {
-        *b
-    } else {
-        die!("Runtime error: Bool expected, got {}", val.typename());
+    /// Get an integer from the replay log if given, from stdin otherwise
+    fn read_int(&mut self, idx: usize, prompt: Option<&str>) -> Result<IntType, String> {
+        let Some(replay) = &mut self.replay else {
+            return get_int_input(prompt);
+        };
+        let Some(line) = replay.pop_front() else {
+            bail!("replay log ran out of inputs at {:04}", idx);
+        };
+        let Some(n) = parse_int(&line) else {
+            bail!("\"{}\" in replay log is not an integer", line);
+        };
+        if self.config.markdown {
+            println!("> {} **{}**\n", prompt.unwrap_or("Input"), n);
+        }
+        Ok(n)
     }
-}
 
-fn unwrap_num(val: &Typed) -> IntType {
-    if let Typed::Num(n) = val {
-        *n
-    } else {
-        die!("Runtime error: Num expected, got {}", val.typename());
+    /// Get a line from the replay log if given, from stdin otherwise.
+    /// With `mask`, the line is fitted to it and asked again until it fits.
+    #[allow(clippy::option_if_let_else)]
+    fn read_str(
+        &mut self,
+        idx: usize,
+        prompt: Option<&str>,
+        mask: Option<&str>,
+    ) -> Result<String, String> {
+        let fit = |line: &str| match mask {
+            Some(mask) => apply_mask(mask, line.trim()),
+            None => Some(line.to_owned()),
+        };
+        let Some(replay) = &mut self.replay else {
+            return get_str_input(prompt, mask, fit);
+        };
+        let Some(line) = replay.pop_front() else {
+            bail!("replay log ran out of inputs at {:04}", idx);
+        };
+        let Some(s) = fit(&line) else {
+            bail!("\"{}\" in replay log doesn't fit the mask", line);
+        };
+        if self.config.markdown {
+            println!("> {} **{}**\n", prompt.unwrap_or("Input"), s);
+        }
+        Ok(s)
     }
-}
 
-fn unwrap_sub(val: &Typed) -> usize {
-    if let Typed::Sub(n) = val {
-        *n
-    } else {
-        die!("Runtime error: Sub expected, got {}", val.typename());
+    /// Wait until Enter is pressed.
+    /// If a line bound by `onkey` is entered instead, the sub to call is returned.
+    /// With `--dev`, "jump" opens the menu of the scenes, which sets `jump_to`.
+    fn wait_for_enter(&mut self, prog: &AST) -> Option<(String, usize)> {
+        let mut stdout = std::io::stdout();
+        let prompt = if self.config.dev {
+            "[Proceed with Enter⏎ , or \"jump\" to a scene]"
+        } else {
+            "[Proceed with Enter⏎ ]"
+        };
+        // the lock is not held while reading, so that Ctrl-Z can clean up the terminal
+        self.mode.prompt(&mut stdout, prompt);
+        // Enter held down repeats, and the repeated ones would go through the following waits at once
+        // (a cooldown of 0 keeps what was typed ahead)
+        let cooldown = self.config.wait_cooldown.unwrap_or(DEFAULT_WAIT_COOLDOWN);
+        if self.mode.is_terminal() && cooldown > 0 {
+            std::thread::sleep(std::time::Duration::from_millis(cooldown));
+            discard_typeahead();
+        }
+        if self.mode.is_terminal() {
+            *PROMPT_SHOWN.lock().unwrap() = Some(prompt);
+        }
+        let line = read_line_from_stdin();
+        *PROMPT_SHOWN.lock().unwrap() = None;
+        self.mode.erase_prompt(&mut stdout);
+        if self.config.dev && line.trim() == "jump" {
+            self.jump_to = scene_menu(prog, self.scene.as_deref());
+            return None;
+        }
+        self.keys
+            .iter()
+            .find(|(key, ..)| key == line.trim())
+            .map(|(_, name, idx)| (name.clone(), *idx))
     }
-}
 
-pub fn run(prog: AST) {
-    let mut runtime = Runtime::new();
+    /// Whether the statement at `ip` waits for the player
+    fn at_wait_point(&self, prog: &AST) -> bool {
+        // nobody waits while replaying or skipping
+        self.replay.is_none()
+            && self.skip_to.is_none()
+            && match &prog.stmts[self.ip] {
+                Statement::Print { nobreak, .. } => !nobreak && self.wait_enabled(),
+                Statement::Input { .. } => true,
+                _ => false,
+            }
+    }
 
-    let mut i = 1; // index 0 is reserved (unreachable)
-    let mut if_eval = false;
-    let mut breaking = false;
+    /// Call the sub of a timer which has elapsed, if any (returns whether it was called)
+    fn fire_timer(&mut self) -> bool {
+        let now = std::time::Instant::now();
+        let Some(pos) = self.timers.iter().position(|(at, ..)| *at <= now) else {
+            return false;
+        };
+        let (_, name, idx) = self.timers.remove(pos);
+        self.trace(self.ip, "TIMER", format_args!("{name} -> {idx:04}"));
+        // the statement about to wait is executed on return
+        self.push(ScopeKind::Sub, self.ip, idx);
+        self.ip = idx + 1;
+        true
+    }
 
-    while i < prog.stmts.len() {
-        match &prog.stmts[i] {
-            Statement::Print { args } => {
-                exec_print(
-                    i,
-                    &runtime,
-                    unwrap_bool(runtime.get_var("_wait").unwrap().get()),
-                    args,
-                );
+    /// Execute the statement at `ip` and move to the next one
+    /// (returns false when the program is halted)
+    #[allow(clippy::too_many_lines)]
+    pub fn step(&mut self, prog: &AST) -> Result<bool, String> {
+        if self.at_wait_point(prog) && self.fire_timer() {
+            return Ok(true);
+        }
+        match &prog.stmts[self.ip] {
+            Statement::Print {
+                args,
+                sep,
+                nobreak,
+                align,
+                speaker,
+            } => {
+                if self.hooked_print == Some(self.ip) {
+                    // back from the sub
+                    self.hooked_print = None;
+                } else if self.resume_wait != Some(self.ip) {
+                    if let Some(hook) = self.print_hook(PrintHook::Before) {
+                        let len = text_length(&eval_text(self, args, sep)?);
+                        // come back to show the text
+                        self.hooked_print = Some(self.ip);
+                        self.call_print_hook(hook, len, self.ip);
+                        return Ok(true);
+                    }
+                }
+                self.trace(self.ip, "PRINT", format_args!(""));
+                if self.resume_wait.take() == Some(self.ip) {
+                    // shown already
+                } else if self.vertical_enabled() {
+                    let mut text = eval_text(self, args, sep)?;
+                    if let Some(speaker) = speaker {
+                        if !text.starts_with(['「', '『']) {
+                            text = format!("「{text}」");
+                        }
+                        text = format!("{speaker}{text}");
+                    }
+                    self.print_vertical(&text, *nobreak);
+                } else {
+                    if *align != Align::Left {
+                        // aligned text needs a line of its own
+                        self.end_line();
+                    }
+                    exec_print(self, args, sep, *nobreak, *align, speaker.as_deref())?;
+                    if self.skip_to.is_none() {
+                        self.line_open = *nobreak;
+                    }
+                }
+                if !nobreak && self.wait_enabled() {
+                    if let Some((name, idx)) = self.wait_for_enter(prog) {
+                        self.trace(self.ip, "ONKEY", format_args!("{name} -> {idx:04}"));
+                        // come back to wait again
+                        self.push(ScopeKind::OnKey, self.ip, idx);
+                        self.ip = idx + 1;
+                        return Ok(true);
+                    }
+                    if let Some(title) = self.jump_to.clone() {
+                        self.trace(self.ip, "JUMP", format_args!("{title:?}"));
+                        // the script is run again from the beginning by `run`
+                        return Ok(false);
+                    }
+                }
+                if let Some(hook) = self.print_hook(PrintHook::After) {
+                    let len = text_length(&eval_text(self, args, sep)?);
+                    self.call_print_hook(hook, len, self.ip + 1);
+                    return Ok(true);
+                }
             }
             Statement::Sub {
                 name,
+                slot,
                 offset_to_end,
             } => {
-                runtime.decl_var(name, Variable::new(Typed::Sub(i)));
-                i += offset_to_end;
+                self.trace(self.ip, "SUB", format_args!("{name}"));
+                self.decl_var(name, *slot, Variable::new(Typed::Sub(self.ip)))?;
+                self.ip += offset_to_end;
             }
-            Statement::Call { name } => {
-                if let Some(idx) = runtime.get_var(name) {
-                    let idx = unwrap_sub(idx.get());
+            Statement::Call { name, slot, into } => {
+                if let Some(idx) = self.var(*slot) {
+                    let idx = unwrap_sub(idx.get())?;
+                    self.trace(self.ip, "CALL", format_args!("{name} -> {idx:04}"));
+
+                    if self.config.markdown && self.skip_to.is_none() {
+                        // subroutines are taken as scenes
+                        println!("## {name}\n");
+                    }
 
+                    let limit = self.config.max_call_depth.unwrap_or(DEFAULT_MAX_CALL_DEPTH);
+                    if self.sub_depth >= limit {
+                        bail!(
+                            "stack overflow: calls nested deeper than {} (calling \"{}\")",
+                            limit,
+                            name
+                        );
+                    }
+
+                    let kind = match into {
+                        Some((var, var_slot)) => ScopeKind::CallInto(var.clone(), *var_slot),
+                        None => ScopeKind::Sub,
+                    };
                     // register address to return (the next line)
-                    runtime.push(ScopeKind::Sub, i + 1);
+                    self.push(kind, self.ip + 1, idx);
 
                     // jump to the address of the sub
-                    i = idx;
+                    self.ip = idx;
                 } else {
-                    die!("Runtime error: function \"{}\" was not found", name);
+                    bail!("function \"{}\" was not found", name);
                 }
             }
             Statement::While {
                 cond,
                 offset_to_end,
             } => {
-                if breaking {
+                if self.breaking {
                     // break was fired, jump to the End
-                    breaking = false;
-                    i += offset_to_end;
+                    self.trace(self.ip, "WHILE", format_args!("left by break"));
+                    self.breaking = false;
+                    self.ip += offset_to_end;
                 } else {
-                    let val = runtime.eval(cond).unwrap_or_else(|e| {
-                        // FIXME
-                        die!("Runtime error: failed to eval condition of While : {}", e);
-                    });
+                    let val = self
+                        .eval(cond)
+                        .map_err(|e| format!("failed to eval condition of While : {e}"))?;
+                    self.trace(self.ip, "WHILE", format_args!("cond = {val}"));
 
-                    if unwrap_bool(&val) {
+                    if unwrap_bool(&val)? {
                         // condition was met, push a scope
                         // when reached to end, pop the scope and come here
-                        runtime.push(ScopeKind::Loop, i);
+                        self.push(ScopeKind::Loop, self.ip, self.ip);
                     } else {
                         // condition wasn't met, jump to the End
-                        i += offset_to_end;
+                        self.ip += offset_to_end;
                     }
                 }
             }
-            Statement::Let { name, init, is_mut } => {
+            Statement::Let {
+                name,
+                slot,
+                init,
+                is_mut,
+                is_global,
+            } => {
                 // no check for internals, as already checked in the parse phase.
-                let init_val = runtime.eval(init).unwrap_or_else(|e| {
-                    die!("Runtime error: Failed to eval init value of Let: {}", e);
-                });
-                runtime.decl_var(
-                    name,
-                    if *is_mut {
-                        Variable::new_mut(init_val)
-                    } else {
-                        Variable::new(init_val)
-                    },
-                );
+                let init_val = self
+                    .eval(init)
+                    .map_err(|e| format!("Failed to eval init value of Let: {e}"))?;
+                self.trace(self.ip, "LET", format_args!("{name} = {init_val}"));
+                let var = if *is_mut {
+                    Variable::new_mut(init_val)
+                } else {
+                    Variable::new(init_val)
+                };
+                if *is_global {
+                    self.decl_global(*slot, var);
+                } else {
+                    self.decl_var(name, *slot, var)?;
+                }
             }
-            Statement::Modify { name, expr } => {
+            Statement::Modify {
+                name,
+                slot,
+                indices,
+                expr,
+            } => {
                 // no check for internals, as already checked in the parse phase.
-                let to_value = runtime.eval(expr).unwrap_or_else(|e| {
-                    // FIXME
-                    die!("Runtime error: Failed to eval value of Modify: {}", e);
-                });
-                runtime.modify_var(name, to_value);
+                let to_value = self
+                    .eval(expr)
+                    .map_err(|e| format!("Failed to eval value of Modify: {e}"))?;
+                let indices = indices
+                    .iter()
+                    .map(|index| {
+                        unwrap_num(
+                            &self
+                                .eval(index)
+                                .map_err(|e| format!("Failed to eval index of Modify: {e}"))?,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if indices.is_empty() {
+                    self.trace(self.ip, "MODIFY", format_args!("{name} = {to_value}"));
+                } else {
+                    // as `grid[1, 0]`
+                    self.trace(
+                        self.ip,
+                        "MODIFY",
+                        format_args!("{name}{indices:?} = {to_value}"),
+                    );
+                }
+                self.modify_var(name, *slot, &indices, to_value)?;
             }
             Statement::If {
                 cond,
@@ -307,19 +832,19 @@ pub fn run(prog: AST) {
             } => {
                 // use a scope, but don't use a return address
                 // push a frame always to unify End behavior
-                runtime.push(ScopeKind::Branch, 0);
-                let val = runtime.eval(cond).unwrap_or_else(|e| {
-                    // FIXME
-                    die!("Runtime error: Failed to eval condition of If: {}", e);
-                });
-                if unwrap_bool(&val) {
+                self.push(ScopeKind::Branch, 0, self.ip);
+                let val = self
+                    .eval(cond)
+                    .map_err(|e| format!("Failed to eval condition of If: {e}"))?;
+                self.trace(self.ip, "IF", format_args!("cond = {val}"));
+                if unwrap_bool(&val)? {
                     // go to body
                     // no-op
                 } else {
                     // jump to the next Elif/Else/End
-                    i += offset_to_next;
-                    if_eval = true;
-                    continue;
+                    self.ip += offset_to_next;
+                    self.if_eval = true;
+                    return Ok(true);
                 }
             }
             Statement::ElIf {
@@ -327,41 +852,106 @@ pub fn run(prog: AST) {
                 offset_to_next,
                 ..
             } => {
-                if if_eval {
+                if self.if_eval {
                     // jumped from If/Elif
-                    let val = runtime.eval(cond).unwrap_or_else(|e| {
-                        // FIXME
-                        die!("Runtime error: Failed to eval condition of Elif: {}", e);
-                    });
-                    if unwrap_bool(&val) {
+                    let val = self
+                        .eval(cond)
+                        .map_err(|e| format!("Failed to eval condition of Elif: {e}"))?;
+                    self.trace(self.ip, "ELIF", format_args!("cond = {val}"));
+                    if unwrap_bool(&val)? {
                         // don't push a frame as If alread pushed one
-                        if_eval = false;
+                        self.if_eval = false;
                     } else {
                         // go to the next Elif/Else/End
-                        i += offset_to_next;
-                        continue;
+                        self.ip += offset_to_next;
+                        return Ok(true);
                     }
                 } else {
                     // come from a block
                     // jump to the End
-                    i += offset_to_next;
-                    continue;
+                    self.ip += offset_to_next;
+                    return Ok(true);
                 }
             }
             Statement::Else { offset_to_end, .. } => {
-                if if_eval {
+                if self.if_eval {
                     // jumped from If/Elif
                     // don't push a frame as If alread pushed one
-                    if_eval = false;
+                    self.trace(self.ip, "ELSE", format_args!("taken"));
+                    self.if_eval = false;
                 } else {
                     // come from a block
-                    i += offset_to_end;
-                    continue;
+                    self.ip += offset_to_end;
+                    return Ok(true);
+                }
+            }
+            Statement::Switch {
+                subject,
+                offset_to_next,
+            } => {
+                let val = self
+                    .eval(subject)
+                    .map_err(|e| format!("Failed to eval subject of Switch: {e}"))?;
+                self.trace(self.ip, "SWITCH", format_args!("subject = {val}"));
+                // one frame for all the arms, as for If
+                self.push(ScopeKind::Switch(val), 0, self.ip);
+                // jump to the first Case/Default/End
+                self.ip += offset_to_next;
+                self.if_eval = true;
+                return Ok(true);
+            }
+            Statement::Case {
+                value,
+                offset_to_next,
+            } => {
+                if self.if_eval {
+                    // jumped from Switch/Case
+                    let val = self
+                        .eval(value)
+                        .map_err(|e| format!("Failed to eval value of Case: {e}"))?;
+                    let matched = matches!(
+                        self.call_stack.last().map(|s| &s.kind),
+                        Some(ScopeKind::Switch(subject)) if *subject == val
+                    );
+                    self.trace(self.ip, "CASE", format_args!("{val} = {matched}"));
+                    if matched {
+                        self.if_eval = false;
+                    } else {
+                        // go to the next Case/Default/End
+                        self.ip += offset_to_next;
+                        return Ok(true);
+                    }
+                } else {
+                    // come from an arm
+                    // jump to the End
+                    self.ip += offset_to_next;
+                    return Ok(true);
                 }
             }
+            Statement::Try { offset_to_recover } => {
+                let to = self.ip + offset_to_recover;
+                self.trace(self.ip, "TRY", format_args!("recover at {to:04}"));
+                // left by Recover, or by an error going to the arm after it
+                self.push(ScopeKind::Try, to, self.ip);
+            }
+            Statement::Recover { offset_to_end } => {
+                // the body ended without errors, skip the arm and its End
+                self.trace(self.ip, "RECOV", format_args!("skip"));
+                self.pop();
+                self.ip += offset_to_end;
+            }
             Statement::End => {
-                if_eval = false;
-                let top = runtime.pop().map(|s| s.ret_idx);
+                if let Some(ScopeKind::CallInto(var, _)) = self.call_stack.last().map(|s| &s.kind) {
+                    bail!("sub ended without returning a value for {}", var);
+                }
+                self.if_eval = false;
+                let top = self.pop().map(|s| {
+                    if matches!(s.kind, ScopeKind::OnKey) {
+                        self.resume_wait = Some(s.ret_idx);
+                    }
+                    s.ret_idx
+                });
+                self.trace(self.ip, "END", format_args!(""));
                 match top {
                     Some(0) => {
                         // return address unspecified
@@ -369,72 +959,1224 @@ pub fn run(prog: AST) {
                     }
                     Some(ret_idx) => {
                         // return to the specified address
-                        i = ret_idx;
-                        continue;
+                        self.ip = ret_idx;
+                        return Ok(true);
                     }
                     _ => {
-                        die!("Runtime error: scope stack is empty");
+                        bail!("scope stack is empty");
                     }
                 }
             }
             Statement::Input {
                 prompt,
                 name,
+                slot,
                 as_num,
-            } => {
-                if *as_num {
-                    runtime.modify_var(name, Typed::Num(get_int_input(prompt.as_deref())));
+                mask,
+                valid,
+                invalid_msg,
+            } => loop {
+                use std::io::Write;
+                // the question comes after the text
+                self.flush_page();
+                let val = if *as_num {
+                    Typed::Num(self.read_int(self.ip, prompt.as_deref())?)
                 } else {
-                    todo!()
+                    Typed::Str(self.read_str(self.ip, prompt.as_deref(), mask.as_deref())?)
+                };
+                // the input is ended by Enter
+                self.line_open = false;
+                self.trace(self.ip, "INPUT", format_args!("{name} = {val}"));
+                self.modify_var(name, *slot, &[], val)?;
+
+                // the conditions see the value in the variable
+                let mut met = true;
+                for cond in valid {
+                    let val = self
+                        .eval(cond)
+                        .map_err(|e| format!("Failed to eval condition of Input: {e}"))?;
+                    met &= unwrap_bool(&val)?;
                 }
-            }
-            Statement::Roll { count, face, name } => {
-                let count = unwrap_num(&runtime.eval(count).unwrap_or_else(|e| {
-                    die!("Runtime error: Failed to eval count of Roll: {}", e);
-                }));
-                let face = unwrap_num(&runtime.eval(face).unwrap_or_else(|e| {
-                    die!("Runtime error: Failed to eval face of Roll: {}", e);
-                }));
+                if met {
+                    break;
+                }
+                if self.replay.is_some() {
+                    bail!(
+                        "input in replay log doesn't meet the conditions at {:04}",
+                        self.ip
+                    );
+                }
+                writeln!(
+                    std::io::stdout(),
+                    "!! {}",
+                    invalid_msg
+                        .as_deref()
+                        .unwrap_or("Provided input is invalid")
+                )
+                .map_err(|e| format!("failed to show the prompt: {e}"))?;
+            },
+            Statement::Roll {
+                count,
+                face,
+                name,
+                slot,
+                silent,
+            } => {
+                let count = unwrap_num(
+                    &self
+                        .eval(count)
+                        .map_err(|e| format!("Failed to eval count of Roll: {e}"))?,
+                )?;
+                let face = unwrap_num(
+                    &self
+                        .eval(face)
+                        .map_err(|e| format!("Failed to eval face of Roll: {e}"))?,
+                )?;
 
                 if count <= 0 {
-                    die!("Runtime error: Count for Roll must be a positive integer");
+                    bail!("Count for Roll must be a positive integer");
                 }
 
                 if face <= 0 {
-                    die!("Runtime error: Face for Roll must be a positive integer");
+                    bail!("Face for Roll must be a positive integer");
+                }
+                let show = !silent && self.wait_enabled() && self.mode.is_terminal();
+                let sum = if show && count <= MAX_SHOWN_DICE {
+                    let results = roll_each(&mut self.rng, count, face);
+                    self.end_line();
+                    show_roll(self.mode, self.ip, face, &results);
+                    results.iter().sum()
+                } else {
+                    let sum = roll_dice(&mut self.rng, count, face);
+                    if show {
+                        self.end_line();
+                        println!("{:04} : {}d{} = {}", self.ip, count, face, sum);
+                    }
+                    sum
+                };
+                self.trace(
+                    self.ip,
+                    "ROLL",
+                    format_args!("{name} = {sum} ({count}d{face})"),
+                );
+                self.modify_var(name, *slot, &[], Typed::Num(sum))?;
+            }
+            Statement::Halt { code } => {
+                if let Some(code) = code {
+                    let code = unwrap_num(
+                        &self
+                            .eval(code)
+                            .map_err(|e| format!("Failed to eval code of Halt: {e}"))?,
+                    )?;
+                    self.exit_status = std::convert::TryFrom::try_from(code)
+                        .ok()
+                        .filter(|code| (0..=255).contains(code))
+                        .ok_or_else(|| format!("Exit status must be from 0 to 255: {code}"))?;
+                }
+                let status = self.exit_status;
+                self.trace(self.ip, "HALT", format_args!("{status}"));
+                return Ok(false);
+            }
+            Statement::Log { args } => {
+                self.trace(self.ip, "LOG", format_args!(""));
+                let msg = args
+                    .iter()
+                    .map(|arg| {
+                        match self
+                            .eval(arg)
+                            .map_err(|e| format!("Failed to eval arg of Log: {e}"))?
+                        {
+                            Typed::Str(s) => Ok(s),
+                            val => Ok(val.to_string()),
+                        }
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                self.write_log(prog.rows[self.ip], &msg.join(" "));
+            }
+            Statement::Newline { count } => {
+                let count = match count {
+                    Some(count) => unwrap_num(
+                        &self
+                            .eval(count)
+                            .map_err(|e| format!("Failed to eval count of Newline: {e}"))?,
+                    )?,
+                    None => 1,
+                };
+                if count < 0 {
+                    bail!("Count for Newline must not be negative");
+                }
+                self.trace(self.ip, "NEWLN", format_args!("{count}"));
+                if self.skip_to.is_none() && self.vertical_enabled() {
+                    self.page
+                        .newline(std::convert::TryFrom::try_from(count).unwrap_or(usize::MAX));
+                } else if self.skip_to.is_none() {
+                    let mut count = count;
+                    if self.line_open {
+                        // the first one ends the open line
+                        self.end_line();
+                        count -= 1;
+                    }
+                    // Markdown cannot show empty lines
+                    if !self.config.markdown {
+                        for _ in 0..count {
+                            println!();
+                        }
+                    }
                 }
-                runtime.modify_var(name, Typed::Num(roll_dice(count, face)));
             }
-            Statement::Halt => {
-                return;
+            Statement::Breakpoint => {
+                self.trace(self.ip, "BRKPT", format_args!(""));
+                if self.config.dev {
+                    debug_prompt(self.ip, self, prog);
+                }
+            }
+            Statement::Scene { title } => {
+                self.trace(self.ip, "SCENE", format_args!("{title:?}"));
+                self.scene = Some(title.clone());
+                if self.skip_to.as_ref() == Some(title) {
+                    self.skip_to = None;
+                }
+                if self.skip_to.is_none() {
+                    self.end_line();
+                    if self.config.markdown {
+                        println!("## {title}\n");
+                    } else {
+                        println!("==== {title} ====");
+                    }
+                }
+            }
+            Statement::Status { template } => {
+                self.trace(
+                    self.ip,
+                    "STATUS",
+                    format_args!("{:?}", template.to_string()),
+                );
+                self.status = template.clone();
+            }
+            Statement::Checkpoint => {
+                self.trace(self.ip, "CHKPT", format_args!(""));
+                self.checkpoint = Some(Snapshot {
+                    ip: self.ip,
+                    call_stack: self.call_stack.clone(),
+                    sub_depth: self.sub_depth,
+                    globals: self.globals.clone(),
+                    scene: self.scene.clone(),
+                    status: self.status.clone(),
+                    if_eval: self.if_eval,
+                    breaking: self.breaking,
+                    timers: self.timers.clone(),
+                    keys: self.keys.clone(),
+                    error_handler: self.error_handler.clone(),
+                    print_hooks: self.print_hooks.clone(),
+                });
+            }
+            Statement::After { ms, name, slot } => {
+                use std::convert::TryFrom;
+                let ms = unwrap_num(
+                    &self
+                        .eval(ms)
+                        .map_err(|e| format!("Failed to eval time of After: {e}"))?,
+                )?;
+                let Ok(ms) = u64::try_from(ms) else {
+                    bail!("Time for After must not be negative");
+                };
+                let Some(var) = self.var(*slot) else {
+                    bail!("function \"{}\" was not found", name);
+                };
+                let idx = unwrap_sub(var.get())?;
+                self.trace(self.ip, "AFTER", format_args!("{ms} ms, {name}"));
+                let at = std::time::Instant::now() + std::time::Duration::from_millis(ms);
+                self.timers.push((at, name.clone(), idx));
+            }
+            Statement::OnKey { key, name, slot } => {
+                let Some(var) = self.var(*slot) else {
+                    bail!("function \"{}\" was not found", name);
+                };
+                let idx = unwrap_sub(var.get())?;
+                self.trace(self.ip, "ONKEY", format_args!("{key:?} = {name}"));
+                // binding a key again replaces the sub
+                self.keys.retain(|(k, ..)| k != key);
+                self.keys.push((key.clone(), name.clone(), idx));
+            }
+            Statement::Hook { point, name, slot } => {
+                let Some(var) = self.var(*slot) else {
+                    bail!("function \"{}\" was not found", name);
+                };
+                let idx = unwrap_sub(var.get())?;
+                self.trace(
+                    self.ip,
+                    "HOOK",
+                    format_args!("{} = {}", point.as_str(), name),
+                );
+                // hooking again replaces the sub
+                self.print_hooks.retain(|(p, ..)| p != point);
+                self.print_hooks.push((*point, name.clone(), idx));
+            }
+            Statement::OnError { name, slot } => {
+                let Some(var) = self.var(*slot) else {
+                    bail!("function \"{}\" was not found", name);
+                };
+                let idx = unwrap_sub(var.get())?;
+                self.trace(self.ip, "ONERR", format_args!("{name}"));
+                self.error_handler = Some((name.clone(), idx));
+            }
+            Statement::Restore => {
+                let Some(snapshot) = &self.checkpoint else {
+                    bail!("no checkpoint to restore");
+                };
+                let to = snapshot.ip;
+                self.call_stack = snapshot.call_stack.clone();
+                self.sub_depth = snapshot.sub_depth;
+                self.scene = snapshot.scene.clone();
+                self.globals = snapshot.globals.clone();
+                self.status = snapshot.status.clone();
+                self.if_eval = snapshot.if_eval;
+                self.breaking = snapshot.breaking;
+                self.timers = snapshot.timers.clone();
+                self.keys = snapshot.keys.clone();
+                self.error_handler = snapshot.error_handler.clone();
+                self.print_hooks = snapshot.print_hooks.clone();
+                self.trace(self.ip, "RESTOR", format_args!("-> {to:04}"));
+                // resume right after the Checkpoint
+                self.ip = to;
             }
             Statement::Break => {
-                i = loop {
-                    if let Some(scope) = runtime.pop() {
+                // leaving a sub by Break returns nothing
+                if let Some(ScopeKind::CallInto(var, _)) = self
+                    .call_stack
+                    .iter()
+                    .rev()
+                    .map(|s| &s.kind)
+                    .find(|k| matches!(k, ScopeKind::Loop) || k.is_sub())
+                {
+                    bail!(
+                        "sub was left by Break without returning a value for {}",
+                        var
+                    );
+                }
+                let to = loop {
+                    if let Some(scope) = self.pop() {
                         match scope.kind {
                             ScopeKind::Loop => {
-                                breaking = true;
+                                self.breaking = true;
                                 break scope.ret_idx;
                             }
-                            ScopeKind::Sub => {
+                            ScopeKind::Sub
+                            | ScopeKind::CallInto(..)
+                            | ScopeKind::OnError
+                            | ScopeKind::Hook => {
                                 break scope.ret_idx;
                             }
-                            ScopeKind::Branch => {
+                            ScopeKind::OnKey => {
+                                self.resume_wait = Some(scope.ret_idx);
+                                break scope.ret_idx;
+                            }
+                            ScopeKind::Branch | ScopeKind::Switch(_) | ScopeKind::Try => {
                                 // break the outer scope
                             }
                         }
                     } else {
-                        die!("Runtime error: scope stack is empty");
+                        bail!("scope stack is empty");
                     }
                 };
-                continue;
+                self.trace(self.ip, "BREAK", format_args!("-> {to:04}"));
+                self.ip = to;
+                return Ok(true);
+            }
+            Statement::Return { value } => {
+                let value = match value {
+                    Some(value) => Some(
+                        self.eval(value)
+                            .map_err(|e| format!("Failed to eval value of Return: {e}"))?,
+                    ),
+                    None => None,
+                };
+                let Some(pos) = self.call_stack.iter().rposition(|s| s.kind.is_sub()) else {
+                    bail!("scope stack is empty");
+                };
+                if let (ScopeKind::CallInto(var, _), None) = (&self.call_stack[pos].kind, &value) {
+                    bail!("sub returned without a value for {}", var);
+                }
+                // leave the blocks in the sub as well
+                let scope = self.unwind(pos).unwrap();
+                self.if_eval = false;
+                match &value {
+                    Some(value) => self.trace(
+                        self.ip,
+                        "RETURN",
+                        format_args!("{} -> {:04}", value, scope.ret_idx),
+                    ),
+                    None => self.trace(self.ip, "RETURN", format_args!("-> {:04}", scope.ret_idx)),
+                }
+                match (scope.kind, value) {
+                    (ScopeKind::CallInto(var, slot), Some(value)) => {
+                        self.modify_var(&var, slot, &[], value)?;
+                    }
+                    (ScopeKind::OnKey, _) => self.resume_wait = Some(scope.ret_idx),
+                    // the value is not taken
+                    _ => {}
+                }
+                self.ip = scope.ret_idx;
+                return Ok(true);
             }
             #[allow(unreachable_patterns)]
             other => {
-                die!("Runtime error: unknown instruction: {:?}", other);
+                bail!("unknown instruction: {:?}", other);
+            }
+        }
+        self.ip += 1;
+        Ok(true)
+    }
+
+    /// Prepare to start from the sub named `entry`:
+    /// declare the variables and the subs at the top level, skipping all the rest,
+    /// and call the sub so that the program ends when it returns
+    fn enter(&mut self, prog: &AST, entry: &str) -> Result<(), String> {
+        let mut depth = 0_usize;
+        let mut ip = 1;
+        while ip < prog.stmts.len() {
+            match &prog.stmts[ip] {
+                Statement::Sub {
+                    name,
+                    slot,
+                    offset_to_end,
+                } if depth == 0 => {
+                    self.decl_var(name, *slot, Variable::new(Typed::Sub(ip)))?;
+                    ip += offset_to_end;
+                }
+                Statement::Let {
+                    name,
+                    slot,
+                    init,
+                    is_mut,
+                    ..
+                } if depth == 0 => {
+                    let val = self
+                        .eval(init)
+                        .map_err(|e| format!("Failed to eval init value of Let: {e}"))?;
+                    let var = if *is_mut {
+                        Variable::new_mut(val)
+                    } else {
+                        Variable::new(val)
+                    };
+                    self.decl_var(name, *slot, var)?;
+                }
+                Statement::While { .. }
+                | Statement::If { .. }
+                | Statement::Switch { .. }
+                | Statement::Sub { .. }
+                | Statement::Try { .. } => {
+                    depth += 1;
+                }
+                Statement::End => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            ip += 1;
+        }
+        let top_level = prog.names.get(&0).map_or(&[][..], Vec::as_slice);
+        let slot = top_level
+            .iter()
+            .position(|name| name == entry)
+            .map(|index| Slot { scope: 0, index });
+        let idx = match slot.and_then(|slot| self.var(slot)).map(Variable::get) {
+            Some(Typed::Sub(idx)) => *idx,
+            _ => bail!("sub \"{}\" was not found at the top level", entry),
+        };
+        self.trace(idx, "ENTRY", format_args!("{entry}"));
+        // the End of the sub returns past the last statement
+        self.push(ScopeKind::Sub, prog.stmts.len(), idx);
+        self.ip = idx + 1;
+        Ok(())
+    }
+
+    /// Execute until the end of the program (or until `stop` is set)
+    fn exec(&mut self, prog: &AST) -> Result<(), String> {
+        let result = loop {
+            if self.ip >= prog.stmts.len() {
+                break Ok(());
+            }
+            if let Some(stop) = &self.config.stop {
+                if stop.load(std::sync::atomic::Ordering::Relaxed) {
+                    break Ok(());
+                }
+            }
+            CURRENT_IP.store(self.ip, Ordering::Relaxed);
+            CURRENT_ROW.store(prog.rows[self.ip], Ordering::Relaxed);
+            match self.step(prog) {
+                Ok(true) => {
+                    if !self.vertical_enabled() {
+                        self.flush_page();
+                    }
+                    self.update_status();
+                }
+                Ok(false) => break Ok(()),
+                Err(e) => {
+                    let handled = self
+                        .recover(prog, e)
+                        .or_else(|e| self.call_error_handler(prog, e));
+                    if let Err(e) = handled {
+                        break Err(e);
+                    }
+                }
             }
+        };
+        self.flush_page();
+        self.end_line();
+        self.release_status_row();
+        result
+    }
+
+    /// The sub hooked at `point`, unless it is a Print in a hooked sub (which would call it forever)
+    fn print_hook(&self, point: PrintHook) -> Option<(String, usize)> {
+        if self
+            .call_stack
+            .iter()
+            .any(|s| matches!(s.kind, ScopeKind::Hook))
+        {
+            return None;
+        }
+        self.print_hooks
+            .iter()
+            .find(|(p, ..)| *p == point)
+            .map(|(_, name, idx)| (name.clone(), *idx))
+    }
+
+    /// Call a hooked sub with `_print_length` set, to return to `ret_idx`
+    fn call_print_hook(&mut self, (name, idx): (String, usize), len: IntType, ret_idx: usize) {
+        self.trace(self.ip, "HOOK", format_args!("{name} -> {idx:04}"));
+        self.set_internal("_print_length", Typed::Num(len));
+        self.push(ScopeKind::Hook, ret_idx, idx);
+        self.ip = idx + 1;
+    }
+
+    /// Call the sub registered by `onerror` with the error in `_error_message` and `_error_line`,
+    /// to go on after the failed statement when it returns.
+    /// The error is given back if there is no such sub, or if the sub itself failed.
+    fn call_error_handler(&mut self, prog: &AST, msg: String) -> Result<(), String> {
+        let Some((name, idx)) = self.error_handler.clone() else {
+            return Err(msg);
+        };
+        if self
+            .call_stack
+            .iter()
+            .any(|s| matches!(s.kind, ScopeKind::OnError))
+        {
+            return Err(msg);
         }
-        i += 1;
+        self.trace(self.ip, "ERROR", format_args!("{name} -> {idx:04}"));
+        self.set_error(prog, msg);
+        let resume = self.resume_after_error(prog);
+        self.push(ScopeKind::OnError, resume, idx);
+        self.ip = idx + 1;
+        Ok(())
+    }
+
+    /// Go to the Recover arm of the innermost Try with the error in `_error_message` and `_error_line`,
+    /// leaving the scopes (and subs) inside the Try.
+    /// The error is given back if not in a Try.
+    fn recover(&mut self, prog: &AST, msg: String) -> Result<(), String> {
+        let Some(pos) = self
+            .call_stack
+            .iter()
+            .rposition(|s| matches!(s.kind, ScopeKind::Try))
+        else {
+            return Err(msg);
+        };
+        let to = self.call_stack[pos].ret_idx;
+        self.unwind(pos);
+        self.trace(self.ip, "RECOV", format_args!("-> {to:04}"));
+        self.set_error(prog, msg);
+        // the arm has a scope of its own, left by the End
+        self.push(ScopeKind::Branch, 0, to);
+        self.ip = to + 1;
+        Ok(())
+    }
+
+    /// Set `_error_message` and `_error_line` for the error at `ip`
+    fn set_error(&mut self, prog: &AST, msg: String) {
+        let line = std::convert::TryFrom::try_from(prog.rows[self.ip]).unwrap_or(IntType::MAX);
+        self.set_internal("_error_message", Typed::Str(msg));
+        self.set_internal("_error_line", Typed::Num(line));
+        // a failed Else-If leaves it set, which would be taken by the next Else-If
+        self.if_eval = false;
+    }
+
+    /// Where to go on after the statement at `ip` failed: the next statement,
+    /// or the one after the block whose condition failed (leaving the scope of the If)
+    fn resume_after_error(&mut self, prog: &AST) -> usize {
+        match &prog.stmts[self.ip] {
+            Statement::While { offset_to_end, .. } => self.ip + offset_to_end + 1,
+            Statement::If { .. }
+            | Statement::ElIf { .. }
+            | Statement::Switch { .. }
+            | Statement::Case { .. } => {
+                // Switch pushes its frame only after the subject is evaluated
+                if !matches!(prog.stmts[self.ip], Statement::Switch { .. }) {
+                    self.pop();
+                }
+                let mut ip = self.ip;
+                loop {
+                    match &prog.stmts[ip] {
+                        Statement::If { offset_to_next, .. }
+                        | Statement::ElIf { offset_to_next, .. }
+                        | Statement::Switch { offset_to_next, .. }
+                        | Statement::Case { offset_to_next, .. } => ip += offset_to_next,
+                        Statement::Else { offset_to_end } => ip += offset_to_end,
+                        _ => break ip + 1,
+                    }
+                }
+            }
+            _ => self.ip + 1,
+        }
+    }
+
+    /// Add a paragraph to the page of vertical text (or continue the last one),
+    /// and turn the page when it is full
+    fn print_vertical(&mut self, text: &str, nobreak: bool) {
+        use std::io::Write;
+
+        if self.skip_to.is_some() {
+            return;
+        }
+        // the text of the horizontal mode is left above the page
+        self.end_line();
+        self.page.push(text, nobreak);
+
+        let (width, rows) = self.page_size();
+        if let Some(full) = self.page.turn_if_full(width, rows) {
+            print_page(self.mode, &full, rows);
+        }
+        if self.mode.is_terminal() {
+            let stdout = std::io::stdout();
+            let mut lock = stdout.lock();
+            let page = self.page.render(rows);
+            self.mode.clear_screen(&mut lock);
+            write!(lock, "{page}").unwrap();
+            let _ = lock.flush();
+            *PAGE_SHOWN.lock().unwrap() = Some(page);
+            // the status line has been cleared too
+            self.status_shown = None;
+        }
+    }
+
+    /// Width of the terminal and the number of characters in a column
+    fn page_size(&self) -> (usize, usize) {
+        let (width, height) =
+            crossterm::terminal::size().map_or((80, 24), |(w, h)| (usize::from(w), usize::from(h)));
+        // a row is left for the prompt, and another for the status line
+        let reserved = 1 + usize::from(self.status_rows.is_some());
+        (width, height.saturating_sub(reserved).max(1))
+    }
+
+    /// Finish the page of vertical text, so that the next paragraph begins a new one.
+    /// The page is printed here when the output is not a terminal, where it is not redrawn.
+    fn flush_page(&mut self) {
+        if !self.page.is_empty() {
+            // the text after it is written below, and would be lost if the page were redrawn
+            *PAGE_SHOWN.lock().unwrap() = None;
+            let (_, rows) = self.page_size();
+            print_page(self.mode, &std::mem::take(&mut self.page), rows);
+        }
+    }
+
+    /// Render the status line (None if there is none).
+    /// The scene is put at the beginning on a terminal, as it is printed as a heading elsewhere.
+    fn render_status(&self) -> Option<String> {
+        let scene = self.scene.as_ref().filter(|_| self.mode.is_terminal());
+        if self.status.0.is_empty() {
+            return scene.cloned();
+        }
+        let mut text = scene.map_or_else(String::new, |scene| format!("{scene} | "));
+        for piece in &self.status.0 {
+            match piece {
+                Piece::Text(s) => text.push_str(s),
+                // variables may be out of scope
+                Piece::Var(_, slot) => match self.var(*slot).map(Variable::get) {
+                    Some(Typed::Str(s)) => text.push_str(s),
+                    Some(val) => text.push_str(&val.to_string()),
+                    None => text.push('?'),
+                },
+            }
+        }
+        Some(text)
+    }
+
+    /// Show the status line again if its text has changed.
+    /// It is drawn at the bottom of the terminal, or printed as a line of its own elsewhere.
+    fn update_status(&mut self) {
+        use crossterm::{cursor, queue, terminal};
+        use std::io::Write;
+
+        if self.skip_to.is_some() {
+            return;
+        }
+        let text = self.render_status();
+        if text == self.status_shown {
+            return;
+        }
+
+        if self.config.markdown || !self.mode.is_terminal() {
+            if let Some(text) = &text {
+                self.end_line();
+                if self.config.markdown {
+                    println!("> {text}\n");
+                } else {
+                    println!("---- {text} ----");
+                }
+            }
+        } else if let Some(text) = &text {
+            let stdout = std::io::stdout();
+            let mut lock = stdout.lock();
+            let rows = if let Some(rows) = self.status_rows {
+                rows
+            } else {
+                let rows = terminal::size().map_or(24, |(_, h)| h.max(2));
+                self.end_line();
+                // move the text up if the cursor is on the last row
+                writeln!(lock).unwrap();
+                queue!(lock, cursor::MoveUp(1)).unwrap();
+                self.status_rows = Some(rows);
+                rows
+            };
+            draw_status(&mut lock, rows, text);
+            *STATUS_ROW.lock().unwrap() = Some((rows, text.clone()));
+        } else {
+            self.release_status_row();
+        }
+        self.status_shown = text;
+    }
+
+    /// Give the row kept for the status line back to the text
+    fn release_status_row(&mut self) {
+        if let Some(rows) = self.status_rows.take() {
+            clear_status(&mut std::io::stdout().lock(), rows);
+            *STATUS_ROW.lock().unwrap() = None;
+        }
+    }
+
+    /// Break the line left open by Print
+    fn end_line(&mut self) {
+        if self.line_open {
+            println!();
+            self.line_open = false;
+        }
+    }
+}
+
+/// Print a finished page when the output is not a terminal (pages are redrawn on terminals)
+fn print_page(mode: render::Mode, page: &vertical::Page, rows: usize) {
+    if !mode.is_terminal() {
+        println!("{}", page.render(rows));
+    }
+}
+
+/// Index of the statement being executed and its row, for reports of panics
+static CURRENT_IP: AtomicUsize = AtomicUsize::new(0);
+static CURRENT_ROW: AtomicUsize = AtomicUsize::new(0);
+
+/// Give the terminal back and tell where the script was when the interpreter panics,
+/// instead of leaving the scrolling region set and vanishing with only a message of Rust
+pub fn set_panic_hook(filename: String) {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // the lock may be held by the panicking thread
+        if let Ok(mut status) = STATUS_ROW.try_lock() {
+            if let Some((rows, _)) = status.take() {
+                clear_status(&mut std::io::stdout(), rows);
+            }
+        }
+        default(info);
+        eprintln!(
+            "Internal error at instruction {:04} ({}:{})",
+            CURRENT_IP.load(Ordering::Relaxed),
+            filename,
+            CURRENT_ROW.load(Ordering::Relaxed)
+        );
+        eprintln!("This is a bug of novelang. Please report it with this message and the script.");
+    }));
+}
+
+/// Row kept for the status line and the text on it, shared with the handler of Ctrl-Z
+static STATUS_ROW: std::sync::Mutex<Option<(u16, String)>> = std::sync::Mutex::new(None);
+
+/// Page of vertical text on the screen, shared with the handler of Ctrl-Z to draw it again
+static PAGE_SHOWN: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// Prompt waiting for Enter under the page, drawn again with it
+static PROMPT_SHOWN: std::sync::Mutex<Option<&'static str>> = std::sync::Mutex::new(None);
+
+/// Keep the last row out of the scrolling region (DECSTBM) and write the status line on it
+fn draw_status(w: &mut impl std::io::Write, rows: u16, text: &str) {
+    use crossterm::{cursor, queue, terminal};
+
+    // setting the scrolling region moves the cursor, so it is saved
+    write!(w, "\x1b7\x1b[1;{}r\x1b8", rows - 1).unwrap();
+    queue!(
+        w,
+        cursor::SavePosition,
+        cursor::MoveTo(0, rows - 1),
+        terminal::Clear(terminal::ClearType::CurrentLine)
+    )
+    .unwrap();
+    write!(w, "{text}").unwrap();
+    queue!(w, cursor::RestorePosition).unwrap();
+    let _ = w.flush();
+}
+
+/// Clear the status line and give the whole screen back to the text
+fn clear_status(w: &mut impl std::io::Write, rows: u16) {
+    use crossterm::{cursor, queue, terminal};
+
+    queue!(
+        w,
+        cursor::SavePosition,
+        cursor::MoveTo(0, rows - 1),
+        terminal::Clear(terminal::ClearType::CurrentLine),
+        cursor::RestorePosition
+    )
+    .unwrap();
+    // reset the scrolling region, which also moves the cursor
+    write!(w, "\x1b7\x1b[r\x1b8").unwrap();
+    let _ = w.flush();
+}
+
+/// Leave the terminal as it was before being suspended by Ctrl-Z (SIGTSTP),
+/// and draw the page of vertical text, the prompt under it and the status line again when resumed
+#[cfg(unix)]
+pub fn handle_suspend() -> std::io::Result<()> {
+    use signal_hook::consts::SIGTSTP;
+    use std::io::Write;
+
+    let mut signals = signal_hook::iterator::Signals::new([SIGTSTP])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            let status = STATUS_ROW.lock().unwrap().clone();
+            if let Some((rows, _)) = &status {
+                clear_status(&mut std::io::stdout().lock(), *rows);
+            }
+            // stop here until SIGCONT
+            let _ = signal_hook::low_level::emulate_default_handler(SIGTSTP);
+            let stdout = std::io::stdout();
+            let mut lock = stdout.lock();
+            // the shell has written below the page, which is drawn again from the top
+            if let Some(page) = PAGE_SHOWN.lock().unwrap().as_deref() {
+                render::Mode::Terminal.clear_screen(&mut lock);
+                write!(lock, "{page}").unwrap();
+                if let Some(prompt) = *PROMPT_SHOWN.lock().unwrap() {
+                    render::Mode::Terminal.prompt(&mut lock, prompt);
+                }
+                let _ = lock.flush();
+            }
+            if let Some((rows, text)) = &status {
+                draw_status(&mut lock, *rows, text);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Return the name of the speaker padded to the box and painted if possible
+fn speaker_tag(machine: &Machine<'_>, speaker: &str) -> String {
+    use crate::theme::{Role, Theme};
+    use crossterm::style::Color;
+
+    let width = machine.config.name_width.unwrap_or(0);
+    let name = format!("{speaker:<width$}");
+    if machine.config.markdown {
+        format!("**{}**:", name.trim_end())
+    } else {
+        let color = machine
+            .config
+            .speaker_colors
+            .get(speaker)
+            .copied()
+            .unwrap_or(Color::Reset);
+        let theme = Theme::for_stream(machine.mode.is_terminal());
+        format!("{}:", theme.paint(&name, Role::Speaker(color)))
+    }
+}
+
+/// Evaluate the args of Print and join them with `sep`
+fn eval_text(machine: &Machine<'_>, args: &[exprs::Expr], sep: &str) -> Result<String, String> {
+    let mut text = String::new();
+    for (n, arg) in args.iter().enumerate() {
+        let val = arg
+            .eval_on(machine)
+            .map_err(|e| format!("Failed to eval arg of Print: {e:?}"))?;
+        if n > 0 {
+            text.push_str(sep);
+        }
+        match val {
+            Typed::Str(s) => text.push_str(&s),
+            Typed::Num(_) | Typed::Bool(_) | Typed::Array(_) => text.push_str(&val.to_string()),
+            Typed::Sub(_) => unimplemented!(),
+        }
+    }
+    Ok(text)
+}
+
+/// Length of the text in characters (grapheme clusters)
+fn text_length(text: &str) -> IntType {
+    use unicode_segmentation::UnicodeSegmentation;
+    std::convert::TryFrom::try_from(text.graphemes(true).count()).unwrap_or(IntType::MAX)
+}
+
+fn exec_print(
+    machine: &Machine<'_>,
+    args: &[exprs::Expr],
+    sep: &str,
+    nobreak: bool,
+    align: Align,
+    speaker: Option<&str>,
+) -> Result<(), String> {
+    use std::io::Write;
+    use unicode_segmentation::UnicodeSegmentation;
+    if machine.skip_to.is_some() {
+        return Ok(());
+    }
+    let idx = machine.ip;
+
+    let stdout = std::io::stdout();
+    let mut lock = stdout.lock();
+
+    let markdown = machine.config.markdown;
+    let mut text = eval_text(machine, args, sep)?;
+    // right-to-left paragraphs go to the right margin
+    let rtl = bidi::is_rtl(&text);
+    let align = if rtl && align == Align::Left && !machine.line_open && !text.contains('\n') {
+        Align::Right
+    } else {
+        align
+    };
+    if machine.config.reorder_bidi && !markdown {
+        text = text
+            .lines()
+            .map(bidi::reorder)
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+    // counted before the name is decorated
+    let mut len = text.graphemes(true).count();
+    if let Some(speaker) = speaker {
+        // the name padded to the box, ':' and a space
+        len += speaker
+            .graphemes(true)
+            .count()
+            .max(machine.config.name_width.unwrap_or(0))
+            + 2;
+        text = format!("{} {}", speaker_tag(machine, speaker), text);
+    }
+
+    match align {
+        // a line left open is continued as it is,
+        // and paragraphs of Markdown don't begin with a space
+        Align::Left if markdown || machine.line_open => write!(lock, "{text}"),
+        Align::Left if args.is_empty() => write!(lock, "{idx:04} :"),
+        Align::Left => write!(lock, "{idx:04} : {text}"),
+        _ if markdown && rtl => write!(lock, "<div dir=\"rtl\">{text}</div>"),
+        _ if markdown => write!(lock, "<div align=\"{}\">{}</div>", align.as_str(), text),
+        _ => {
+            let width = crossterm::terminal::size().map_or(80, |(w, _)| usize::from(w));
+            // the index is kept at the left of right-to-left paragraphs
+            let index = if rtl && align == Align::Right {
+                format!("{idx:04} :")
+            } else {
+                String::new()
+            };
+            let room = width.saturating_sub(index.len() + len);
+            let pad = if align == Align::Center {
+                room / 2
+            } else {
+                room
+            };
+            write!(lock, "{}{}{}", index, " ".repeat(pad), text)
+        }
+    }
+    .unwrap();
+    if !nobreak {
+        writeln!(lock).unwrap();
+        if machine.config.markdown {
+            writeln!(lock).unwrap();
+        }
+    }
+    let _ = lock.flush();
+    Ok(())
+}
+
+fn get_int_input(prompt: Option<&str>) -> Result<IntType, String> {
+    use std::io::Write;
+    let mut stdout = std::io::stdout();
+    loop {
+        write!(stdout, "{} > ", prompt.unwrap_or("Provide an integer"))
+            .map_err(|e| format!("failed to show the prompt: {e}"))?;
+        let _ = stdout.flush();
+        if let Some(i) = parse_int(&read_input_line()?) {
+            return Ok(i);
+        }
+        writeln!(stdout, "!! Provided input is invalid")
+            .map_err(|e| format!("failed to show the prompt: {e}"))?;
+        let _ = stdout.flush();
+    }
+}
+
+fn get_str_input(
+    prompt: Option<&str>,
+    mask: Option<&str>,
+    fit: impl Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    use std::io::Write;
+    let mut stdout = std::io::stdout();
+    loop {
+        match mask {
+            Some(mask) => write!(stdout, "{} [{}] > ", prompt.unwrap_or("Input"), mask),
+            None => write!(stdout, "{} > ", prompt.unwrap_or("Input")),
+        }
+        .map_err(|e| format!("failed to show the prompt: {e}"))?;
+        let _ = stdout.flush();
+        if let Some(s) = fit(&read_input_line()?) {
+            return Ok(s);
+        }
+        writeln!(stdout, "!! Provided input doesn't fit the mask")
+            .map_err(|e| format!("failed to show the prompt: {e}"))?;
+        let _ = stdout.flush();
+    }
+}
+
+/// Fit `input` to `mask`, inserting the literals which are omitted.
+/// Returns None if a character is not accepted at its position or the length differs.
+fn apply_mask(mask: &str, input: &str) -> Option<String> {
+    let mut ret = String::new();
+    let mut chars = input.chars().peekable();
+    for m in mask.chars() {
+        let accepts = |c: char| match m {
+            '#' => c.is_ascii_digit(),
+            '?' => c.is_alphabetic(),
+            _ => c.is_alphanumeric(),
+        };
+        if matches!(m, '#' | '?' | '*') {
+            ret.push(chars.next().filter(|c| accepts(*c))?);
+        } else {
+            // literals may be typed or left to be inserted
+            if chars.peek() == Some(&m) {
+                chars.next();
+            }
+            ret.push(m);
+        }
+    }
+    chars.next().is_none().then_some(ret)
+}
+
+/// Show the scenes of the script for `--dev`, and return the one chosen to jump to
+fn scene_menu(prog: &AST, current: Option<&str>) -> Option<String> {
+    use std::io::Write;
+    let titles: Vec<&str> = prog
+        .stmts
+        .iter()
+        .filter_map(|s| match s {
+            Statement::Scene { title } => Some(title.as_str()),
+            _ => None,
+        })
+        .collect();
+    let stderr = std::io::stderr();
+    let mut lock = stderr.lock();
+    if titles.is_empty() {
+        let _ = writeln!(lock, "No scenes in the script");
+        return None;
+    }
+    let _ = writeln!(
+        lock,
+        "Scenes (the script is run again from the beginning up to the one chosen):"
+    );
+    for (n, title) in titles.iter().enumerate() {
+        let mark = if Some(*title) == current { '*' } else { ' ' };
+        let _ = writeln!(lock, "{} {:>3}. {}", mark, n + 1, title);
+    }
+    let _ = write!(lock, "jump (Enter to cancel)> ");
+    let _ = lock.flush();
+    let line = read_line_from_stdin();
+    let chosen = line
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| titles.get(n.checked_sub(1)?));
+    if chosen.is_none() && !line.trim().is_empty() {
+        let _ = writeln!(lock, "no scene numbered \"{}\"", line.trim());
+    }
+    chosen.map(|title| (*title).to_owned())
+}
+
+/// Interact with the developer at a Breakpoint until asked to continue
+fn debug_prompt(idx: usize, machine: &Machine<'_>, prog: &AST) {
+    use std::io::Write;
+    let stderr = std::io::stderr();
+    let mut lock = stderr.lock();
+    writeln!(
+        lock,
+        "Breakpoint at {idx:04} (c: continue, p <name>: print a variable, vars: list variables, q: quit)"
+    )
+    .unwrap();
+    loop {
+        write!(lock, "debug> ").unwrap();
+        let _ = lock.flush();
+        let line = read_line_from_stdin();
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (None | Some("c" | "continue"), _) => return,
+            (Some("p" | "print"), Some(name)) => match machine
+                .named_vars(prog, true)
+                .into_iter()
+                .flatten()
+                .find(|(n, _)| *n == name)
+            {
+                Some((_, var)) => writeln!(lock, "{} = {}", name, var.get()),
+                None => writeln!(lock, "variable \"{name}\" was not found"),
+            }
+            .unwrap(),
+            (Some("vars"), _) => {
+                // innermost first, as they hide the outer ones
+                for mut names in machine.named_vars(prog, false) {
+                    names.sort_by_key(|(name, _)| *name);
+                    for (name, var) in names {
+                        writeln!(lock, "{} = {}", name, var.get()).unwrap();
+                    }
+                }
+            }
+            (Some("q" | "quit"), _) => std::process::exit(crate::exit::USER_QUIT),
+            _ => writeln!(lock, "unknown command").unwrap(),
+        }
+    }
+}
+
+fn unwrap_bool(val: &Typed) -> Result<bool, String> {
+    if let Typed::Bool(b) = val {
+        Ok(*b)
+    } else {
+        bail!("Bool expected, got {}", val.typename());
+    }
+}
+
+fn unwrap_num(val: &Typed) -> Result<IntType, String> {
+    if let Typed::Num(n) = val {
+        Ok(*n)
+    } else {
+        bail!("Num expected, got {}", val.typename());
+    }
+}
+
+fn unwrap_sub(val: &Typed) -> Result<usize, String> {
+    if let Typed::Sub(n) = val {
+        Ok(*n)
+    } else {
+        bail!("Sub expected, got {}", val.typename());
+    }
+}
+
+/// Run the program, and return the exit status given by `halt` (0 if it ended otherwise)
+///
+/// The caller decides what to do with a runtime error, so that `run --watch` can keep going.
+#[allow(clippy::option_if_let_else)]
+pub fn run(prog: AST, config: &Config) -> Result<i32, String> {
+    let mut machine = Machine::new(config);
+    let result = loop {
+        machine.hoist_globals(&prog);
+        let result = match &config.entry {
+            Some(entry) => machine.enter(&prog, entry),
+            None => Ok(()),
+        }
+        .and_then(|()| machine.exec(&prog));
+        match machine.jump_to.take() {
+            // fast-forwarded from the beginning, so that the state is the one of reaching the scene
+            Some(title) if result.is_ok() => {
+                machine = Machine::new(config);
+                machine.skip_to = Some(title);
+            }
+            _ => break result,
+        }
+    };
+    if let Some(out) = &config.dump_vars {
+        machine
+            .dump_vars(&prog, out, result.as_ref().err())
+            .unwrap_or_else(|e| die!("Error: failed to dump variables : {}", e));
+    }
+    result.map(|()| machine.exit_status)
+}
+
+fn json_value(val: &Typed) -> String {
+    match val {
+        Typed::Str(s) => json_str(s),
+        Typed::Array(a) => format!(
+            "[{}]",
+            a.iter().map(json_value).collect::<Vec<_>>().join(", ")
+        ),
+        val => val.to_string(),
+    }
+}
+
+fn json_str(s: &str) -> String {
+    use std::fmt::Write;
+    let mut ret = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            c if c.is_control() => write!(ret, "\\u{:04x}", c as u32).unwrap(),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Parse an integer, accepting full-width digits and signs typed with IMEs
+fn parse_int(s: &str) -> Option<IntType> {
+    let s: String = s
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '－' => '-',
+            '＋' => '+',
+            c => crate::lex::ascii_digit(c).unwrap_or(c),
+        })
+        .collect();
+    s.parse().ok()
+}
+
+/// Milliseconds after the prompt during which Enter is ignored, unless configured
+const DEFAULT_WAIT_COOLDOWN: u64 = 250;
+
+/// Calls which may be nested, unless configured
+const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// Throw away the lines typed on the terminal but not read yet
+fn discard_typeahead() {
+    #[cfg(unix)]
+    {
+        use crossterm::tty::IsTty;
+
+        if std::io::stdin().is_tty() {
+            // SAFETY: tcflush only takes the descriptor and a flag
+            unsafe {
+                libc::tcflush(libc::STDIN_FILENO, libc::TCIFLUSH);
+            }
+        }
+    }
+}
+
+/// Read a line typed for Input, where the end of input is an error rather than an empty line
+/// (it would be asked again forever if the line has to fit a mask)
+fn read_input_line() -> Result<String, String> {
+    use std::io::BufRead;
+    let mut line = String::new();
+    let read = std::io::stdin().lock().read_line(&mut line);
+    match read {
+        Ok(0) => bail!("unexpected end of input"),
+        Ok(_) => {
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            Ok(line)
+        }
+        Err(e) => bail!("failed to read input: {}", e),
     }
 }
 
@@ -442,12 +2184,32 @@ fn read_line_from_stdin() -> String {
     use std::io::BufRead;
     let stdin = std::io::stdin();
     let mut it = stdin.lock().lines();
-    it.next().unwrap_or_else(|| Ok("".to_owned())).unwrap()
+    it.next().unwrap_or_else(|| Ok(String::new())).unwrap()
+}
+
+/// Dice shown one by one (more are shown only by the total)
+const MAX_SHOWN_DICE: IntType = 10;
+
+/// Roll dice and return each result (drawing the same numbers as `roll_dice`)
+fn roll_each(rng: &mut impl rand::Rng, count: IntType, face: IntType) -> Vec<IntType> {
+    (0..count).map(|_| rng.gen_range(1..=face)).collect()
+}
+
+/// Show tumbling dice for a moment, and then the results with the total
+fn show_roll(mode: render::Mode, idx: usize, face: IntType, results: &[IntType]) {
+    use std::io::Write;
+
+    let stdout = std::io::stdout();
+    let mut lock = stdout.lock();
+    let mut dice: Vec<Box<dyn animation::Effect>> = results
+        .iter()
+        .map(|&r| Box::new(animation::Tumble::new(face, r)) as _)
+        .collect();
+    animation::play(mode, &mut lock, &format!("{idx:04} : "), &mut dice);
+    writeln!(lock, " = {}", results.iter().sum::<IntType>()).unwrap();
 }
 
-fn roll_dice(count: IntType, face: IntType) -> IntType {
-    use rand::Rng;
-    let mut rng = rand::thread_rng();
+fn roll_dice(rng: &mut impl rand::Rng, count: IntType, face: IntType) -> IntType {
     let mut sum = 0;
 
     for _ in 0..count {
@@ -0,0 +1,99 @@
+//! Animations drawn in frames at a steady rate
+//!
+//! Effects tell what they look like at a given time, and one loop draws them together,
+//! only when the line has changed. It sleeps until the next frame instead of for fixed delays,
+//! so that the time taken by drawing doesn't add up.
+
+use super::render;
+use crate::types::IntType;
+use std::time::{Duration, Instant};
+
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// Something drawn on a part of a line over time
+pub trait Effect {
+    /// How long it moves
+    fn duration(&self) -> Duration;
+    /// What it looks like at `elapsed` since the beginning (the final look once it has passed)
+    fn frame(&mut self, elapsed: Duration) -> String;
+}
+
+/// Play the effects side by side after `prefix`, until all of them have ended.
+/// The line is left with their final looks and without a line break.
+pub fn play(
+    mode: render::Mode,
+    w: &mut impl std::io::Write,
+    prefix: &str,
+    effects: &mut [Box<dyn Effect>],
+) {
+    let total = effects
+        .iter()
+        .map(|e| e.duration())
+        .max()
+        .unwrap_or_default();
+    let start = Instant::now();
+    let mut shown = None;
+    for n in 0.. {
+        let elapsed = (FRAME * n).min(total);
+        let line = effects
+            .iter_mut()
+            .map(|e| e.frame(elapsed))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if shown.as_ref() != Some(&line) {
+            mode.rewrite_line(w, &format!("{prefix}{line}"));
+            shown = Some(line);
+        }
+        if elapsed >= total {
+            break;
+        }
+        // the next frame is due at a fixed time from the start, however long this one took
+        std::thread::sleep((start + FRAME * (n + 1)).saturating_duration_since(Instant::now()));
+    }
+}
+
+/// A die tumbling and landing on its result
+pub struct Tumble {
+    face: IntType,
+    result: IntType,
+    /// The face shown and when it was turned up
+    shown: Option<(IntType, Duration)>,
+}
+
+impl Tumble {
+    /// How long it tumbles
+    const DURATION: Duration = Duration::from_millis(480);
+    /// How long a face is shown while tumbling
+    const TURN: Duration = Duration::from_millis(60);
+
+    pub const fn new(face: IntType, result: IntType) -> Self {
+        Self {
+            face,
+            result,
+            shown: None,
+        }
+    }
+}
+
+impl Effect for Tumble {
+    fn duration(&self) -> Duration {
+        Self::DURATION
+    }
+
+    fn frame(&mut self, elapsed: Duration) -> String {
+        use rand::Rng;
+
+        if elapsed >= Self::DURATION {
+            return format!("[{}]", self.result);
+        }
+        match self.shown {
+            Some((face, since)) if elapsed < since + Self::TURN => format!("[{face}]"),
+            _ => {
+                // not drawn from the seeded generator, so that the results don't depend on the animation
+                let face = rand::thread_rng().gen_range(1..=self.face);
+                self.shown = Some((face, elapsed));
+                format!("[{face}]")
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Bidirectional text, for stories translated into Arabic or Hebrew
+//!
+//! Lines are put in the visual order by the Unicode bidirectional algorithm (UAX #9),
+//! for terminals which show the characters in the order they are stored.
+
+use unicode_bidi::BidiInfo;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Whether the line is right-to-left, judged by its first strong character
+pub fn is_rtl(line: &str) -> bool {
+    BidiInfo::new(line, None)
+        .paragraphs
+        .first()
+        .is_some_and(|para| para.level.is_rtl())
+}
+
+/// The bracket shown for `s` in right-to-left text (mirroring is left to the renderer by the algorithm)
+fn mirror(s: &str) -> &str {
+    match s {
+        "(" => ")",
+        ")" => "(",
+        "[" => "]",
+        "]" => "[",
+        "{" => "}",
+        "}" => "{",
+        "<" => ">",
+        ">" => "<",
+        "«" => "»",
+        "»" => "«",
+        _ => s,
+    }
+}
+
+/// Return the line in the visual order (left to right on the screen), for terminals without bidi support
+pub fn reorder(line: &str) -> String {
+    let info = BidiInfo::new(line, None);
+    if !info.has_rtl() {
+        return line.to_owned();
+    }
+    let mut out = String::with_capacity(line.len());
+    for para in &info.paragraphs {
+        let (levels, runs) = info.visual_runs(para, para.range.clone());
+        for run in runs {
+            let text = &line[run.clone()];
+            if levels[run.start].is_rtl() {
+                // reversed by grapheme clusters, so that combining marks stay on their letters
+                out.extend(text.graphemes(true).rev().map(mirror));
+            } else {
+                out.push_str(text);
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,74 @@
+//! Line endings and cursor control for where the output goes
+//!
+//! The terminal is never put in the raw mode (input is read by lines, echoed by the terminal),
+//! so lines end with "\n" and the terminal returns the carriage itself.
+//! Cursor control is written only to terminals, so that files and pipes get plain text.
+
+use crossterm::{cursor, queue, terminal};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// A terminal in the cooked mode
+    Terminal,
+    /// Files and pipes
+    Headless,
+}
+
+impl Mode {
+    pub fn of_stdout() -> Self {
+        use crossterm::tty::IsTty;
+
+        if std::io::stdout().is_tty() {
+            Self::Terminal
+        } else {
+            Self::Headless
+        }
+    }
+
+    pub fn is_terminal(self) -> bool {
+        self == Self::Terminal
+    }
+
+    /// Show a prompt for the player, left on the line to be answered after it
+    pub fn prompt(self, w: &mut impl Write, text: &str) {
+        if self.is_terminal() {
+            write!(w, "{text}").unwrap();
+            let _ = w.flush();
+        }
+    }
+
+    /// Erase the prompt answered by Enter, which the terminal has echoed as a line break
+    pub fn erase_prompt(self, w: &mut impl Write) {
+        if self.is_terminal() {
+            queue!(
+                w,
+                cursor::MoveToPreviousLine(1),
+                terminal::Clear(terminal::ClearType::CurrentLine)
+            )
+            .unwrap();
+            let _ = w.flush();
+        }
+    }
+
+    /// Draw the line again from its beginning (only terminals can, so nothing is written elsewhere)
+    pub fn rewrite_line(self, w: &mut impl Write, text: &str) {
+        if self.is_terminal() {
+            queue!(w, terminal::Clear(terminal::ClearType::CurrentLine)).unwrap();
+            write!(w, "\r{text}").unwrap();
+            let _ = w.flush();
+        }
+    }
+
+    /// Clear the screen and go to the top left corner
+    pub fn clear_screen(self, w: &mut impl Write) {
+        if self.is_terminal() {
+            queue!(
+                w,
+                terminal::Clear(terminal::ClearType::All),
+                cursor::MoveTo(0, 0)
+            )
+            .unwrap();
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use crate::types::Typed;
+use crate::exprs::element_at;
+use crate::types::{IntType, Typed};
 
 #[derive(Debug, Clone)]
 pub struct Variable {
@@ -10,6 +11,7 @@ pub struct Variable {
 pub enum ModifyError {
     TypeDiffers,
     Immutable,
+    OutOfRange { index: IntType, len: usize },
 }
 
 impl Variable {
@@ -25,16 +27,28 @@ impl Variable {
             value,
         }
     }
-    pub fn modify(&mut self, to: Typed) -> Result<Typed, ModifyError> {
-        if self.is_mutable {
-            match (&self.value, &to) {
-                (Typed::Num(_), Typed::Num(_)) | (Typed::Bool(_), Typed::Bool(_)) => {
-                    Ok(std::mem::replace(&mut self.value, to))
-                }
-                _ => Err(ModifyError::TypeDiffers),
-            }
-        } else {
-            Err(ModifyError::Immutable)
+    /// Replace the value, or its element at `indices` (one index for each level of arrays)
+    pub fn modify(&mut self, indices: &[IntType], to: Typed) -> Result<Typed, ModifyError> {
+        if !self.is_mutable {
+            return Err(ModifyError::Immutable);
+        }
+        let mut target = &mut self.value;
+        for &index in indices {
+            let Typed::Array(a) = target else {
+                return Err(ModifyError::TypeDiffers);
+            };
+            let len = a.len();
+            let Some(i) = element_at(index, len) else {
+                return Err(ModifyError::OutOfRange { index, len });
+            };
+            target = &mut a[i];
+        }
+        match (&*target, &to) {
+            (Typed::Num(_), Typed::Num(_))
+            | (Typed::Bool(_), Typed::Bool(_))
+            | (Typed::Str(_), Typed::Str(_))
+            | (Typed::Array(_), Typed::Array(_)) => Ok(std::mem::replace(target, to)),
+            _ => Err(ModifyError::TypeDiffers),
         }
     }
 
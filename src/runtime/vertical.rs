@@ -0,0 +1,146 @@
+//! Vertical writing (tategaki): columns from top to bottom, going from right to left
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Cells taken by a column, including the space before the next one
+const COLUMN_WIDTH: usize = 3;
+
+/// Return the form of the character used in vertical writing.
+/// Half-width characters become full-width, and punctuation is rotated.
+fn vertical_form(g: &str) -> String {
+    let mut chars = g.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else {
+        return g.to_owned();
+    };
+    let c = match c {
+        ' ' => '\u{3000}',
+        '!'..='~' => char::from_u32(u32::from(c) + 0xFEE0).unwrap_or(c),
+        c => c,
+    };
+    let rotated = match c {
+        '、' => '︑',
+        '。' => '︒',
+        '，' => '︐',
+        '：' => '︓',
+        '；' => '︔',
+        '！' => '︕',
+        '？' => '︖',
+        '「' => '﹁',
+        '」' => '﹂',
+        '『' => '﹃',
+        '』' => '﹄',
+        '（' => '︵',
+        '）' => '︶',
+        '｛' => '︷',
+        '｝' => '︸',
+        '〔' => '︹',
+        '〕' => '︺',
+        '【' => '︻',
+        '】' => '︼',
+        '《' => '︽',
+        '》' => '︾',
+        '〈' => '︿',
+        '〉' => '﹀',
+        '［' => '﹇',
+        '］' => '﹈',
+        'ー' | '－' | '—' | '―' => '｜',
+        '…' => '︙',
+        '‥' => '︰',
+        '～' | '〜' => '≀',
+        c => c,
+    };
+    rotated.to_string()
+}
+
+/// Split the paragraphs into columns of `rows` characters, in the order they are read
+fn columns(paragraphs: &[String], rows: usize) -> Vec<Vec<String>> {
+    let rows = rows.max(1);
+    let mut columns = Vec::new();
+    for p in paragraphs {
+        let cells: Vec<String> = p.graphemes(true).map(vertical_form).collect();
+        if cells.is_empty() {
+            columns.push(Vec::new());
+        }
+        for chunk in cells.chunks(rows) {
+            columns.push(chunk.to_vec());
+        }
+    }
+    columns
+}
+
+/// Draw the columns with the first one at the right end
+fn render(columns: &[Vec<String>]) -> String {
+    let rows = columns.iter().map(Vec::len).max().unwrap_or(0);
+    let mut out = String::new();
+    for row in 0..rows {
+        let mut line = String::new();
+        for (i, column) in columns.iter().rev().enumerate() {
+            if i > 0 {
+                line.push(' ');
+            }
+            let cell = column.get(row).map_or("", String::as_str);
+            line.push_str(cell);
+            // characters not in full width are put in the left half of the cell
+            line.push_str(&" ".repeat(2_usize.saturating_sub(cell.width())));
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Paragraphs written on a page
+#[derive(Default)]
+pub struct Page {
+    paragraphs: Vec<String>,
+    /// Whether the last paragraph is continued by the next one
+    open: bool,
+}
+
+impl Page {
+    pub const fn is_empty(&self) -> bool {
+        self.paragraphs.is_empty()
+    }
+
+    /// Add a paragraph, or continue the last one if it is open
+    pub fn push(&mut self, text: &str, nobreak: bool) {
+        match self.paragraphs.last_mut() {
+            Some(last) if self.open => last.push_str(text),
+            _ => self.paragraphs.push(text.to_owned()),
+        }
+        self.open = nobreak;
+    }
+
+    /// Add `count` empty columns, the first of which ends the open paragraph
+    pub fn newline(&mut self, count: usize) {
+        let count = if self.open {
+            count.saturating_sub(1)
+        } else {
+            count
+        };
+        self.open = false;
+        self.paragraphs
+            .extend(std::iter::repeat_n(String::new(), count));
+    }
+
+    /// If the page doesn't fit in `width` cells, move the last paragraph to a new page
+    /// and return the full one
+    pub fn turn_if_full(&mut self, width: usize, rows: usize) -> Option<Self> {
+        let max = (width / COLUMN_WIDTH).max(1);
+        if self.paragraphs.len() < 2 || columns(&self.paragraphs, rows).len() <= max {
+            return None;
+        }
+        let last = self.paragraphs.pop().unwrap();
+        let next = Self {
+            paragraphs: vec![last],
+            open: self.open,
+        };
+        Some(std::mem::replace(self, next))
+    }
+
+    /// Draw the page with columns of `rows` characters
+    pub fn render(&self, rows: usize) -> String {
+        render(&columns(&self.paragraphs, rows))
+    }
+}
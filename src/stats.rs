@@ -0,0 +1,84 @@
+//! Statistics of a story
+
+use crate::lex::{Command, Items, Lexed};
+use crate::parse::{Statement, AST};
+
+#[derive(Default)]
+pub struct Stats {
+    prints: usize,
+    /// Words in the strings of Print
+    words: usize,
+    /// Non-whitespace characters in the strings of Print (for languages without spaces)
+    chars: usize,
+    /// If, Else-If and Else
+    branches: usize,
+    inputs: usize,
+    /// Halt and the end of the script
+    endings: usize,
+    /// Name and number of statements of each subroutine
+    subs: Vec<(String, usize)>,
+}
+
+impl Stats {
+    pub fn new(prog: &AST, lexed: &Lexed) -> Self {
+        let mut stats = Self::default();
+
+        let mut in_print = false;
+        for tk in &lexed.tokens {
+            match &tk.item {
+                Items::Cmd(c) => {
+                    in_print = matches!(
+                        c,
+                        Command::Print | Command::Say | Command::Center | Command::Right
+                    );
+                }
+                Items::Str(s) if in_print => {
+                    stats.words += s.split_whitespace().count();
+                    stats.chars += s.chars().filter(|c| !c.is_whitespace()).count();
+                }
+                _ => {}
+            }
+        }
+
+        for stmt in &prog.stmts {
+            match stmt {
+                Statement::Print { .. } => stats.prints += 1,
+                Statement::If { .. }
+                | Statement::ElIf { .. }
+                | Statement::Case { .. }
+                | Statement::Else { .. } => {
+                    stats.branches += 1;
+                }
+                Statement::Input { .. } => stats.inputs += 1,
+                Statement::Halt { .. } => stats.endings += 1,
+                Statement::Sub {
+                    name,
+                    offset_to_end,
+                    ..
+                } => stats.subs.push((name.clone(), offset_to_end - 1)),
+                _ => {}
+            }
+        }
+        if !matches!(prog.stmts.last(), Some(Statement::Halt { .. })) {
+            stats.endings += 1;
+        }
+
+        stats
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Print statements : {}", self.prints)?;
+        writeln!(f, "Words            : {}", self.words)?;
+        writeln!(f, "Characters       : {}", self.chars)?;
+        writeln!(f, "Branches         : {}", self.branches)?;
+        writeln!(f, "Inputs           : {}", self.inputs)?;
+        writeln!(f, "Endings          : {}", self.endings)?;
+        writeln!(f, "Subroutines      : {}", self.subs.len())?;
+        for (name, size) in &self.subs {
+            writeln!(f, "    {name} : {size} statement(s)")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+//! Styles of everything painted on the terminal, chosen in one place
+//! so that colors can be turned off, or replaced for colorblind and low-vision players
+
+use crossterm::style::{Color, Stylize};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    /// Colors and bold text
+    Normal,
+    /// Plain text
+    NoColor,
+    /// No hues to tell apart: reverse video, underline and bold only
+    HighContrast,
+}
+
+/// What a painted text is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Error,
+    Warning,
+    Note,
+    /// Arrows and bars around the source in diagnostics
+    Gutter,
+    /// Text to stand out without a color of its own (e.g. the message of a diagnostic)
+    Emphasis,
+    /// Name of a speaker, with the color configured for them
+    Speaker(Color),
+}
+
+static THEME: AtomicU8 = AtomicU8::new(Theme::Normal as u8);
+
+impl Theme {
+    /// Set the theme for the whole process (`--no-color` wins over `--high-contrast`)
+    pub fn init(no_color: bool, high_contrast: bool) {
+        let theme = if no_color || std::env::var_os("NO_COLOR").is_some() {
+            Self::NoColor
+        } else if high_contrast {
+            Self::HighContrast
+        } else {
+            Self::Normal
+        };
+        THEME.store(theme as u8, Ordering::Relaxed);
+    }
+
+    /// The theme for a stream, which is never painted unless it is a terminal
+    pub fn for_stream(is_tty: bool) -> Self {
+        if !is_tty {
+            return Self::NoColor;
+        }
+        match THEME.load(Ordering::Relaxed) {
+            x if x == Self::NoColor as u8 => Self::NoColor,
+            x if x == Self::HighContrast as u8 => Self::HighContrast,
+            _ => Self::Normal,
+        }
+    }
+
+    pub fn paint(self, s: &str, role: Role) -> String {
+        match self {
+            Self::NoColor => s.to_owned(),
+            Self::Normal => {
+                let color = match role {
+                    Role::Error => Color::Red,
+                    Role::Warning => Color::Yellow,
+                    Role::Note => Color::Cyan,
+                    Role::Gutter => Color::Blue,
+                    Role::Emphasis => Color::Reset,
+                    Role::Speaker(color) => color,
+                };
+                s.with(color).bold().to_string()
+            }
+            Self::HighContrast => match role {
+                Role::Error => s.bold().reverse().to_string(),
+                Role::Warning => s.bold().underlined().to_string(),
+                Role::Note | Role::Gutter | Role::Emphasis | Role::Speaker(_) => {
+                    s.bold().to_string()
+                }
+            },
+        }
+    }
+}
@@ -2,11 +2,13 @@
 pub type IntType = i64;
 
 /// The typed content of a variable
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum Typed {
     Num(IntType),
     Bool(bool),
     Str(String),
+    /// Elements of one type, never empty but for a global array not declared yet
+    Array(Vec<Self>),
     Sub(usize),
 }
 
@@ -16,19 +18,45 @@ impl Typed {
             Self::Num(_) => "Num",
             Self::Bool(_) => "Bool",
             Self::Str(_) => "Str",
+            Self::Array(_) => "Array",
             Self::Sub(_) => "Sub",
         }
     }
 }
 
+impl std::fmt::Display for Typed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Num(n) => write!(f, "{n}"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Str(s) => write!(f, "{s:?}"),
+            Self::Array(a) => {
+                write!(f, "[")?;
+                for (i, val) in a.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{val}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Sub(idx) => write!(f, "sub at {idx:04}"),
+        }
+    }
+}
+
 impl std::ops::Neg for Typed {
     type Output = Self;
     fn neg(self) -> Self {
         match self {
             Self::Num(n) => Self::Num(-n),
             Self::Bool(b) => Self::Bool(!b),
-            Self::Str(s) => Self::Str(s.chars().rev().collect()),
-            Self::Sub(_) => unimplemented!(),
+            Self::Str(s) => {
+                use unicode_segmentation::UnicodeSegmentation;
+                // by grapheme clusters, so that emoji and combining marks are kept
+                Self::Str(s.graphemes(true).rev().collect())
+            }
+            Self::Array(_) | Self::Sub(_) => unimplemented!(),
         }
     }
 }
@@ -36,9 +64,10 @@ impl std::ops::Neg for Typed {
 impl PartialEq for Typed {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (Typed::Num(this), Typed::Num(that)) => this.eq(that),
-            (Typed::Bool(this), Typed::Bool(that)) => this.eq(that),
-            (Typed::Str(this), Typed::Str(that)) => this.eq(that),
+            (Self::Num(this), Self::Num(that)) => this.eq(that),
+            (Self::Bool(this), Self::Bool(that)) => this.eq(that),
+            (Self::Str(this), Self::Str(that)) => this.eq(that),
+            (Self::Array(this), Self::Array(that)) => this.eq(that),
             _ => unimplemented!(),
         }
     }
@@ -47,8 +76,30 @@ impl PartialEq for Typed {
 impl PartialOrd for Typed {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         match (self, other) {
-            (Typed::Num(this), Typed::Num(that)) => Some(this.cmp(that)),
+            (Self::Num(this), Self::Num(that)) => Some(this.cmp(that)),
+            (Self::Bool(this), Self::Bool(that)) => Some(this.cmp(that)),
+            (Self::Str(this), Self::Str(that)) => Some(this.cmp(that)),
+            // element by element, and a shorter one first if it is the beginning of the other
+            (Self::Array(this), Self::Array(that)) => this.partial_cmp(that),
             _ => None,
         }
     }
 }
+
+/// Where a variable is stored, decided by the parser
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Slot {
+    /// Index of the statement opening the block which declares the variable
+    /// (0 for the top level, whose slots begin with the internal variables)
+    pub scope: usize,
+    /// Position among the variables of the block in order of declaration
+    pub index: usize,
+}
+
+impl Slot {
+    /// Slot of a name which was not resolved, never found at runtime
+    pub const UNRESOLVED: Self = Self {
+        scope: usize::MAX,
+        index: usize::MAX,
+    };
+}
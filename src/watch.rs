@@ -0,0 +1,38 @@
+//! Detection of modifications of the script for watch mode
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Spawn a thread polling `path`, which sets the returned flag when the file is modified
+pub fn spawn(path: PathBuf) -> Arc<AtomicBool> {
+    let changed = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&changed);
+    std::thread::spawn(move || {
+        let mut last = modified(&path);
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let now = modified(&path);
+            // the file may be missing for a moment while an editor saves it
+            if now.is_some() && now != last {
+                last = now;
+                flag.store(true, Ordering::Relaxed);
+            }
+        }
+    });
+    changed
+}
+
+/// Block until the flag is set, then clear it
+pub fn wait(changed: &AtomicBool) {
+    while !changed.swap(false, Ordering::Relaxed) {
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
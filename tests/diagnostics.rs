@@ -0,0 +1,49 @@
+//! Golden tests of rendered diagnostics
+//!
+//! Each `tests/ui/*.nvl` is checked and its stderr is compared with the `.stderr` file beside it.
+//! Run with `BLESS=1` to write the current output as the expected one.
+
+use std::path::Path;
+use std::process::Command;
+
+fn check(script: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_novelang"))
+        .args(["-q", "check"])
+        .arg(script)
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("failed to run novelang");
+    String::from_utf8(output.stderr).expect("stderr is not UTF-8")
+}
+
+#[test]
+fn ui() {
+    let bless = std::env::var_os("BLESS").is_some();
+    let mut scripts: Vec<_> = std::fs::read_dir("tests/ui")
+        .expect("tests/ui is missing")
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "nvl"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    let mut failed = Vec::new();
+    for script in &scripts {
+        let actual = check(script);
+        let expected_path = script.with_extension("stderr");
+        if bless {
+            std::fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        let expected = std::fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual != expected {
+            eprintln!("--- {}\n+++ actual\n{}", expected_path.display(), actual);
+            failed.push(script.display().to_string());
+        }
+    }
+    assert!(
+        failed.is_empty(),
+        "output differs for {:?} (run with BLESS=1 to update)",
+        failed
+    );
+}
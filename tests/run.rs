@@ -0,0 +1,203 @@
+//! Golden tests of running scripts
+//!
+//! Each `tests/run/*.nvl` is run without waiting, at a fixed time, and its stdout, exit status and stderr
+//! are compared with the `.out` file beside it.
+//! Scripts needing a configuration are put in a subdirectory along with their `novelint.toml`.
+//! A script with a `.in` file beside it reads it as stdin, and waits for Enter in it;
+//! a script with a `.args` file is given the words in it as the arguments after `--`.
+//! Run with `BLESS=1` to write the current output as the expected one.
+//!
+//! Each script is run twice, and the second run, which reads the program from the cache,
+//! must give the same output.
+//! `tests/i18n/game.nvl` is run with its catalog `fr.po` and compared in the same way.
+//! The scripts are also minified, and the minified ones must give the same output
+//! except for stderr, where runtime errors name the shortened variables.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const CONFIG: &str = "novelint.toml";
+/// Files beside a script, giving its stdin and its arguments
+const INPUTS: [&str; 2] = ["in", "args"];
+/// Date and time seen by the scripts (a Thursday, the leap day)
+const FIXED_TIME: &str = "2024-02-29T07:05:09";
+
+/// Scripts in `tests/run` and in its subdirectories, sorted
+fn scripts() -> Vec<PathBuf> {
+    let mut scripts = Vec::new();
+    let mut dirs = vec![PathBuf::from("tests/run")];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).expect("tests/run is missing") {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|e| e == "nvl") {
+                scripts.push(path);
+            }
+        }
+    }
+    scripts.sort();
+    scripts
+}
+
+fn minify(script: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_novelang"))
+        .args(["-q", "minify"])
+        .arg(script)
+        .output()
+        .expect("failed to run novelang");
+    assert!(
+        output.status.success(),
+        "failed to minify {}",
+        script.display()
+    );
+    String::from_utf8(output.stdout).expect("stdout is not UTF-8")
+}
+
+/// An empty directory for the cache of parsed programs
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    dir
+}
+
+fn run(script: &Path, cache: &Path) -> String {
+    run_with(script, cache, &[])
+}
+
+/// Run `script` with the options of `run` in `opts`
+fn run_with(script: &Path, cache: &Path, opts: &[&str]) -> String {
+    use std::io::Write;
+
+    let input = std::fs::read(script.with_extension("in")).ok();
+    let args = std::fs::read_to_string(script.with_extension("args")).unwrap_or_default();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_novelang"));
+    command.arg("-q");
+    if input.is_none() {
+        command.arg("--no-wait");
+    }
+    command
+        .args(["--seed", "0", "--fixed-time", FIXED_TIME, "run"])
+        .args(opts)
+        .arg(script);
+    if !args.trim().is_empty() {
+        command.arg("--").args(args.split_whitespace());
+    }
+    let mut child = command
+        .env("NO_COLOR", "1")
+        .env("XDG_CACHE_HOME", cache)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run novelang");
+    if let Some(input) = input {
+        // the script may exit without reading all of it
+        let _ = child.stdin.take().unwrap().write_all(&input);
+    }
+    let output = child.wait_with_output().expect("failed to run novelang");
+    let mut out = String::from_utf8(output.stdout).expect("stdout is not UTF-8");
+    if !output.status.success() {
+        out.push_str(&format!("--- exit status {:?}\n", output.status.code()));
+    }
+    let stderr = String::from_utf8(output.stderr).expect("stderr is not UTF-8");
+    if !stderr.is_empty() {
+        out.push_str("--- stderr\n");
+        out.push_str(&stderr);
+    }
+    out
+}
+
+#[test]
+fn run_output() {
+    let bless = std::env::var_os("BLESS").is_some();
+    let scripts = scripts();
+    assert!(!scripts.is_empty());
+    let cache = cache_dir("cache");
+
+    let mut failed = Vec::new();
+    for script in &scripts {
+        let actual = run(script, &cache);
+        let cached = run(script, &cache);
+        if cached != actual {
+            eprintln!("--- {}\n+++ from the cache\n{}", script.display(), cached);
+            failed.push(script.display().to_string());
+            continue;
+        }
+        let expected_path = script.with_extension("out");
+        if bless {
+            std::fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        let expected = std::fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual != expected {
+            eprintln!("--- {}\n+++ actual\n{}", expected_path.display(), actual);
+            failed.push(script.display().to_string());
+        }
+    }
+    assert!(
+        failed.is_empty(),
+        "output differs for {:?} (run with BLESS=1 to update)",
+        failed
+    );
+}
+
+#[test]
+fn minified() {
+    // the expected output may be being written by `run_output`
+    if std::env::var_os("BLESS").is_some() {
+        return;
+    }
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("minified");
+    std::fs::create_dir_all(&dir).unwrap();
+    let cache = cache_dir("minified-cache");
+    let mut failed = Vec::new();
+    for script in scripts() {
+        let minified = dir.join(script.strip_prefix("tests/run").unwrap());
+        let minified_dir = minified.parent().unwrap();
+        std::fs::create_dir_all(minified_dir).unwrap();
+        let config = script.with_file_name(CONFIG);
+        if config.is_file() {
+            std::fs::copy(&config, minified_dir.join(CONFIG)).unwrap();
+        }
+        for ext in INPUTS {
+            let input = script.with_extension(ext);
+            if input.is_file() {
+                std::fs::copy(&input, minified.with_extension(ext)).unwrap();
+            }
+        }
+        std::fs::write(&minified, minify(&script)).unwrap();
+        let actual = run(&minified, &cache);
+        let expected = std::fs::read_to_string(script.with_extension("out")).unwrap_or_default();
+        let without_stderr = |s: &str| s.split("--- stderr\n").next().unwrap().to_owned();
+        if without_stderr(&actual) != without_stderr(&expected) {
+            eprintln!("--- {}\n+++ minified\n{}", script.display(), actual);
+            failed.push(script.display().to_string());
+        }
+    }
+    assert!(
+        failed.is_empty(),
+        "minified output differs for {:?}",
+        failed
+    );
+}
+
+#[test]
+fn translated() {
+    // only the strings shown are replaced by the catalog `fr.po` beside the script
+    let script = Path::new("tests/i18n/game.nvl");
+    let actual = run_with(script, &cache_dir("i18n-cache"), &["--lang", "fr"]);
+    let expected_path = script.with_extension("out");
+    if std::env::var_os("BLESS").is_some() {
+        std::fs::write(&expected_path, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&expected_path).unwrap_or_default();
+    assert_eq!(actual, expected, "run with BLESS=1 to update");
+}